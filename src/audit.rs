@@ -0,0 +1,344 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_file;
+use crate::duration::parse_duration;
+
+/// Device a change was made on, as recorded in the audit log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditTarget {
+    pub label: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditResult {
+    Ok,
+    Failed,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<FixedOffset>,
+    pub user: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+    pub result: AuditResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new<T>(
+        target: &AuditTarget,
+        command: impl Into<String>,
+        previous: Option<String>,
+        new: Option<String>,
+        result: &Result<T>,
+    ) -> Self {
+        let (result, error) = match result {
+            Ok(_) => (AuditResult::Ok, None),
+            Err(error) => (AuditResult::Failed, Some(format!("{error:#}"))),
+        };
+
+        Self {
+            timestamp: Local::now().fixed_offset(),
+            user: os_user(),
+            label: target.label.clone(),
+            serial_number: target.serial_number.clone(),
+            command: command.into(),
+            previous,
+            new,
+            result,
+            error,
+        }
+    }
+}
+
+/// Which records `iqos audit` prints.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditFilter {
+    pub label: Option<String>,
+    /// Serial saved for `label`, so records made before the label existed
+    /// still match.
+    pub serial_number: Option<String>,
+    pub since: Option<DateTime<FixedOffset>>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        if self.since.is_some_and(|since| record.timestamp < since) {
+            return false;
+        }
+
+        let Some(label) = &self.label else {
+            return true;
+        };
+
+        record.label.as_deref() == Some(label.as_str())
+            || (self.serial_number.is_some() && record.serial_number == self.serial_number)
+    }
+}
+
+pub fn audit_file() -> PathBuf {
+    config_file().with_file_name("audit.jsonl")
+}
+
+/// Append a record to the audit log. A log that cannot be written is reported
+/// but never fails the command that was audited.
+pub fn record(record: AuditRecord) {
+    if let Err(error) = append_to(&audit_file(), &record) {
+        eprintln!("Warning: could not write audit log: {error:#}");
+    }
+}
+
+pub fn append_to(path: &Path, record: &AuditRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn load_from(path: &Path) -> Result<Vec<AuditRecord>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", path.display()))
+        }
+    };
+
+    let mut records = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            // A line cut short by a crash should not hide the rest of the log.
+            Err(error) => eprintln!(
+                "Warning: skipping malformed audit record at {}:{}: {error}",
+                path.display(),
+                index + 1
+            ),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Parse `--since`: an RFC 3339 timestamp, a local date (`2024-05-01`) or an
+/// age relative to `now` (`12h`, `7d`).
+pub fn parse_since(value: &str, now: DateTime<Local>) -> Result<DateTime<FixedOffset>> {
+    let value = value.trim();
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp);
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+        return Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|timestamp| timestamp.fixed_offset())
+            .with_context(|| format!("Invalid --since value: {value}"));
+    }
+
+    parse_duration(value)
+        .map(|age| (now - age).fixed_offset())
+        .map_err(|_| {
+            anyhow!(
+                "Invalid --since value: {value} (expected a date, RFC 3339 time or age like 7d)"
+            )
+        })
+}
+
+pub fn print_records(records: &[AuditRecord]) {
+    if records.is_empty() {
+        println!("No audit records");
+        return;
+    }
+
+    for record in records {
+        println!("{}", format_record(record));
+    }
+}
+
+fn format_record(record: &AuditRecord) -> String {
+    let target = match (&record.label, &record.serial_number) {
+        (Some(label), Some(serial)) => format!("{label} ({serial})"),
+        (Some(label), None) => label.clone(),
+        (None, Some(serial)) => serial.clone(),
+        (None, None) => "-".to_string(),
+    };
+    let change = format!(
+        "{} -> {}",
+        record.previous.as_deref().unwrap_or("?"),
+        record.new.as_deref().unwrap_or("-")
+    );
+    let result = match (&record.result, &record.error) {
+        (AuditResult::Ok, _) => "ok".to_string(),
+        (AuditResult::Failed, Some(error)) => format!("failed: {error}"),
+        (AuditResult::Failed, None) => "failed".to_string(),
+    };
+
+    format!(
+        "{}  {}  {target}  {}  {change}  {result}",
+        record.timestamp.format("%Y-%m-%d %H:%M:%S %:z"),
+        record.user,
+        record.command
+    )
+}
+
+fn os_user() -> String {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::unique_temp_dir;
+
+    fn record_at(timestamp: &str, label: Option<&str>, serial: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            user: "alice".to_string(),
+            label: label.map(str::to_string),
+            serial_number: serial.map(str::to_string),
+            command: "brightness".to_string(),
+            previous: Some("high".to_string()),
+            new: Some("low".to_string()),
+            result: AuditResult::Ok,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn appends_and_loads_json_lines() {
+        let dir = unique_temp_dir("audit");
+        let path = dir.join("audit.jsonl");
+        let target = AuditTarget {
+            label: Some("minera".to_string()),
+            serial_number: Some("SN123".to_string()),
+        };
+
+        let ok = AuditRecord::new(&target, "lock", None, Some("locked".to_string()), &Ok(()));
+        let failed = AuditRecord::new(
+            &target,
+            "brightness",
+            Some("high".to_string()),
+            Some("low".to_string()),
+            &Err::<(), _>(anyhow!("write failed")),
+        );
+        append_to(&path, &ok).unwrap();
+        append_to(&path, &failed).unwrap();
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "{\"truncated\":",
+        )
+        .unwrap();
+
+        let records = load_from(&path).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(records, vec![ok, failed]);
+        assert_eq!(records[1].result, AuditResult::Failed);
+        assert_eq!(records[1].error.as_deref(), Some("write failed"));
+    }
+
+    #[test]
+    fn filters_by_label_serial_and_time() {
+        let old = record_at("2024-01-01T10:00:00+00:00", Some("minera"), Some("SN1"));
+        let renamed = record_at("2024-02-01T10:00:00+00:00", None, Some("SN1"));
+        let other = record_at("2024-02-01T10:00:00+00:00", Some("blackcat"), Some("SN2"));
+
+        let filter = AuditFilter {
+            label: Some("minera".to_string()),
+            serial_number: Some("SN1".to_string()),
+            since: None,
+        };
+        assert!(filter.matches(&old));
+        assert!(filter.matches(&renamed));
+        assert!(!filter.matches(&other));
+
+        let filter = AuditFilter {
+            since: Some(DateTime::parse_from_rfc3339("2024-01-15T00:00:00+00:00").unwrap()),
+            ..AuditFilter::default()
+        };
+        assert!(!filter.matches(&old));
+        assert!(filter.matches(&other));
+    }
+
+    #[test]
+    fn parses_since_values() {
+        let now = Local.with_ymd_and_hms(2024, 5, 10, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            parse_since("7d", now).unwrap(),
+            Local
+                .with_ymd_and_hms(2024, 5, 3, 12, 0, 0)
+                .unwrap()
+                .fixed_offset()
+        );
+        assert_eq!(
+            parse_since("2024-05-01", now).unwrap(),
+            Local
+                .with_ymd_and_hms(2024, 5, 1, 0, 0, 0)
+                .unwrap()
+                .fixed_offset()
+        );
+        assert_eq!(
+            parse_since("2024-05-01T08:30:00+02:00", now).unwrap(),
+            DateTime::parse_from_rfc3339("2024-05-01T08:30:00+02:00").unwrap()
+        );
+        assert!(parse_since("last tuesday", now).is_err());
+    }
+
+    #[test]
+    fn formats_failed_records_with_error() {
+        let mut record = record_at("2024-01-01T10:00:00+00:00", Some("minera"), Some("SN1"));
+        record.result = AuditResult::Failed;
+        record.error = Some("timeout".to_string());
+
+        assert_eq!(
+            format_record(&record),
+            "2024-01-01 10:00:00 +00:00  alice  minera (SN1)  brightness  high -> low  failed: timeout"
+        );
+    }
+}
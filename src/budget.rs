@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveTime, TimeZone};
+use iqos::DeviceCapability;
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_file;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
+use crate::state;
+
+/// How long a threshold warning vibrates the device.
+const WARNING_VIBRATION: Duration = Duration::from_secs(2);
+
+/// Puff limit for one saved device, set under `[devices.<label>.budget]`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Budget {
+    pub limit: u32,
+    #[serde(default)]
+    pub period: BudgetPeriod,
+    /// Local time (`HH:MM`) at which the count starts over. Weekly budgets
+    /// start over on Monday at this time.
+    #[serde(default = "default_reset")]
+    pub reset: String,
+    /// Percentages of the limit at which to warn.
+    #[serde(default = "default_warn_at")]
+    pub warn_at: Vec<u32>,
+    /// Vibrate the device (as Find My IQOS does) when warning.
+    #[serde(default = "default_vibrate")]
+    pub vibrate: bool,
+    /// Shell command run on every warning, lock and reset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+fn default_reset() -> String {
+    "00:00".to_string()
+}
+
+fn default_warn_at() -> Vec<u32> {
+    vec![80]
+}
+
+fn default_vibrate() -> bool {
+    true
+}
+
+impl Budget {
+    pub fn reset_time(&self) -> Result<NaiveTime> {
+        NaiveTime::parse_from_str(self.reset.trim(), "%H:%M")
+            .map_err(|_| anyhow!("Invalid budget reset time: {} (expected HH:MM)", self.reset))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.limit == 0 {
+            bail!("Invalid budget: limit must be at least 1");
+        }
+        if let Some(percent) = self
+            .warn_at
+            .iter()
+            .find(|percent| !(1..100).contains(*percent))
+        {
+            bail!("Invalid budget: warn_at value {percent} must be a percentage from 1 to 99");
+        }
+        self.reset_time().map(|_| ())
+    }
+
+    /// Start of the budget period containing `now`.
+    pub fn period_start(&self, now: DateTime<Local>) -> Result<DateTime<Local>> {
+        let reset = self.reset_time()?;
+        let days_back = match self.period {
+            BudgetPeriod::Daily => 0,
+            BudgetPeriod::Weekly => i64::from(now.weekday().num_days_from_monday()),
+        };
+
+        let mut date = now.date_naive() - chrono::Duration::days(days_back);
+        loop {
+            let start = Local
+                .from_local_datetime(&date.and_time(reset))
+                .earliest()
+                .with_context(|| format!("{date} {reset} does not exist in the local time zone"))?;
+            if start <= now {
+                return Ok(start);
+            }
+            date -= match self.period {
+                BudgetPeriod::Daily => chrono::Duration::days(1),
+                BudgetPeriod::Weekly => chrono::Duration::weeks(1),
+            };
+        }
+    }
+}
+
+/// Usage tracked between polls, persisted so a restarted watcher keeps its
+/// baseline.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BudgetState {
+    pub period_start: DateTime<FixedOffset>,
+    /// The device's lifetime puff counter at the start of the period.
+    pub baseline: u32,
+    #[serde(default)]
+    pub warned: Vec<u32>,
+    /// Counter value when the device was last locked for this period.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_at: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetAction {
+    /// A new period started; unlock if the last one ended locked.
+    Reset {
+        unlock: bool,
+    },
+    Warn {
+        percent: u32,
+    },
+    Lock,
+}
+
+impl BudgetState {
+    pub fn new(period_start: DateTime<Local>, count: u32) -> Self {
+        Self {
+            period_start: period_start.fixed_offset(),
+            baseline: count,
+            warned: Vec::new(),
+            locked_at: None,
+        }
+    }
+
+    pub fn used(&self, count: u32) -> u32 {
+        count.saturating_sub(self.baseline)
+    }
+
+    /// Account for a new counter reading and return what to do about it.
+    pub fn update(
+        &mut self,
+        budget: &Budget,
+        period_start: DateTime<Local>,
+        count: u32,
+    ) -> Vec<BudgetAction> {
+        let mut actions = Vec::new();
+
+        if self.period_start != period_start.fixed_offset() {
+            let unlock = self.locked_at.is_some();
+            *self = Self::new(period_start, count);
+            actions.push(BudgetAction::Reset { unlock });
+        }
+
+        // The counter went backwards: the device was reset or replaced.
+        if count < self.baseline {
+            self.baseline = count;
+        }
+
+        let used = self.used(count);
+        let mut thresholds = budget.warn_at.clone();
+        thresholds.sort_unstable();
+        for percent in thresholds {
+            let reached = u64::from(used) * 100 >= u64::from(budget.limit) * u64::from(percent);
+            if reached && !self.warned.contains(&percent) {
+                self.warned.push(percent);
+                if used < budget.limit {
+                    actions.push(BudgetAction::Warn { percent });
+                }
+            }
+        }
+
+        // Lock again if the device was unlocked by hand and used further.
+        if used >= budget.limit && self.locked_at.is_none_or(|locked_at| count > locked_at) {
+            self.locked_at = Some(count);
+            actions.push(BudgetAction::Lock);
+        }
+
+        actions
+    }
+}
+
+pub fn state_file() -> PathBuf {
+    config_file().with_file_name("budget.json")
+}
+
+/// Poll the puff counter every `interval` until a device read fails, acting
+/// on the budget of `label`. The caller reconnects and calls this again.
+pub async fn watch(
+    session: &Session,
+    label: &str,
+    budget: &Budget,
+    interval: Duration,
+) -> Result<()> {
+    let can_lock = session.profile().supports(DeviceCapability::DeviceLock);
+    if !can_lock {
+        eprintln!("Warning: this device cannot be locked; the budget will only warn");
+    }
+
+    // A dry run keeps its state in memory so it warns as a real run would,
+    // without touching the saved baseline.
+    let dry_run = session.options().dry_run;
+    let path = state_file();
+    let mut states: BTreeMap<String, BudgetState> = state::load_json(&path)?;
+    let mut last_used = None;
+    loop {
+        let count = session
+            .iqos()
+            .await
+            .read_diagnosis()
+            .await?
+            .total_smoking_count
+            .map(u32::from)
+            .context("the device did not report a puff count")?;
+        let now = Local::now();
+        let period_start = budget.period_start(now)?;
+
+        if !dry_run {
+            states = state::load_json(&path)?;
+        }
+        // Puffs before the first poll of a period are not on record.
+        let seen_period = states
+            .get(label)
+            .is_some_and(|state| state.period_start == period_start.fixed_offset());
+        if !seen_period && last_used.is_none() {
+            println!(
+                "Counting puffs for {label} from now; puffs taken earlier this {} are not included",
+                period_name(budget.period)
+            );
+        }
+        let mut state = states
+            .get(label)
+            .cloned()
+            .unwrap_or_else(|| BudgetState::new(period_start, count));
+        let actions = state.update(budget, period_start, count);
+        let used = state.used(count);
+
+        if last_used != Some(used) {
+            println!(
+                "{}  {label}: {used}/{} puffs this {}",
+                now.format("%Y-%m-%d %H:%M:%S"),
+                budget.limit,
+                period_name(budget.period)
+            );
+            last_used = Some(used);
+        }
+
+        // Saved only once every action succeeded, so a failed warning or
+        // lock is retried after reconnecting.
+        for action in actions {
+            act(session, label, budget, action, used, can_lock).await?;
+        }
+        if !dry_run {
+            state::update_json(&path, |saved: &mut BTreeMap<String, BudgetState>| {
+                saved.insert(label.to_string(), state.clone());
+                Ok(())
+            })?;
+        }
+        states.insert(label.to_string(), state);
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn act(
+    session: &Session,
+    label: &str,
+    budget: &Budget,
+    action: BudgetAction,
+    used: u32,
+    can_lock: bool,
+) -> Result<()> {
+    let event = match action {
+        BudgetAction::Reset { unlock } => {
+            println!("Budget period started for {label}");
+            if unlock && can_lock {
+                let iqos = session.iqos().await;
+                let written = session
+                    .write(&iqos, "budget unlock", None, Setting::Locked(false))
+                    .await?;
+                if written.is_written() {
+                    println!("{}", written.report("Device unlocked"));
+                }
+            }
+            "reset"
+        }
+        BudgetAction::Warn { percent } => {
+            println!(
+                "Warning: {label} has used {used} of {} puffs ({percent}% threshold)",
+                budget.limit
+            );
+            if budget.vibrate && session.options().dry_run {
+                println!("Dry run: warning vibration skipped");
+            } else if budget.vibrate {
+                let iqos = session.iqos().await;
+                iqos.find_my_iqos_start().await?;
+                tokio::time::sleep(WARNING_VIBRATION).await;
+                iqos.find_my_iqos_stop().await?;
+            }
+            "warn"
+        }
+        BudgetAction::Lock => {
+            println!("Budget of {} puffs reached for {label}", budget.limit);
+            if can_lock {
+                let iqos = session.iqos().await;
+                let written = session
+                    .write(&iqos, "budget lock", None, Setting::Locked(true))
+                    .await?;
+                if written.is_written() {
+                    println!("{}", written.report("Device locked"));
+                }
+            }
+            "lock"
+        }
+    };
+
+    if let Some(hook) = &budget.hook {
+        if session.options().dry_run {
+            println!("Dry run: budget hook not run ({event})");
+        } else {
+            run_hook(hook, event, label, used, budget.limit);
+        }
+    }
+    Ok(())
+}
+
+fn period_name(period: BudgetPeriod) -> &'static str {
+    match period {
+        BudgetPeriod::Daily => "day",
+        BudgetPeriod::Weekly => "week",
+    }
+}
+
+/// Run the hook with the event in its environment. A failing hook is
+/// reported but never stops the watcher.
+fn run_hook(hook: &str, event: &str, label: &str, used: u32, limit: u32) {
+    let mut command = if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command
+        .arg(hook)
+        .env("IQOS_BUDGET_EVENT", event)
+        .env("IQOS_LABEL", label)
+        .env("IQOS_PUFFS", used.to_string())
+        .env("IQOS_LIMIT", limit.to_string());
+
+    match tokio::task::block_in_place(|| command.status()) {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Warning: budget hook exited with {status}"),
+        Err(error) => eprintln!("Warning: could not run budget hook: {error}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(limit: u32, period: BudgetPeriod) -> Budget {
+        Budget {
+            limit,
+            period,
+            reset: "04:00".to_string(),
+            warn_at: vec![50, 80],
+            vibrate: true,
+            hook: None,
+        }
+    }
+
+    fn local(day: u32, hour: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn reads_budget_with_defaults() {
+        let budget: Budget = toml::from_str("limit = 20").unwrap();
+
+        assert_eq!(budget.period, BudgetPeriod::Daily);
+        assert_eq!(
+            budget.reset_time().unwrap(),
+            NaiveTime::from_hms_opt(0, 0, 0).unwrap()
+        );
+        assert_eq!(budget.warn_at, vec![80]);
+        assert!(budget.vibrate);
+        assert!(toml::from_str::<Budget>("limit = 20\nreset = \"25:00\"")
+            .unwrap()
+            .validate()
+            .is_err());
+        for warn_at in ["[0]", "[100]", "[101]"] {
+            let error = toml::from_str::<Budget>(&format!("limit = 20\nwarn_at = {warn_at}"))
+                .unwrap()
+                .validate()
+                .unwrap_err();
+            assert!(error.to_string().contains("from 1 to 99"), "{error}");
+        }
+        assert!(toml::from_str::<Budget>("limit = 20\nwarn_at = [99]")
+            .unwrap()
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn period_starts_at_the_last_reset() {
+        let daily = budget(20, BudgetPeriod::Daily);
+        // 2024-05-10 is a Friday.
+        assert_eq!(daily.period_start(local(10, 12)).unwrap(), local(10, 4));
+        assert_eq!(daily.period_start(local(10, 3)).unwrap(), local(9, 4));
+
+        let weekly = budget(100, BudgetPeriod::Weekly);
+        assert_eq!(weekly.period_start(local(10, 12)).unwrap(), local(6, 4));
+        assert_eq!(
+            weekly.period_start(local(6, 3)).unwrap(),
+            Local.with_ymd_and_hms(2024, 4, 29, 4, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn warns_once_per_threshold_then_locks() {
+        let budget = budget(10, BudgetPeriod::Daily);
+        let start = local(10, 4);
+        let mut state = BudgetState::new(start, 100);
+
+        assert!(state.update(&budget, start, 104).is_empty());
+        assert_eq!(
+            state.update(&budget, start, 105),
+            vec![BudgetAction::Warn { percent: 50 }]
+        );
+        assert!(state.update(&budget, start, 105).is_empty());
+        assert_eq!(
+            state.update(&budget, start, 108),
+            vec![BudgetAction::Warn { percent: 80 }]
+        );
+        assert_eq!(state.update(&budget, start, 110), vec![BudgetAction::Lock]);
+        assert!(state.update(&budget, start, 110).is_empty());
+        // Unlocked by hand and used again.
+        assert_eq!(state.update(&budget, start, 111), vec![BudgetAction::Lock]);
+    }
+
+    #[test]
+    fn new_period_resets_baseline_and_unlocks() {
+        let budget = budget(10, BudgetPeriod::Daily);
+        let mut state = BudgetState::new(local(9, 4), 100);
+        state.update(&budget, local(9, 4), 112);
+
+        assert_eq!(
+            state.update(&budget, local(10, 4), 112),
+            vec![BudgetAction::Reset { unlock: true }]
+        );
+        assert_eq!(state.baseline, 112);
+        assert_eq!(state.used(113), 1);
+    }
+
+    #[test]
+    fn counter_reset_moves_the_baseline() {
+        let budget = budget(10, BudgetPeriod::Daily);
+        let start = local(10, 4);
+        let mut state = BudgetState::new(start, 500);
+
+        assert!(state.update(&budget, start, 3).is_empty());
+        assert_eq!(state.used(4), 1);
+    }
+}
@@ -1,6 +1,14 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
-use clap::{ArgAction, Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+
+use crate::config::Annotation;
+use crate::loader::cmds::device::DeviceCommand;
+use crate::loader::cmds::help::cli_after_help;
+use crate::loader::cmds::RegisteredCommand;
+use crate::settings::{OutputFormat, Settings};
+use crate::target::{PickPolicy, ADDRESS_PREFIX};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -15,163 +23,290 @@ pub struct Cli {
     #[arg(short = 'v', long = "version", action = ArgAction::SetTrue)]
     pub version: bool,
 
-    /// Target device model or saved device label.
+    /// Target device: a model selector, saved label or address, optionally
+    /// prefixed with `model:`, `label:` or `addr:`.
     #[arg(long, value_name = "target")]
     pub model: Option<String>,
 
+    /// Target the device at this Bluetooth address.
+    #[arg(long, value_name = "addr", conflicts_with = "model")]
+    pub address: Option<String>,
+
+    /// Which device to use when several match a model selector.
+    #[arg(long, value_enum, value_name = "policy", default_value_t = PickPolicy::Strongest)]
+    pub pick: PickPolicy,
+
     /// BLE scan timeout in seconds.
     #[arg(long, value_name = "secs")]
     pub timeout: Option<u64>,
 
+    /// Read current settings and print what would change without writing.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Do not read settings back after writing them. Smart Gesture and the
+    /// lock state are never read back, since the device cannot report them.
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// How many times to rewrite a setting that does not read back as written.
+    #[arg(long, value_name = "n", default_value_t = 2)]
+    pub verify_retries: u32,
+
+    /// Report which device was targeted and where that choice came from.
+    #[arg(long)]
+    pub verbose: bool,
+
     #[command(subcommand)]
     pub command: Option<CliCommand>,
 }
 
+impl Cli {
+    /// The target given on the command line, with `--address` written as
+    /// an `addr:` target.
+    pub fn target(&self) -> Option<String> {
+        self.address
+            .as_ref()
+            .map(|address| format!("{ADDRESS_PREFIX}{address}"))
+            .or_else(|| self.model.clone())
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum CliCommand {
-    /// Configure auto-start.
-    Autostart {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
+    #[command(flatten)]
+    Registered(RegisteredCommand),
+    /// Show the audit log of state-changing commands.
+    Audit {
+        /// Only show changes made to this saved device.
+        #[arg(long, value_name = "label")]
+        device: Option<String>,
+        /// Only show changes since a date, RFC 3339 time or age (e.g. 7d).
+        #[arg(long, value_name = "when")]
+        since: Option<String>,
+    },
+    /// Enforce the puff budget configured for a saved device.
+    #[command(disable_help_subcommand = true)]
+    Budget {
+        #[command(subcommand)]
+        command: BudgetCommand,
     },
-    /// Display battery level.
-    Battery,
-    /// Set display brightness.
-    Brightness {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
+    /// Read two devices one after the other and show their differences.
+    Compare {
+        #[arg(value_name = "labelA")]
+        a: String,
+        #[arg(value_name = "labelB")]
+        b: String,
+        /// Output format [default: the `output` setting, or text].
+        #[arg(long, value_enum, value_name = "format")]
+        output: Option<OutputFormat>,
     },
+    /// Read and change the [settings] table of config.toml.
+    #[command(disable_help_subcommand = true)]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print a shell completion script.
+    Completions {
+        #[arg(value_name = "shell")]
+        shell: Shell,
+    },
+    /// Run the [[schedule]] rules from config.toml in the foreground.
+    Scheduler,
+    /// Show a live dashboard of the connected device.
+    Tui,
     /// Manage saved devices.
+    #[command(disable_help_subcommand = true, after_help = cli_after_help("device"))]
     Device {
         #[command(subcommand)]
         command: DeviceCommand,
     },
-    /// Retrieve telemetry data.
-    Diagnosis,
-    /// Activate find-my-device vibration.
-    Findmyiqos,
-    /// Configure FlexBattery.
-    Flexbattery {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
+    /// List completion candidates for `--model` (used by completion scripts).
+    #[command(name = "__targets", hide = true)]
+    Targets {
+        /// Only list saved device labels.
+        #[arg(long)]
+        labels: bool,
     },
-    /// Configure FlexPuff.
-    Flexpuff {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
-    },
-    /// Device metadata, firmware, and voltage snapshot.
-    Info,
-    /// Lock the device.
-    Lock,
-    /// Configure SmartGesture.
-    Smartgesture {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
-    },
-    /// Unlock the device.
-    Unlock,
-    /// Configure vibration feedback.
-    Vibration {
-        #[arg(
-            value_name = "arg",
-            allow_hyphen_values = true,
-            trailing_var_arg = true
-        )]
-        args: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum BudgetCommand {
+    /// Poll the puff counter, warn at thresholds and lock at the limit.
+    Watch {
+        /// How often to read the counter (e.g. 30s, 5m).
+        #[arg(long, value_name = "duration", default_value = "30s")]
+        interval: String,
     },
 }
 
-#[derive(Debug, Subcommand)]
-pub enum DeviceCommand {
-    /// Save the currently targeted device under a label.
-    Save {
-        #[arg(value_name = "label")]
-        label: String,
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the value in effect for a setting.
+    Get {
+        #[arg(value_name = "key")]
+        key: String,
     },
-    /// List saved device labels.
-    List,
-    /// Remove a saved device label.
-    Remove {
-        #[arg(value_name = "label")]
-        label: String,
+    /// Validate a value and store it in config.toml.
+    Set {
+        #[arg(value_name = "key")]
+        key: String,
+        #[arg(value_name = "value")]
+        value: String,
+    },
+    /// Remove a setting from config.toml, restoring its default.
+    Unset {
+        #[arg(value_name = "key")]
+        key: String,
     },
+    /// List every setting with its value and where the value comes from.
+    List,
+    /// Print the path of config.toml.
+    Path,
+    /// Open config.toml in $VISUAL or $EDITOR, then check it still parses.
+    Edit,
+    /// Move a legacy ~/.iqos_cli config to the XDG location, or upgrade the
+    /// file to the current format, keeping a backup.
+    Migrate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OneShotCommand {
-    Registered {
-        name: &'static str,
-        args: Vec<String>,
+    Registered(RegisteredCommand),
+    Audit {
+        device: Option<String>,
+        since: Option<String>,
     },
+    BudgetWatch {
+        interval: String,
+    },
+    Compare {
+        a: String,
+        b: String,
+        output: Option<OutputFormat>,
+    },
+    Config(ConfigCommand),
+    Scheduler,
+    Tui,
     DeviceSave {
         label: String,
     },
-    DeviceList,
+    DeviceList {
+        long: bool,
+        tags: Vec<String>,
+    },
+    DeviceAnnotate {
+        label: String,
+        annotation: Annotation,
+    },
     DeviceRemove {
         label: String,
     },
+    DeviceFirmware {
+        label: String,
+    },
+    DeviceRename {
+        old: String,
+        new: String,
+    },
+    DeviceSetDefault {
+        label: String,
+    },
+    DeviceShow {
+        label: String,
+    },
+    DevicePrune {
+        older_than: String,
+    },
+    DeviceExport {
+        labels: Vec<String>,
+        no_serial: bool,
+    },
+    DeviceImport {
+        file: PathBuf,
+        replace: bool,
+        prefix: Option<String>,
+        no_serial: bool,
+    },
+    Completions {
+        shell: Shell,
+    },
+    Targets {
+        labels_only: bool,
+    },
 }
 
 impl CliCommand {
     pub fn into_one_shot(self) -> OneShotCommand {
         match self {
-            Self::Autostart { args } => registered("autostart", args),
-            Self::Battery => registered("battery", Vec::new()),
-            Self::Brightness { args } => registered("brightness", args),
+            Self::Registered(command) => OneShotCommand::Registered(command),
+            Self::Audit { device, since } => OneShotCommand::Audit { device, since },
+            Self::Budget { command } => match command {
+                BudgetCommand::Watch { interval } => OneShotCommand::BudgetWatch { interval },
+            },
+            Self::Compare { a, b, output } => OneShotCommand::Compare { a, b, output },
+            Self::Config { command } => OneShotCommand::Config(command),
+            Self::Completions { shell } => OneShotCommand::Completions { shell },
+            Self::Scheduler => OneShotCommand::Scheduler,
+            Self::Tui => OneShotCommand::Tui,
             Self::Device { command } => match command {
                 DeviceCommand::Save { label } => OneShotCommand::DeviceSave { label },
-                DeviceCommand::List => OneShotCommand::DeviceList,
+                DeviceCommand::List { long, tags } => OneShotCommand::DeviceList { long, tags },
+                DeviceCommand::Annotate {
+                    label,
+                    owner,
+                    notes,
+                    tags,
+                    untags,
+                } => OneShotCommand::DeviceAnnotate {
+                    label,
+                    annotation: Annotation {
+                        owner,
+                        notes,
+                        add_tags: tags,
+                        remove_tags: untags,
+                    },
+                },
                 DeviceCommand::Remove { label } => OneShotCommand::DeviceRemove { label },
+                DeviceCommand::Firmware { label } => OneShotCommand::DeviceFirmware { label },
+                DeviceCommand::Rename { old, new } => OneShotCommand::DeviceRename { old, new },
+                DeviceCommand::SetDefault { label } => OneShotCommand::DeviceSetDefault { label },
+                DeviceCommand::Show { label } => OneShotCommand::DeviceShow { label },
+                DeviceCommand::Prune { older_than } => OneShotCommand::DevicePrune { older_than },
+                DeviceCommand::Export { labels, no_serial } => {
+                    OneShotCommand::DeviceExport { labels, no_serial }
+                }
+                DeviceCommand::Import {
+                    file,
+                    replace,
+                    prefix,
+                    no_serial,
+                    ..
+                } => OneShotCommand::DeviceImport {
+                    file,
+                    replace,
+                    prefix,
+                    no_serial,
+                },
+            },
+            Self::Targets { labels } => OneShotCommand::Targets {
+                labels_only: labels,
             },
-            Self::Diagnosis => registered("diagnosis", Vec::new()),
-            Self::Findmyiqos => registered("findmyiqos", Vec::new()),
-            Self::Flexbattery { args } => registered("flexbattery", args),
-            Self::Flexpuff { args } => registered("flexpuff", args),
-            Self::Info => registered("info", Vec::new()),
-            Self::Lock => registered("lock", Vec::new()),
-            Self::Smartgesture { args } => registered("smartgesture", args),
-            Self::Unlock => registered("unlock", Vec::new()),
-            Self::Vibration { args } => registered("vibration", args),
         }
     }
 }
 
-fn registered(name: &'static str, mut args: Vec<String>) -> OneShotCommand {
-    args.insert(0, name.to_string());
-    OneShotCommand::Registered { name, args }
-}
-
-pub fn scan_timeout(cli_value: Option<u64>) -> Duration {
-    let seconds = cli_value
-        .or_else(|| {
-            std::env::var("IQOS_SCAN_TIMEOUT")
-                .ok()
-                .and_then(|value| value.parse().ok())
-        })
-        .unwrap_or(10);
-
-    Duration::from_secs(seconds)
+/// `--timeout`, then `IQOS_SCAN_TIMEOUT`, then the `timeout` setting.
+pub fn scan_timeout(cli_value: Option<u64>, settings: &Settings) -> Duration {
+    settings.scan_timeout(cli_value)
 }
 
 pub fn print_version() {
@@ -185,6 +320,18 @@ pub fn has_version_flag(args: &[String]) -> bool {
         .any(|arg| arg == "-v" || arg == "--version")
 }
 
+/// Global options that take a value, as `--name value` or `--name=value`.
+pub(crate) const GLOBAL_VALUE_OPTIONS: &[&str] = &[
+    "--model",
+    "--address",
+    "--pick",
+    "--timeout",
+    "--verify-retries",
+];
+
+/// Global flags without a value.
+pub(crate) const GLOBAL_FLAGS: &[&str] = &["--dry-run", "--no-verify", "--verbose"];
+
 pub fn normalize_global_options(args: Vec<String>) -> Vec<String> {
     let Some((program, rest)) = args.split_first() else {
         return args;
@@ -202,34 +349,23 @@ pub fn normalize_global_options(args: Vec<String>) -> Vec<String> {
             break;
         }
 
-        if let Some(value) = arg.strip_prefix("--model=") {
-            global_options.push("--model".to_string());
-            global_options.push(value.to_string());
-            continue;
-        }
-
-        if arg == "--model" {
-            match iter.next() {
-                Some(value) => {
-                    global_options.push(arg.clone());
-                    global_options.push(value.clone());
-                }
-                None => remaining.push(arg.clone()),
-            }
-            continue;
-        }
-
-        if let Some(value) = arg.strip_prefix("--timeout=") {
-            global_options.push("--timeout".to_string());
-            global_options.push(value.to_string());
+        if GLOBAL_FLAGS.contains(&arg.as_str()) {
+            global_options.push(arg.clone());
             continue;
         }
 
-        if arg == "--timeout" {
-            match iter.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if GLOBAL_VALUE_OPTIONS.contains(&name) {
+            match inline_value
+                .map(str::to_string)
+                .or_else(|| iter.next().cloned())
+            {
                 Some(value) => {
-                    global_options.push(arg.clone());
-                    global_options.push(value.clone());
+                    global_options.push(name.to_string());
+                    global_options.push(value);
                 }
                 None => remaining.push(arg.clone()),
             }
@@ -251,7 +387,12 @@ pub fn should_use_cli(args: &[String]) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::loader::args::{Switch, SwitchOrStatus};
+    use crate::loader::cmds::brightness::{BrightnessArg, BrightnessArgs};
+    use crate::loader::cmds::flexpuff::FlexPuffArgs;
+    use crate::loader::cmds::vibration::{VibrationArgs, VibrationFlag, VibrationWord};
     use crate::model_selector::parse_device_model;
+    use clap::CommandFactory;
     use iqos::DeviceModel;
 
     #[test]
@@ -273,42 +414,45 @@ mod tests {
     }
 
     #[test]
-    fn converts_registered_commands_to_repl_arg_shape() {
-        let command = CliCommand::Vibration {
-            args: vec!["heating".to_string(), "on".to_string()],
-        }
-        .into_one_shot();
+    fn parses_registered_commands_into_typed_args() {
+        let cli = Cli::try_parse_from(["iqos", "flexpuff", "enable"]).unwrap();
 
         assert_eq!(
-            command,
-            OneShotCommand::Registered {
-                name: "vibration",
-                args: vec![
-                    "vibration".to_string(),
-                    "heating".to_string(),
-                    "on".to_string()
-                ],
-            }
+            cli.command.map(CliCommand::into_one_shot),
+            Some(OneShotCommand::Registered(RegisteredCommand::Flexpuff(
+                FlexPuffArgs {
+                    action: SwitchOrStatus::On,
+                }
+            )))
         );
     }
 
     #[test]
-    fn passthrough_commands_accept_hyphen_prefixed_values() {
-        let cli = Cli::try_parse_from(["iqos", "vibration", "heating", "-badflag", "--also-value"])
-            .unwrap();
-
+    fn setting_values_ignore_case() {
+        let cli = Cli::try_parse_from(["iqos", "brightness", "HIGH"]).unwrap();
         assert_eq!(
             cli.command.map(CliCommand::into_one_shot),
-            Some(OneShotCommand::Registered {
-                name: "vibration",
-                args: vec![
-                    "vibration".to_string(),
-                    "heating".to_string(),
-                    "-badflag".to_string(),
-                    "--also-value".to_string(),
-                ],
-            })
+            Some(OneShotCommand::Registered(RegisteredCommand::Brightness(
+                BrightnessArgs {
+                    level: Some(BrightnessArg::High),
+                }
+            )))
         );
+
+        assert!(Cli::try_parse_from(["iqos", "autostart", "ON"]).is_ok());
+        assert!(Cli::try_parse_from(["iqos", "flexpuff", "Enable"]).is_ok());
+        assert!(Cli::try_parse_from(["iqos", "smartgesture", "Off"]).is_ok());
+        assert!(Cli::try_parse_from(["iqos", "flexbattery", "ECO"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_options_and_suggests_commands() {
+        let error = Cli::try_parse_from(["iqos", "vibration", "heating", "-badflag"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::UnknownArgument);
+
+        let error = Cli::try_parse_from(["iqos", "flexbatery"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidSubcommand);
+        assert!(error.to_string().contains("flexbattery"));
     }
 
     #[test]
@@ -329,7 +473,70 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         assert_eq!(cli.model.as_deref(), Some("iluma-i"));
         assert_eq!(cli.timeout, Some(2));
-        assert!(matches!(cli.command, Some(CliCommand::Battery)));
+        assert!(matches!(
+            cli.command,
+            Some(CliCommand::Registered(RegisteredCommand::Battery(_)))
+        ));
+    }
+
+    #[test]
+    fn address_is_an_addr_target() {
+        let args =
+            normalize_global_options(strings(["iqos", "battery", "--address=AA:BB:CC:DD:EE:FF"]));
+        assert_eq!(
+            args,
+            strings(["iqos", "--address", "AA:BB:CC:DD:EE:FF", "battery"])
+        );
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.target().as_deref(), Some("addr:AA:BB:CC:DD:EE:FF"));
+
+        let cli = Cli::try_parse_from(["iqos", "--model", "label:iluma"]).unwrap();
+        assert_eq!(cli.target().as_deref(), Some("label:iluma"));
+
+        assert!(Cli::try_parse_from([
+            "iqos",
+            "--model",
+            "iluma",
+            "--address",
+            "AA:BB:CC:DD:EE:FF"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parses_pick_policy_after_command() {
+        let cli = Cli::try_parse_from(["iqos", "battery"]).unwrap();
+        assert_eq!(cli.pick, PickPolicy::Strongest);
+
+        let args = normalize_global_options(strings(["iqos", "battery", "--pick", "prompt"]));
+        assert_eq!(args, strings(["iqos", "--pick", "prompt", "battery"]));
+        assert_eq!(Cli::try_parse_from(args).unwrap().pick, PickPolicy::Prompt);
+
+        assert!(Cli::try_parse_from(["iqos", "--pick", "loudest", "battery"]).is_err());
+    }
+
+    #[test]
+    fn global_option_lists_match_cli() {
+        let command = Cli::command();
+        for name in GLOBAL_VALUE_OPTIONS.iter().chain(GLOBAL_FLAGS) {
+            let argument = command
+                .get_arguments()
+                .find(|argument| argument.get_long() == name.strip_prefix("--"))
+                .unwrap_or_else(|| panic!("{name} is not a global option"));
+            assert_eq!(
+                argument.get_action().takes_values(),
+                GLOBAL_VALUE_OPTIONS.contains(name),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_verbose_after_command() {
+        let args = normalize_global_options(strings(["iqos", "battery", "--verbose"]));
+
+        assert_eq!(args, strings(["iqos", "--verbose", "battery"]));
+        assert!(Cli::try_parse_from(args).unwrap().verbose);
     }
 
     #[test]
@@ -363,11 +570,50 @@ mod tests {
         assert_eq!(cli.timeout, Some(3));
         assert_eq!(
             cli.command.map(CliCommand::into_one_shot),
-            Some(OneShotCommand::Registered {
-                name: "vibration",
-                args: strings(["vibration", "heating", "on"]),
-            })
+            Some(OneShotCommand::Registered(RegisteredCommand::Vibration(
+                VibrationArgs {
+                    settings: vec![
+                        VibrationWord::Flag(VibrationFlag::Heating),
+                        VibrationWord::State(Switch::On),
+                    ],
+                }
+            )))
+        );
+    }
+
+    #[test]
+    fn moves_dry_run_flag_before_command() {
+        let args = normalize_global_options(strings([
+            "iqos",
+            "vibration",
+            "heating",
+            "off",
+            "--dry-run",
+        ]));
+
+        assert_eq!(
+            args,
+            strings(["iqos", "--dry-run", "vibration", "heating", "off"])
         );
+        assert!(Cli::try_parse_from(args).unwrap().dry_run);
+    }
+
+    #[test]
+    fn verification_is_on_by_default_and_configurable() {
+        let cli = Cli::try_parse_from(["iqos", "brightness", "low"]).unwrap();
+        assert!(!cli.no_verify);
+        assert_eq!(cli.verify_retries, 2);
+
+        let args = normalize_global_options(strings([
+            "iqos",
+            "brightness",
+            "low",
+            "--no-verify",
+            "--verify-retries=5",
+        ]));
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(cli.no_verify);
+        assert_eq!(cli.verify_retries, 5);
     }
 
     #[test]
@@ -450,6 +696,168 @@ mod tests {
         assert!(cli.command.is_none());
     }
 
+    #[test]
+    fn parses_budget_watch_interval() {
+        let cli = Cli::try_parse_from(["iqos", "--model", "minera", "budget", "watch"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::BudgetWatch {
+                interval: "30s".to_string()
+            }
+        );
+
+        let cli = Cli::try_parse_from(["iqos", "budget", "watch", "--interval", "5m"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::BudgetWatch {
+                interval: "5m".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_compare_output_format() {
+        let cli = Cli::try_parse_from(["iqos", "compare", "minera", "blackcat"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::Compare {
+                a: "minera".to_string(),
+                b: "blackcat".to_string(),
+                output: None,
+            }
+        );
+
+        let cli = Cli::try_parse_from(["iqos", "compare", "a", "b", "--output", "json"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::Compare {
+                output: Some(OutputFormat::Json),
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["iqos", "compare", "a"]).is_err());
+    }
+
+    #[test]
+    fn parses_config_subcommands() {
+        let cli = Cli::try_parse_from(["iqos", "config", "set", "timeout", "5"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::Config(ConfigCommand::Set {
+                key: "timeout".to_string(),
+                value: "5".to_string(),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["iqos", "config", "list"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::Config(ConfigCommand::List)
+        );
+        assert!(Cli::try_parse_from(["iqos", "config", "set", "timeout"]).is_err());
+    }
+
+    #[test]
+    fn parses_device_export_and_import() {
+        let cli =
+            Cli::try_parse_from(["iqos", "device", "export", "a", "b", "--no-serial"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::DeviceExport {
+                labels: vec!["a".to_string(), "b".to_string()],
+                no_serial: true,
+            }
+        );
+
+        let cli = Cli::try_parse_from([
+            "iqos",
+            "device",
+            "import",
+            "devices.toml",
+            "--replace",
+            "--prefix",
+            "team-",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::DeviceImport {
+                file: PathBuf::from("devices.toml"),
+                replace: true,
+                prefix: Some("team-".to_string()),
+                no_serial: false,
+            }
+        );
+        assert!(Cli::try_parse_from([
+            "iqos",
+            "device",
+            "import",
+            "devices.toml",
+            "--merge",
+            "--replace"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parses_device_annotate_and_tag_filter() {
+        let cli = Cli::try_parse_from([
+            "iqos", "device", "annotate", "minera", "--owner", "Mika", "--tag", "loaner", "--tag",
+            "lab", "--untag", "spare",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::DeviceAnnotate {
+                label: "minera".to_string(),
+                annotation: Annotation {
+                    owner: Some("Mika".to_string()),
+                    notes: None,
+                    add_tags: vec!["loaner".to_string(), "lab".to_string()],
+                    remove_tags: vec!["spare".to_string()],
+                },
+            }
+        );
+
+        let cli = Cli::try_parse_from(["iqos", "device", "list", "--tag", "loaner"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_one_shot(),
+            OneShotCommand::DeviceList {
+                long: false,
+                tags: vec!["loaner".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_tui() {
+        let cli = Cli::try_parse_from(["iqos", "--model", "minera", "tui"]).unwrap();
+
+        assert_eq!(cli.model.as_deref(), Some("minera"));
+        assert_eq!(cli.command.unwrap().into_one_shot(), OneShotCommand::Tui);
+    }
+
+    #[test]
+    fn parses_completions_shell() {
+        let cli = Cli::try_parse_from(["iqos", "completions", "zsh"]).unwrap();
+
+        assert_eq!(
+            cli.command.map(CliCommand::into_one_shot),
+            Some(OneShotCommand::Completions { shell: Shell::Zsh })
+        );
+        assert!(Cli::try_parse_from(["iqos", "completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn subcommand_help_includes_command_grammar() {
+        let error = Cli::try_parse_from(["iqos", "vibration", "--help"]).unwrap_err();
+        let help = error.to_string();
+
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayHelp);
+        assert!(help.contains("Usage: iqos vibration [FLAG STATE]..."));
+        assert!(help.contains("Supported models: All models"));
+    }
+
     fn strings(values: impl IntoIterator<Item = &'static str>) -> Vec<String> {
         values.into_iter().map(str::to_string).collect()
     }
@@ -0,0 +1,328 @@
+use anyhow::Result;
+use colored::Colorize;
+use iqos::{DeviceCapability, Iqos, IqosBle};
+use serde::Serialize;
+
+use crate::config::ConnectedDevice;
+use crate::overrides::DeviceProfile;
+
+/// Shown for settings the model does not have.
+const NOT_SUPPORTED: &str = "n/a";
+
+/// Identity, status and settings read from one device, as named rows so two
+/// devices can be lined up field by field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub label: String,
+    pub fields: Vec<(&'static str, Option<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldComparison {
+    pub field: &'static str,
+    pub a: Option<String>,
+    pub b: Option<String>,
+    pub same: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Comparison {
+    pub a: String,
+    pub b: String,
+    pub fields: Vec<FieldComparison>,
+}
+
+/// Read everything `compare` shows. Settings the model does not support are
+/// reported as `n/a`; any failed read fails the snapshot.
+pub async fn read_snapshot(
+    label: &str,
+    iqos: &Iqos<IqosBle>,
+    device: &ConnectedDevice,
+) -> Result<Snapshot> {
+    let transport = iqos.transport();
+    let profile = DeviceProfile::of(device);
+    let model = profile.model;
+    let status = iqos
+        .read_device_status(model, transport.device_info().clone())
+        .await?;
+    let diagnosis = iqos.read_diagnosis().await?;
+    let battery = transport.read_battery_level().await?;
+    let on_off = |enabled: bool| Some(if enabled { "on" } else { "off" }.to_string());
+    let unsupported = || Some(NOT_SUPPORTED.to_string());
+
+    let mut fields = vec![
+        ("address", Some(device.address.clone())),
+        ("model", Some(format!("{model:?}"))),
+        ("serial_number", device.serial_number.clone()),
+        ("product_number", Some(status.product_number.clone())),
+        ("stick_firmware", Some(status.stick_firmware.to_string())),
+        (
+            "holder_product_number",
+            status.holder_product_number.clone(),
+        ),
+        (
+            "holder_firmware",
+            status
+                .holder_firmware
+                .as_ref()
+                .map(|firmware| firmware.to_string()),
+        ),
+        ("battery_level", Some(format!("{battery}%"))),
+        (
+            "battery_voltage",
+            diagnosis
+                .battery_voltage
+                .or(status.battery_voltage)
+                .map(|volts| format!("{volts:.2}V")),
+        ),
+        (
+            "total_puffs",
+            diagnosis.total_smoking_count.map(|count| count.to_string()),
+        ),
+        (
+            "days_used",
+            diagnosis.days_used.map(|days| days.to_string()),
+        ),
+    ];
+
+    fields.push((
+        "brightness",
+        if profile.supports(DeviceCapability::Brightness) {
+            Some(iqos.read_brightness().await?.to_string())
+        } else {
+            unsupported()
+        },
+    ));
+
+    if profile.supports(DeviceCapability::Vibration) {
+        let vibration = iqos.read_vibration_settings(model).await?;
+        fields.extend([
+            ("vibration.heating", on_off(vibration.when_heating_start())),
+            (
+                "vibration.starting",
+                on_off(vibration.when_starting_to_use()),
+            ),
+            ("vibration.puffend", on_off(vibration.when_puff_end())),
+            (
+                "vibration.terminated",
+                on_off(vibration.when_manually_terminated()),
+            ),
+            (
+                "vibration.charge",
+                match vibration.when_charging_start() {
+                    Some(enabled) => on_off(enabled),
+                    None => unsupported(),
+                },
+            ),
+        ]);
+    } else {
+        fields.extend(
+            [
+                "vibration.heating",
+                "vibration.starting",
+                "vibration.puffend",
+                "vibration.terminated",
+                "vibration.charge",
+            ]
+            .map(|field| (field, unsupported())),
+        );
+    }
+
+    fields.push((
+        "flexpuff",
+        if profile.supports(DeviceCapability::FlexPuff) {
+            on_off(iqos.read_flexpuff(model).await?.is_enabled())
+        } else {
+            unsupported()
+        },
+    ));
+
+    if profile.supports(DeviceCapability::FlexBattery) {
+        let flexbattery = iqos.read_flexbattery(model).await?;
+        fields.extend([
+            (
+                "flexbattery.mode",
+                Some(format!("{:?}", flexbattery.mode())),
+            ),
+            (
+                "flexbattery.pause",
+                flexbattery.pause_mode().and_then(on_off),
+            ),
+        ]);
+    } else {
+        fields
+            .extend(["flexbattery.mode", "flexbattery.pause"].map(|field| (field, unsupported())));
+    }
+
+    fields.push((
+        "autostart",
+        if profile.supports(DeviceCapability::AutoStart) {
+            on_off(iqos.read_autostart(model).await?)
+        } else {
+            unsupported()
+        },
+    ));
+
+    Ok(Snapshot {
+        label: label.to_string(),
+        fields,
+    })
+}
+
+/// Line up two snapshots. Fields only one device reports are kept, with the
+/// other side empty.
+pub fn compare(a: &Snapshot, b: &Snapshot) -> Comparison {
+    let mut fields: Vec<FieldComparison> = a
+        .fields
+        .iter()
+        .map(|(field, value)| {
+            let other = b
+                .fields
+                .iter()
+                .find(|(name, _)| name == field)
+                .and_then(|(_, value)| value.clone());
+            FieldComparison {
+                field,
+                same: *value == other,
+                a: value.clone(),
+                b: other,
+            }
+        })
+        .collect();
+    for (field, value) in &b.fields {
+        if !a.fields.iter().any(|(name, _)| name == field) {
+            fields.push(FieldComparison {
+                field,
+                a: None,
+                b: value.clone(),
+                same: value.is_none(),
+            });
+        }
+    }
+
+    Comparison {
+        a: a.label.clone(),
+        b: b.label.clone(),
+        fields,
+    }
+}
+
+impl Comparison {
+    pub fn differences(&self) -> usize {
+        self.fields.iter().filter(|field| !field.same).count()
+    }
+
+    /// Two columns, one row per field, with differing rows marked by `*`
+    /// and, with `highlight`, coloured.
+    pub fn render_text(&self, highlight: bool) -> String {
+        let value = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let field_width = self
+            .fields
+            .iter()
+            .map(|field| field.field.len())
+            .max()
+            .unwrap_or(0)
+            .max("FIELD".len());
+        let a_width = self
+            .fields
+            .iter()
+            .map(|field| value(&field.a).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(self.a.chars().count());
+
+        let mut text = format!(
+            "  {:<field_width$}  {:<a_width$}  {}\n",
+            "FIELD", self.a, self.b
+        );
+        for field in &self.fields {
+            let row = format!(
+                "{:<field_width$}  {:<a_width$}  {}",
+                field.field,
+                value(&field.a),
+                value(&field.b)
+            );
+            if field.same {
+                text.push_str(&format!("  {row}\n"));
+            } else if highlight {
+                text.push_str(&format!("{} {}\n", "*".yellow(), row.yellow().bold()));
+            } else {
+                text.push_str(&format!("* {row}\n"));
+            }
+        }
+
+        match self.differences() {
+            0 => text.push_str("No differences\n"),
+            1 => text.push_str("1 field differs\n"),
+            count => text.push_str(&format!("{count} fields differ\n")),
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(label: &str, fields: &[(&'static str, Option<&str>)]) -> Snapshot {
+        Snapshot {
+            label: label.to_string(),
+            fields: fields
+                .iter()
+                .map(|(field, value)| (*field, value.map(str::to_string)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn marks_fields_that_differ() {
+        let a = snapshot(
+            "minera",
+            &[
+                ("stick_firmware", Some("v1.0")),
+                ("brightness", Some("high")),
+                ("holder_firmware", None),
+            ],
+        );
+        let b = snapshot(
+            "blackcat",
+            &[
+                ("stick_firmware", Some("v1.1")),
+                ("brightness", Some("high")),
+                ("holder_firmware", None),
+            ],
+        );
+
+        let comparison = compare(&a, &b);
+
+        assert_eq!(comparison.differences(), 1);
+        assert_eq!(
+            comparison.render_text(false),
+            "  FIELD            minera  blackcat\n\
+             * stick_firmware   v1.0    v1.1\n  \
+             brightness       high    high\n  \
+             holder_firmware  -       -\n\
+             1 field differs\n"
+        );
+    }
+
+    #[test]
+    fn serializes_rows_for_json_output() {
+        let a = snapshot("a", &[("flexpuff", Some("on"))]);
+        let b = snapshot("b", &[("flexpuff", Some("off")), ("autostart", Some("on"))]);
+
+        let json = serde_json::to_value(compare(&a, &b)).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "a": "a",
+                "b": "b",
+                "fields": [
+                    {"field": "flexpuff", "a": "on", "b": "off", "same": false},
+                    {"field": "autostart", "a": null, "b": "on", "same": false},
+                ],
+            })
+        );
+    }
+}
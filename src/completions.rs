@@ -0,0 +1,406 @@
+use clap::CommandFactory;
+
+use crate::cli::{Cli, Shell, GLOBAL_FLAGS, GLOBAL_VALUE_OPTIONS};
+use crate::config::AppConfig;
+use crate::model_selector::MODEL_SELECTORS;
+
+const DEVICE_SUBCOMMANDS: &[&str] = &[
+    "save",
+    "list",
+    "show",
+    "rename",
+    "set-default",
+    "remove",
+    "annotate",
+    "prune",
+    "firmware",
+    "export",
+    "import",
+];
+/// `device` subcommands whose argument is a saved label.
+const LABEL_SUBCOMMANDS: &[&str] = &[
+    "show",
+    "rename",
+    "set-default",
+    "remove",
+    "annotate",
+    "firmware",
+    "export",
+];
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// Values offered for `--model`: saved labels first, then model selectors.
+pub fn completion_targets(config: &AppConfig, labels_only: bool) -> Vec<String> {
+    let mut targets: Vec<String> = config.devices.keys().cloned().collect();
+    if !labels_only {
+        targets.extend(MODEL_SELECTORS.iter().map(|selector| selector.to_string()));
+    }
+    targets
+}
+
+pub fn print_completion_targets(labels_only: bool) {
+    // Completion callbacks must never fail loudly; an unreadable config just
+    // means there are no labels to offer.
+    let config = AppConfig::load().unwrap_or_default();
+    for target in completion_targets(&config, labels_only) {
+        println!("{target}");
+    }
+}
+
+pub fn script(shell: Shell) -> String {
+    let commands = visible_commands();
+    match shell {
+        Shell::Bash => bash_script(&commands),
+        Shell::Zsh => zsh_script(&commands),
+        Shell::Fish => fish_script(&commands),
+    }
+}
+
+fn visible_commands() -> Vec<(String, String)> {
+    Cli::command()
+        .get_subcommands()
+        .filter(|command| !command.is_hide_set())
+        .map(|command| {
+            let about = command
+                .get_about()
+                .map(|about| about.to_string().trim_end_matches('.').to_string())
+                .unwrap_or_default();
+            (command.get_name().to_string(), about)
+        })
+        .collect()
+}
+
+/// A global option as clap defines it, so the scripts follow `cli.rs`.
+struct GlobalOption {
+    name: &'static str,
+    takes_value: bool,
+    help: String,
+    values: Vec<String>,
+}
+
+fn global_options() -> Vec<GlobalOption> {
+    let command = Cli::command();
+    GLOBAL_VALUE_OPTIONS
+        .iter()
+        .chain(GLOBAL_FLAGS)
+        .map(|name| {
+            let argument = command
+                .get_arguments()
+                .find(|argument| argument.get_long() == name.strip_prefix("--"));
+            GlobalOption {
+                name,
+                takes_value: GLOBAL_VALUE_OPTIONS.contains(name),
+                help: argument
+                    .and_then(|argument| argument.get_help())
+                    .map(|help| help.to_string().trim_end_matches('.').to_string())
+                    .unwrap_or_default(),
+                values: argument
+                    .filter(|argument| argument.get_action().takes_values())
+                    .map(|argument| {
+                        argument
+                            .get_possible_values()
+                            .iter()
+                            .filter(|value| !value.is_hide_set())
+                            .map(|value| value.get_name().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// Every global option, for completing the word before the command.
+fn global_option_words(options: &[GlobalOption]) -> String {
+    options
+        .iter()
+        .map(|option| option.name)
+        .chain(["--help", "--version"])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Value options whose value is taken as it is, joined with `|`.
+fn free_value_options(options: &[GlobalOption]) -> String {
+    options
+        .iter()
+        .filter(|option| option.takes_value && option.name != "--model" && option.values.is_empty())
+        .map(|option| option.name)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn bash_script(commands: &[(String, String)]) -> String {
+    let names = command_names(commands);
+    let options = global_options();
+    let words = global_option_words(&options);
+    let skipped = GLOBAL_VALUE_OPTIONS.join("|");
+    let free = free_value_options(&options);
+    let valued: String = options
+        .iter()
+        .filter(|option| !option.values.is_empty())
+        .map(|option| {
+            format!(
+                "        {})\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            return\n            ;;\n",
+                option.name,
+                option.values.join(" ")
+            )
+        })
+        .collect();
+    let device = DEVICE_SUBCOMMANDS.join(" ");
+    let labelled = LABEL_SUBCOMMANDS.join(" ");
+    let shells = SHELLS.join(" ");
+
+    format!(
+        r#"_iqos() {{
+    local cur prev cmd sub i
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+    case "$prev" in
+        --model)
+            COMPREPLY=($(compgen -W "$("${{COMP_WORDS[0]}}" __targets 2>/dev/null)" -- "$cur"))
+            return
+            ;;
+{valued}        {free})
+            return
+            ;;
+    esac
+
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${{COMP_WORDS[i]}}" in
+            {skipped}) ((i++)) ;;
+            -*) ;;
+            *)
+                if [[ -z "$cmd" ]]; then
+                    cmd="${{COMP_WORDS[i]}}"
+                elif [[ -z "$sub" ]]; then
+                    sub="${{COMP_WORDS[i]}}"
+                fi
+                ;;
+        esac
+    done
+
+    case "$cmd" in
+        "")
+            COMPREPLY=($(compgen -W "{names} {words}" -- "$cur"))
+            ;;
+        device)
+            if [[ -z "$sub" ]]; then
+                COMPREPLY=($(compgen -W "{device}" -- "$cur"))
+            elif [[ " {labelled} " == *" $sub "* ]]; then
+                COMPREPLY=($(compgen -W "$("${{COMP_WORDS[0]}}" __targets --labels 2>/dev/null)" -- "$cur"))
+            fi
+            ;;
+        compare)
+            COMPREPLY=($(compgen -W "$("${{COMP_WORDS[0]}}" __targets 2>/dev/null)" -- "$cur"))
+            ;;
+        completions)
+            [[ -z "$sub" ]] && COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+            ;;
+    esac
+}}
+complete -F _iqos iqos
+"#
+    )
+}
+
+fn zsh_script(commands: &[(String, String)]) -> String {
+    let described = commands
+        .iter()
+        .map(|(name, about)| format!("        '{name}:{}'", about.replace('\'', "'\\''")))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let device = DEVICE_SUBCOMMANDS.join(" ");
+    let labelled = LABEL_SUBCOMMANDS.join("|");
+    let shells = SHELLS.join(" ");
+    let options = global_options();
+    let words = global_option_words(&options);
+    let skipped = GLOBAL_VALUE_OPTIONS.join("|");
+    let free = free_value_options(&options);
+    let valued: String = options
+        .iter()
+        .filter(|option| !option.values.is_empty())
+        .map(|option| {
+            format!(
+                "        {})\n            compadd {}\n            return\n            ;;\n",
+                option.name,
+                option.values.join(" ")
+            )
+        })
+        .collect();
+
+    format!(
+        r#"#compdef iqos
+
+_iqos() {{
+    local -a commands targets
+    local cmd sub i
+    commands=(
+{described}
+    )
+
+    case "$words[CURRENT-1]" in
+        --model)
+            targets=(${{(f)"$("$words[1]" __targets 2>/dev/null)"}})
+            compadd -a targets
+            return
+            ;;
+{valued}        {free})
+            return
+            ;;
+    esac
+
+    for ((i = 2; i < CURRENT; i++)); do
+        case "$words[i]" in
+            {skipped}) ((i++)) ;;
+            -*) ;;
+            *)
+                if [[ -z "$cmd" ]]; then
+                    cmd="$words[i]"
+                elif [[ -z "$sub" ]]; then
+                    sub="$words[i]"
+                fi
+                ;;
+        esac
+    done
+
+    case "$cmd" in
+        "")
+            _describe 'command' commands
+            compadd -- {words}
+            ;;
+        device)
+            if [[ -z "$sub" ]]; then
+                compadd {device}
+            elif [[ "$sub" == ({labelled}) ]]; then
+                targets=(${{(f)"$("$words[1]" __targets --labels 2>/dev/null)"}})
+                compadd -a targets
+            fi
+            ;;
+        compare)
+            targets=(${{(f)"$("$words[1]" __targets 2>/dev/null)"}})
+            compadd -a targets
+            ;;
+        completions)
+            [[ -z "$sub" ]] && compadd {shells}
+            ;;
+    esac
+}}
+
+compdef _iqos iqos
+"#
+    )
+}
+
+fn fish_script(commands: &[(String, String)]) -> String {
+    let names = command_names(commands);
+    let device = DEVICE_SUBCOMMANDS.join(" ");
+    let labelled = LABEL_SUBCOMMANDS.join(" ");
+    let shells = SHELLS.join(" ");
+    let mut script = String::from("complete -c iqos -f\n");
+    for option in global_options() {
+        let mut line = format!("complete -c iqos -l {}", &option.name[2..]);
+        if option.takes_value {
+            line.push_str(" -x");
+        }
+        if option.name == "--model" {
+            line.push_str(" -a '(iqos __targets 2>/dev/null)'");
+        } else if !option.values.is_empty() {
+            line.push_str(&format!(" -a '{}'", option.values.join(" ")));
+        }
+        script.push_str(&format!(
+            "{line} -d '{}'\n",
+            option.help.replace('\'', "\\'")
+        ));
+    }
+    script.push_str("complete -c iqos -s v -l version -d 'Print IQOS CLI version'\n");
+
+    for (name, about) in commands {
+        script.push_str(&format!(
+            "complete -c iqos -n 'not __fish_seen_subcommand_from {names}' -a {name} -d '{}'\n",
+            about.replace('\'', "\\'")
+        ));
+    }
+
+    script.push_str(&format!(
+        "complete -c iqos -n '__fish_seen_subcommand_from device; and not __fish_seen_subcommand_from {device}' -a '{device}'\n\
+         complete -c iqos -n '__fish_seen_subcommand_from device; and __fish_seen_subcommand_from {labelled}' -a '(iqos __targets --labels 2>/dev/null)'\n\
+         complete -c iqos -n '__fish_seen_subcommand_from compare' -a '(iqos __targets 2>/dev/null)'\n\
+         complete -c iqos -n '__fish_seen_subcommand_from completions; and not __fish_seen_subcommand_from {shells}' -a '{shells}'\n"
+    ));
+
+    script
+}
+
+fn command_names(commands: &[(String, String)]) -> String {
+    commands
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SavedDevice;
+
+    #[test]
+    fn lists_labels_before_model_selectors() {
+        let mut config = AppConfig::default();
+        config.devices.insert(
+            "minera".to_string(),
+            SavedDevice {
+                address: "AA:BB:CC:DD:EE:FF".to_string(),
+                ..SavedDevice::default()
+            },
+        );
+
+        let targets = completion_targets(&config, false);
+        assert_eq!(targets.first().map(String::as_str), Some("minera"));
+        assert!(targets.contains(&"iluma-i-prime".to_string()));
+
+        assert_eq!(completion_targets(&config, true), vec!["minera"]);
+    }
+
+    #[test]
+    fn hides_internal_target_command() {
+        assert!(visible_commands()
+            .iter()
+            .all(|(name, _)| name != "__targets"));
+    }
+
+    #[test]
+    fn scripts_cover_visible_commands_and_call_back_for_targets() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell);
+
+            for command in ["battery", "device", "vibration", "completions"] {
+                assert!(script.contains(command), "{shell:?} missing {command}");
+            }
+            assert!(script.contains("__targets --labels"), "{shell:?}");
+            for option in GLOBAL_VALUE_OPTIONS.iter().chain(GLOBAL_FLAGS) {
+                let name = if shell == Shell::Fish {
+                    format!("-l {}", &option[2..])
+                } else {
+                    option.to_string()
+                };
+                assert!(script.contains(&name), "{shell:?} missing {option}");
+            }
+        }
+    }
+
+    #[test]
+    fn scripts_skip_every_global_option_value() {
+        let skipped = GLOBAL_VALUE_OPTIONS.join("|");
+        assert!(skipped.contains("--verify-retries"));
+        for shell in [Shell::Bash, Shell::Zsh] {
+            assert!(
+                script(shell).contains(&format!("{skipped}) ((i++)) ;;")),
+                "{shell:?}"
+            );
+        }
+        assert!(script(Shell::Bash).contains("--address|--timeout|--verify-retries)"));
+    }
+}
@@ -1,30 +1,68 @@
 use std::collections::BTreeMap;
-use std::fs::{self, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
 #[cfg(unix)]
 use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
 
 use anyhow::{bail, Context as _, Result};
-use iqos::DeviceModel;
+use chrono::{DateTime, FixedOffset, Local};
+use iqos::{DeviceModel, Iqos, IqosBle};
 use serde::{Deserialize, Serialize};
 
+use crate::budget::{Budget, BudgetPeriod};
 use crate::model_selector::is_reserved_model_label;
+use crate::overrides::{DeviceProfile, ModelOverride};
+use crate::schedule::ScheduleRule;
+use crate::settings::Settings;
+use crate::target::{has_selector_prefix, strip_selector_prefix, LABEL_PREFIX};
+
+/// Format version written to config.toml. Bump it together with a new entry
+/// in [`MIGRATIONS`] whenever the layout changes.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+/// Version 0 is every file written before the `version` key existed; its
+/// layout is unchanged in version 1.
+const MIGRATIONS: [fn(&mut toml::Table) -> Result<()>; CONFIG_VERSION as usize] = [|_| Ok(())];
+
+/// Files kept next to config.toml that move with it.
+const STATE_FILES: &[&str] = &[
+    "audit.jsonl",
+    "budget.json",
+    "scheduler.json",
+    "firmware.json",
+];
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct AppConfig {
+    /// Always [`CONFIG_VERSION`] once loaded; older files are migrated.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub default: Option<DefaultDevice>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub devices: BTreeMap<String, SavedDevice>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_overrides: Vec<ModelOverride>,
 }
 
+/// The device used when no `--model` is given. When it names a saved label,
+/// the label's current address is used, so renaming or re-saving the label
+/// carries over; `address` is the fallback once the label is gone.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct DefaultDevice {
     pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct SavedDevice {
     pub address: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -33,6 +71,129 @@ pub struct SavedDevice {
     pub model: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub serial_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_connected: Option<DateTime<FixedOffset>>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub connect_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stick_firmware: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder_product_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder_firmware: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_voltage: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<Budget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Prefix that selects every saved device with a tag, as in `tag:loaner`.
+pub const TAG_SELECTOR_PREFIX: &str = "tag:";
+
+/// Changes made by `device annotate`. An empty owner or notes clears it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotation {
+    pub owner: Option<String>,
+    pub notes: Option<String>,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl SavedDevice {
+    /// Tags match without regard to ASCII case.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags
+            .iter()
+            .any(|saved| saved.eq_ignore_ascii_case(tag.trim()))
+    }
+
+    pub fn annotate(&mut self, annotation: &Annotation) {
+        let text =
+            |value: &String| Some(value.trim().to_string()).filter(|value| !value.is_empty());
+        if let Some(owner) = &annotation.owner {
+            self.owner = text(owner);
+        }
+        if let Some(notes) = &annotation.notes {
+            self.notes = text(notes);
+        }
+        self.tags.retain(|tag| {
+            !annotation
+                .remove_tags
+                .iter()
+                .any(|removed| removed.eq_ignore_ascii_case(tag))
+        });
+        for tag in &annotation.add_tags {
+            if !self.has_tag(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        self.tags.sort();
+    }
+}
+
+/// A tag is one word: no whitespace and no commas, which separate tags in
+/// listings.
+pub fn validate_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+        bail!("Invalid tag: {tag:?} (tags are single words without commas)");
+    }
+    Ok(tag.to_string())
+}
+
+/// What was read from a device right after connecting. Fields that could
+/// not be read are `None` and leave the saved values alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceMetadata {
+    pub product_number: Option<String>,
+    pub stick_firmware: Option<String>,
+    pub holder_product_number: Option<String>,
+    pub holder_firmware: Option<String>,
+    pub battery_level: Option<u8>,
+    pub battery_voltage: Option<f32>,
+}
+
+impl DeviceMetadata {
+    /// Read the device status and battery level. This is bookkeeping for
+    /// `device list --long`, so read failures are not reported.
+    pub async fn read(iqos: &Iqos<IqosBle>, profile: DeviceProfile) -> Self {
+        let transport = iqos.transport();
+        let battery_level = transport.read_battery_level().await.ok();
+        let Ok(status) = iqos
+            .read_device_status(profile.model, transport.device_info().clone())
+            .await
+        else {
+            return Self {
+                battery_level,
+                ..Self::default()
+            };
+        };
+
+        Self {
+            product_number: Some(status.product_number),
+            stick_firmware: Some(status.stick_firmware.to_string()),
+            holder_product_number: status.holder_product_number,
+            holder_firmware: status.holder_firmware.map(|firmware| firmware.to_string()),
+            battery_level,
+            battery_voltage: status.battery_voltage,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -51,16 +212,59 @@ impl AppConfig {
 
     pub fn load_from(path: PathBuf) -> Result<Self> {
         match fs::read_to_string(&path) {
-            Ok(contents) => toml::from_str(&contents)
+            Ok(contents) => Self::parse(&contents)
                 .with_context(|| format!("failed to parse {}", path.display())),
-            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self {
+                version: CONFIG_VERSION,
+                ..Self::default()
+            }),
             Err(error) => Err(error).with_context(|| format!("failed to read {}", path.display())),
         }
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = config_file();
-        self.save_to(path)
+    /// Parse a config document, upgrading it in memory if it is older than
+    /// [`CONFIG_VERSION`].
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut document: toml::Table = toml::from_str(contents)?;
+        migrate(&mut document)?;
+        let mut config: Self = toml::Value::Table(document).try_into()?;
+        config.version = CONFIG_VERSION;
+        Ok(config)
+    }
+
+    /// Apply `change` to config.toml as one locked read-modify-write. The
+    /// path is resolved again once the lock is held, so a write that waited
+    /// on `iqos config migrate` lands in the migrated file.
+    pub fn update<T>(change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        loop {
+            let path = config_file();
+            let lock = lock_file(&path)?;
+            if config_file() == path {
+                return Self::update_locked(path, lock, change);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn update_at<T>(path: PathBuf, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let lock = lock_file(&path)?;
+        Self::update_locked(path, lock, change)
+    }
+
+    /// Load the file at `path` afresh while `_lock` is held, apply `change`
+    /// and save the result before unlocking. Changes made by other processes
+    /// since this one last loaded the file are kept, because `change` only
+    /// edits what the caller intends to change. Nothing is saved when
+    /// `change` fails.
+    fn update_locked<T>(
+        path: PathBuf,
+        _lock: File,
+        change: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let mut config = Self::load_from(path.clone())?;
+        let value = change(&mut config)?;
+        config.save_to(path)?;
+        Ok(value)
     }
 
     pub fn save_to(&self, path: PathBuf) -> Result<()> {
@@ -69,7 +273,10 @@ impl AppConfig {
                 .with_context(|| format!("failed to create {}", parent.display()))?;
         }
 
-        let contents = toml::to_string_pretty(self)?;
+        let contents = toml::to_string_pretty(&Self {
+            version: CONFIG_VERSION,
+            ..self.clone()
+        })?;
         let tmp_path = path.with_extension("toml.tmp");
 
         let result = (|| -> Result<()> {
@@ -106,14 +313,143 @@ impl AppConfig {
         result
     }
 
+    /// Make a connected device the default. The current default label is
+    /// kept while it still names the device; otherwise the first label
+    /// saved for it is used.
     pub fn update_default(&mut self, device: &ConnectedDevice) {
+        let names_device = |label: &String| {
+            self.devices
+                .get(label)
+                .is_some_and(|saved| saved.address.eq_ignore_ascii_case(&device.address))
+        };
+        let label = self
+            .default
+            .as_ref()
+            .and_then(|default| default.label.clone())
+            .filter(names_device)
+            .or_else(|| self.label_for_address(&device.address).map(str::to_string));
         self.default = Some(DefaultDevice {
             address: device.address.clone(),
+            label,
         });
     }
 
+    /// Point the default at a saved label without connecting.
+    pub fn set_default_label(&mut self, label: &str) -> Result<()> {
+        let label = normalize_device_label(label)?;
+        let Some(saved) = self.devices.get(&label) else {
+            bail!("Device label not found: {label}");
+        };
+        self.default = Some(DefaultDevice {
+            address: saved.address.clone(),
+            label: Some(label),
+        });
+        Ok(())
+    }
+
+    /// The default device as `(label, address, serial number)`, following
+    /// the default label when it still exists.
+    pub fn default_target(&self) -> Option<(Option<&str>, &str, Option<&str>)> {
+        let default = self.default.as_ref()?;
+        let labelled = default
+            .label
+            .as_deref()
+            .and_then(|label| Some((label, self.devices.get(label)?)));
+        Some(match labelled {
+            Some((label, saved)) => (
+                Some(label),
+                saved.address.as_str(),
+                saved.serial_number.as_deref(),
+            ),
+            None => (None, default.address.as_str(), None),
+        })
+    }
+
+    /// Saved label of the default device, if it has one.
+    pub fn default_label(&self) -> Option<&str> {
+        match self.default_target()? {
+            (Some(label), _, _) => Some(label),
+            (None, address, _) => self.label_for_address(address),
+        }
+    }
+
+    /// Move a saved device to a new label, along with the default and any
+    /// schedule rules that name it. Returns how many rules were updated.
+    pub fn rename_device(&mut self, old: &str, new: &str) -> Result<usize> {
+        let old = normalize_device_label(old)?;
+        let new = validate_device_label(new)?;
+        if !self.devices.contains_key(&old) {
+            bail!("Device label not found: {old}");
+        }
+        if self.devices.contains_key(&new) {
+            bail!("Device label already exists: {new}");
+        }
+
+        let saved = self.devices.remove(&old).unwrap_or_default();
+        self.devices.insert(new.clone(), saved);
+        if let Some(default) = &mut self.default {
+            if default.label.as_deref() == Some(old.as_str()) {
+                default.label = Some(new.clone());
+            }
+        }
+        let mut rules = 0;
+        for rule in &mut self.schedule {
+            if rule.device.as_deref().map(str::trim) == Some(old.as_str()) {
+                rule.device = Some(new.clone());
+                rules += 1;
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Remove saved devices last connected before `cutoff`. Devices with no
+    /// recorded connection are kept, as their age is unknown.
+    pub fn prune_devices(&mut self, cutoff: DateTime<Local>) -> Vec<(String, SavedDevice)> {
+        let stale: Vec<String> = self
+            .devices
+            .iter()
+            .filter(|(_, saved)| saved.last_connected.is_some_and(|seen| seen < cutoff))
+            .map(|(label, _)| label.clone())
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|label| {
+                let saved = self.devices.remove(&label)?;
+                self.forget_default_label(&label);
+                Some((label, saved))
+            })
+            .collect()
+    }
+
+    fn forget_default_label(&mut self, label: &str) {
+        if let Some(default) = &mut self.default {
+            if default.label.as_deref() == Some(label) {
+                default.label = None;
+            }
+        }
+    }
+
+    /// Whether saving `label` for `device` starts a new connection history:
+    /// the label is unused, names another device or was never connected.
+    pub fn is_new_label_for(&self, label: &str, device: &ConnectedDevice) -> bool {
+        self.devices.get(label.trim()).is_none_or(|saved| {
+            !saved.address.eq_ignore_ascii_case(&device.address) || saved.connect_count == 0
+        })
+    }
+
     pub fn save_device(&mut self, label: String, device: &ConnectedDevice) -> Result<()> {
         let label = validate_device_label(&label)?;
+        // Settings written by hand survive re-saving the label, and so does
+        // the connection history when the label still names the same device.
+        let previous = self.devices.remove(&label).unwrap_or_default();
+        let saved = if previous.address.eq_ignore_ascii_case(&device.address) {
+            previous
+        } else {
+            SavedDevice {
+                budget: previous.budget,
+                ..SavedDevice::default()
+            }
+        };
 
         self.devices.insert(
             label,
@@ -122,13 +458,23 @@ impl AppConfig {
                 local_name: device.local_name.clone(),
                 model: Some(format!("{:?}", device.model)),
                 serial_number: device.serial_number.clone(),
+                ..saved
             },
         );
 
         Ok(())
     }
 
-    pub fn update_saved_device_metadata(&mut self, label: &str, device: &ConnectedDevice) {
+    /// Refresh a saved device after connecting to it: identity from the
+    /// connection, firmware and battery from `metadata`, and the connection
+    /// time and count.
+    pub fn update_saved_device_metadata(
+        &mut self,
+        label: &str,
+        device: &ConnectedDevice,
+        metadata: &DeviceMetadata,
+        connected_at: DateTime<Local>,
+    ) {
         let label = label.trim();
         if let Some(saved) = self.devices.get_mut(label) {
             saved.local_name = device
@@ -140,11 +486,79 @@ impl AppConfig {
                 .serial_number
                 .clone()
                 .or_else(|| saved.serial_number.clone());
+
+            saved.last_connected = Some(connected_at.fixed_offset());
+            saved.connect_count = saved.connect_count.saturating_add(1);
+            let metadata = metadata.clone();
+            saved.product_number = metadata.product_number.or(saved.product_number.take());
+            saved.stick_firmware = metadata.stick_firmware.or(saved.stick_firmware.take());
+            saved.holder_product_number = metadata
+                .holder_product_number
+                .or(saved.holder_product_number.take());
+            saved.holder_firmware = metadata.holder_firmware.or(saved.holder_firmware.take());
+            saved.battery_level = metadata.battery_level.or(saved.battery_level);
+            saved.battery_voltage = metadata.battery_voltage.or(saved.battery_voltage);
         }
     }
 
+    /// Every saved label whose address matches a connected device.
+    pub fn labels_for_address(&self, address: &str) -> Vec<String> {
+        self.devices
+            .iter()
+            .filter(|(_, saved)| saved.address.eq_ignore_ascii_case(address))
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    /// Expand selectors into saved labels, in the order given and without
+    /// repeats. `tag:<name>` selects every label with that tag; anything
+    /// else, optionally written `label:<name>`, must be a saved label.
+    pub fn select_labels(&self, selectors: &[String]) -> Result<Vec<String>> {
+        let mut labels: Vec<String> = Vec::new();
+        for selector in selectors {
+            let selector = selector.trim();
+            let label = strip_selector_prefix(selector, LABEL_PREFIX);
+            let selected: Vec<String> = match strip_selector_prefix(selector, TAG_SELECTOR_PREFIX) {
+                Some(tag) => {
+                    let tagged: Vec<String> = self
+                        .devices
+                        .iter()
+                        .filter(|(_, saved)| saved.has_tag(tag))
+                        .map(|(label, _)| label.clone())
+                        .collect();
+                    if tagged.is_empty() {
+                        bail!("No saved device is tagged {}", tag.trim());
+                    }
+                    tagged
+                }
+                None => {
+                    let label = label.unwrap_or(selector);
+                    if !self.devices.contains_key(label) {
+                        bail!("Device label not found: {label}");
+                    }
+                    vec![label.to_string()]
+                }
+            };
+            for label in selected {
+                if !labels.contains(&label) {
+                    labels.push(label);
+                }
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Saved label whose address matches a connected device.
+    pub fn label_for_address(&self, address: &str) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|(_, saved)| saved.address.eq_ignore_ascii_case(address))
+            .map(|(label, _)| label.as_str())
+    }
+
     pub fn remove_device(&mut self, label: &str) -> Result<bool> {
         let label = normalize_device_label(label)?;
+        self.forget_default_label(&label);
         Ok(self.devices.remove(&label).is_some())
     }
 }
@@ -152,13 +566,21 @@ impl AppConfig {
 pub fn validate_device_label(label: &str) -> Result<String> {
     let trimmed = normalize_device_label(label)?;
 
-    if is_reserved_model_label(&trimmed) {
-        bail!("Invalid label: {trimmed} is reserved for model selection");
+    if has_selector_prefix(&trimmed) {
+        bail!("Invalid label: {trimmed} starts with a selector prefix such as label: or tag:");
     }
 
     Ok(trimmed)
 }
 
+/// A note for labels that a bare `--model` value resolves to a model
+/// selector instead, such as `iluma`.
+pub fn shadowed_label_note(label: &str) -> Option<String> {
+    is_reserved_model_label(label).then(|| {
+        format!("Note: a bare {label} selects a model; use label:{label} to target this device")
+    })
+}
+
 pub fn normalize_device_label(label: &str) -> Result<String> {
     let trimmed = label.trim();
     if trimmed.is_empty() {
@@ -168,38 +590,257 @@ pub fn normalize_device_label(label: &str) -> Result<String> {
     Ok(trimmed.to_string())
 }
 
+/// Take the advisory lock guarding `path`, blocking until other writers
+/// release it. The lock is held until the returned file is dropped.
+pub fn lock_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+    let file = options
+        .open(&lock_path)
+        .with_context(|| format!("failed to open {}", lock_path.display()))?;
+    file.lock()
+        .with_context(|| format!("failed to lock {}", lock_path.display()))?;
+    Ok(file)
+}
+
+/// The stored format version of a document: the `version` key, or 0.
+fn document_version(document: &toml::Table) -> Result<u32> {
+    match document.get("version") {
+        None => Ok(0),
+        Some(toml::Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| anyhow::anyhow!("invalid config version {version}"))
+        }
+        Some(value) => bail!("invalid config version {value}"),
+    }
+}
+
+/// Upgrade `document` to [`CONFIG_VERSION`] in place. Returns the version
+/// it had before.
+fn migrate(document: &mut toml::Table) -> Result<u32> {
+    let version = document_version(document)?;
+    if version > CONFIG_VERSION {
+        bail!(
+            "config version {version} is newer than this iqos_cli supports ({CONFIG_VERSION}); upgrade iqos_cli"
+        );
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(document)
+            .with_context(|| format!("failed to migrate config from version {from}"))?;
+    }
+    document.insert(
+        "version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION.into()),
+    );
+    Ok(version)
+}
+
 pub fn config_file() -> PathBuf {
     let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
-    config_file_from(xdg_config_home, dirs::home_dir())
+    let home = dirs::home_dir();
+    let path = config_file_from(xdg_config_home, home.clone());
+    if home.is_some_and(|home| path == legacy_config_file(&home)) {
+        notify_legacy_config(&path);
+    }
+    path
 }
 
 fn config_file_from(xdg_config_home: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
+    let xdg_path = xdg_config_file(xdg_config_home, home.clone());
+    if let Some(home) = home {
+        let legacy_path = legacy_config_file(&home);
+        if xdg_path == home.join(".config").join("iqos_cli").join("config.toml")
+            && legacy_path.exists()
+            && !xdg_path.exists()
+        {
+            return legacy_path;
+        }
+    }
+
+    xdg_path
+}
+
+/// Where config.toml belongs, ignoring any legacy file.
+fn xdg_config_file(xdg_config_home: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
     if let Some(path) = xdg_config_home {
         if path.is_absolute() {
             return path.join("iqos_cli").join("config.toml");
         }
     }
 
-    if let Some(home) = home {
-        let xdg_path = home.join(".config").join("iqos_cli").join("config.toml");
-        let legacy_path = home.join(".iqos_cli").join("config.toml");
-        if legacy_path.exists() && !xdg_path.exists() {
-            return legacy_path;
+    match home {
+        Some(home) => home.join(".config").join("iqos_cli").join("config.toml"),
+        None => std::env::temp_dir().join("iqos_cli").join("config.toml"),
+    }
+}
+
+fn legacy_config_file(home: &Path) -> PathBuf {
+    home.join(".iqos_cli").join("config.toml")
+}
+
+/// Suggest `iqos config migrate`, once per run, while the legacy file is
+/// in use. Nothing is written, so read-only commands and `--dry-run` leave
+/// the legacy directory alone.
+fn notify_legacy_config(path: &Path) {
+    static NOTIFIED: Once = Once::new();
+    NOTIFIED.call_once(|| {
+        eprintln!(
+            "Notice: using the legacy config {}; run `iqos config migrate` to move it to {}",
+            path.display(),
+            xdg_config_file(
+                std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+                dirs::home_dir()
+            )
+            .display()
+        );
+    });
+}
+
+/// What [`migrate_config`] did or, under `--dry-run`, would do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Migration {
+    /// The legacy file and its state files were moved; the original
+    /// config.toml is kept as `backup`.
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+        backup: PathBuf,
+        state_files: Vec<String>,
+    },
+    /// The file was rewritten in the current format; the original is kept
+    /// as `backup`.
+    Upgraded {
+        path: PathBuf,
+        from_version: u32,
+        backup: PathBuf,
+    },
+    UpToDate(PathBuf),
+}
+
+/// Move the legacy config to the XDG location, or upgrade the current file
+/// to [`CONFIG_VERSION`]. Nothing is touched when `dry_run` is set.
+pub fn migrate_config(dry_run: bool) -> Result<Migration> {
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
+    let home = dirs::home_dir();
+    let target = xdg_config_file(xdg_config_home, home.clone());
+    let legacy = home.map(|home| legacy_config_file(&home));
+    migrate_config_at(legacy.as_deref(), &target, dry_run)
+}
+
+fn migrate_config_at(legacy: Option<&Path>, target: &Path, dry_run: bool) -> Result<Migration> {
+    // Writers still resolving to the legacy file lock it rather than
+    // `target`, so hold both until the move is done. Always legacy first.
+    let _locks = if dry_run {
+        None
+    } else {
+        let legacy_lock = legacy
+            .filter(|legacy| legacy.exists())
+            .map(lock_file)
+            .transpose()?;
+        Some((legacy_lock, lock_file(target)?))
+    };
+    if let Some(legacy) = legacy.filter(|legacy| legacy.exists() && !target.exists()) {
+        let config = AppConfig::load_from(legacy.to_path_buf())?;
+        let backup = legacy.with_extension("toml.bak");
+        let state_files: Vec<String> = STATE_FILES
+            .iter()
+            .filter(|name| {
+                legacy.with_file_name(name).exists() && !target.with_file_name(name).exists()
+            })
+            .map(|name| name.to_string())
+            .collect();
+        if !dry_run {
+            config.save_to(target.to_path_buf())?;
+            for name in &state_files {
+                move_file(&legacy.with_file_name(name), &target.with_file_name(name))?;
+            }
+            fs::rename(legacy, &backup)
+                .with_context(|| format!("failed to back up {}", legacy.display()))?;
+        }
+        return Ok(Migration::Moved {
+            from: legacy.to_path_buf(),
+            to: target.to_path_buf(),
+            backup,
+            state_files,
+        });
+    }
+
+    let path = match legacy {
+        Some(legacy) if legacy.exists() && !target.exists() => legacy,
+        _ => target,
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Migration::UpToDate(path.to_path_buf()))
         }
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", path.display()))
+        }
+    };
+    let from_version = document_version(&toml::from_str(&contents)?)?;
+    if from_version >= CONFIG_VERSION {
+        return Ok(Migration::UpToDate(path.to_path_buf()));
+    }
+
+    let config = AppConfig::parse(&contents)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let backup = path.with_extension(format!("toml.v{from_version}.bak"));
+    if !dry_run {
+        fs::copy(path, &backup).with_context(|| format!("failed to back up {}", path.display()))?;
+        config.save_to(path.to_path_buf())?;
+    }
+    Ok(Migration::Upgraded {
+        path: path.to_path_buf(),
+        from_version,
+        backup,
+    })
+}
 
-        return xdg_path;
+/// Rename, falling back to copy and remove across file systems.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
     }
+    fs::copy(from, to).with_context(|| format!("failed to copy {}", from.display()))?;
+    fs::remove_file(from).with_context(|| format!("failed to remove {}", from.display()))
+}
 
-    std::env::temp_dir().join("iqos_cli").join("config.toml")
+fn is_zero(count: &u32) -> bool {
+    *count == 0
 }
 
-pub fn print_saved_devices(config: &AppConfig) {
-    if config.devices.is_empty() {
-        println!("No saved devices");
+/// Print saved devices, only those carrying every tag in `tags` when any
+/// are given.
+pub fn print_saved_devices(config: &AppConfig, long: bool, tags: &[String]) {
+    let devices: Vec<(&String, &SavedDevice)> = config
+        .devices
+        .iter()
+        .filter(|(_, device)| tags.iter().all(|tag| device.has_tag(tag)))
+        .collect();
+    if devices.is_empty() {
+        match tags {
+            [] => println!("No saved devices"),
+            tags => println!("No saved devices tagged {}", tags.join(", ")),
+        }
+        return;
+    }
+    if long {
+        print!("{}", saved_devices_table(&devices));
         return;
     }
 
-    for (label, device) in &config.devices {
+    for (label, device) in devices {
         println!("{label}");
         println!("  address: {}", device.address);
         if let Some(local_name) = &device.local_name {
@@ -211,12 +852,162 @@ pub fn print_saved_devices(config: &AppConfig) {
         if let Some(serial_number) = &device.serial_number {
             println!("  serial_number: {serial_number}");
         }
+        if let Some(owner) = &device.owner {
+            println!("  owner: {owner}");
+        }
+        if !device.tags.is_empty() {
+            println!("  tags: {}", device.tags.join(", "));
+        }
+        if let Some(notes) = &device.notes {
+            println!("  notes: {notes}");
+        }
+    }
+}
+
+/// Everything stored for one saved device, for `device show`.
+pub fn saved_device_details(label: &str, device: &SavedDevice, is_default: bool) -> String {
+    let mut details = if is_default {
+        format!("{label} (default)\n")
+    } else {
+        format!("{label}\n")
+    };
+    let mut field = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            details.push_str(&format!("  {name}: {value}\n"));
+        }
+    };
+    field("address", Some(device.address.clone()));
+    field("local_name", device.local_name.clone());
+    field("model", device.model.clone());
+    field("serial_number", device.serial_number.clone());
+    field(
+        "last_connected",
+        Some(
+            device
+                .last_connected
+                .map(|time| {
+                    time.with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "never".to_string()),
+        ),
+    );
+    field("connect_count", Some(device.connect_count.to_string()));
+    field("product_number", device.product_number.clone());
+    field("stick_firmware", device.stick_firmware.clone());
+    field(
+        "holder_product_number",
+        device.holder_product_number.clone(),
+    );
+    field("holder_firmware", device.holder_firmware.clone());
+    field(
+        "battery_level",
+        device.battery_level.map(|level| format!("{level}%")),
+    );
+    field(
+        "battery_voltage",
+        device.battery_voltage.map(|volts| format!("{volts:.2}V")),
+    );
+    field("owner", device.owner.clone());
+    field(
+        "tags",
+        Some(device.tags.join(", ")).filter(|tags| !tags.is_empty()),
+    );
+    field("notes", device.notes.clone());
+    field(
+        "budget",
+        device.budget.as_ref().map(|budget| {
+            let period = match budget.period {
+                BudgetPeriod::Daily => "daily",
+                BudgetPeriod::Weekly => "weekly",
+            };
+            format!(
+                "{} puffs {period}, resets at {}",
+                budget.limit, budget.reset
+            )
+        }),
+    );
+    details
+}
+
+/// One row per saved device, for spotting stale devices and firmware that
+/// differs from the rest.
+fn saved_devices_table(devices: &[(&String, &SavedDevice)]) -> String {
+    const HEADERS: [&str; 11] = [
+        "LABEL",
+        "MODEL",
+        "LAST CONNECTED",
+        "COUNT",
+        "BATTERY",
+        "PRODUCT",
+        "FIRMWARE",
+        "HOLDER PRODUCT",
+        "HOLDER FIRMWARE",
+        "OWNER",
+        "TAGS",
+    ];
+    let unknown = || "-".to_string();
+    let rows: Vec<[String; 11]> = devices
+        .iter()
+        .map(|(label, device)| {
+            let battery = match (device.battery_level, device.battery_voltage) {
+                (Some(level), Some(volts)) => format!("{level}% {volts:.2}V"),
+                (Some(level), None) => format!("{level}%"),
+                (None, Some(volts)) => format!("{volts:.2}V"),
+                (None, None) => unknown(),
+            };
+            [
+                label.to_string(),
+                device.model.clone().unwrap_or_else(unknown),
+                device
+                    .last_connected
+                    .map(|time| {
+                        time.with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| "never".to_string()),
+                device.connect_count.to_string(),
+                battery,
+                device.product_number.clone().unwrap_or_else(unknown),
+                device.stick_firmware.clone().unwrap_or_else(unknown),
+                device.holder_product_number.clone().unwrap_or_else(unknown),
+                device.holder_firmware.clone().unwrap_or_else(unknown),
+                device.owner.clone().unwrap_or_else(unknown),
+                Some(device.tags.join(","))
+                    .filter(|tags| !tags.is_empty())
+                    .unwrap_or_else(unknown),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    let headers = HEADERS.map(str::to_string);
+    for row in std::iter::once(&headers).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
     }
+    table
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::unique_temp_dir;
+    use chrono::TimeZone;
     #[cfg(unix)]
     use std::os::unix::fs::PermissionsExt;
 
@@ -235,7 +1026,8 @@ mod tests {
         assert_eq!(
             config.default,
             Some(DefaultDevice {
-                address: "AA:BB:CC:DD:EE:FF".to_string()
+                address: "AA:BB:CC:DD:EE:FF".to_string(),
+                label: None,
             })
         );
     }
@@ -259,10 +1051,135 @@ mod tests {
                 local_name: Some("IQOS ILUMA i".to_string()),
                 model: Some("IlumaI".to_string()),
                 serial_number: Some("SN123".to_string()),
+                ..SavedDevice::default()
             })
         );
     }
 
+    #[test]
+    fn knows_when_a_label_is_new_for_a_device() {
+        let mut config = AppConfig::default();
+        let device = ConnectedDevice {
+            address: "AA:BB:CC:DD:EE:FF".to_string(),
+            local_name: None,
+            model: DeviceModel::IlumaI,
+            serial_number: None,
+        };
+        let other = ConnectedDevice {
+            address: "AA:BB:CC:DD:EE:01".to_string(),
+            ..device.clone()
+        };
+        assert!(config.is_new_label_for("blackcat", &device));
+
+        config.save_device("blackcat".to_string(), &device).unwrap();
+        assert!(config.is_new_label_for("blackcat", &device));
+
+        config.devices.get_mut("blackcat").unwrap().connect_count = 1;
+        assert!(!config.is_new_label_for("blackcat", &device));
+        assert!(!config.is_new_label_for(" blackcat ", &device));
+        assert!(config.is_new_label_for("blackcat", &other));
+    }
+
+    #[test]
+    fn records_connection_metadata() {
+        let mut config = AppConfig::default();
+        let device = ConnectedDevice {
+            address: "AA:BB:CC:DD:EE:FF".to_string(),
+            local_name: Some("IQOS ILUMA i".to_string()),
+            model: DeviceModel::IlumaI,
+            serial_number: Some("SN123".to_string()),
+        };
+        config.save_device("blackcat".to_string(), &device).unwrap();
+        let first = Local.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+        let second = Local.with_ymd_and_hms(2024, 5, 2, 9, 0, 0).unwrap();
+
+        config.update_saved_device_metadata(
+            "blackcat",
+            &device,
+            &DeviceMetadata {
+                product_number: Some("P123".to_string()),
+                stick_firmware: Some("v1.2.3".to_string()),
+                battery_level: Some(80),
+                battery_voltage: Some(3.9),
+                ..DeviceMetadata::default()
+            },
+            first,
+        );
+        // A failed status read keeps the firmware seen last time.
+        config.update_saved_device_metadata(
+            "blackcat",
+            &device,
+            &DeviceMetadata {
+                battery_level: Some(55),
+                ..DeviceMetadata::default()
+            },
+            second,
+        );
+
+        let saved = &config.devices["blackcat"];
+        assert_eq!(saved.connect_count, 2);
+        assert_eq!(saved.last_connected, Some(second.fixed_offset()));
+        assert_eq!(saved.stick_firmware.as_deref(), Some("v1.2.3"));
+        assert_eq!(saved.product_number.as_deref(), Some("P123"));
+        assert_eq!(saved.battery_level, Some(55));
+        assert_eq!(saved.battery_voltage, Some(3.9));
+
+        // Re-saving the label for the same device keeps its history.
+        config.save_device("blackcat".to_string(), &device).unwrap();
+        assert_eq!(config.devices["blackcat"].connect_count, 2);
+    }
+
+    #[test]
+    fn loads_devices_saved_before_metadata() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            [devices.blackcat]
+            address = "AA:BB:CC:DD:EE:FF"
+            model = "IlumaI"
+            "#,
+        )
+        .unwrap();
+
+        let saved = &config.devices["blackcat"];
+        assert_eq!(saved.connect_count, 0);
+        assert_eq!(saved.last_connected, None);
+        assert!(!toml::to_string(&config).unwrap().contains("connect_count"));
+    }
+
+    #[test]
+    fn long_list_aligns_columns() {
+        let mut config = AppConfig::default();
+        config.devices.insert(
+            "blackcat".to_string(),
+            SavedDevice {
+                address: "AA:BB:CC:DD:EE:FF".to_string(),
+                model: Some("IlumaI".to_string()),
+                connect_count: 12,
+                stick_firmware: Some("v1.2.3".to_string()),
+                battery_level: Some(80),
+                battery_voltage: Some(3.9),
+                ..SavedDevice::default()
+            },
+        );
+        config.devices.insert(
+            "old".to_string(),
+            SavedDevice {
+                address: "11:22:33:44:55:66".to_string(),
+                ..SavedDevice::default()
+            },
+        );
+
+        let devices: Vec<_> = config.devices.iter().collect();
+        let table = saved_devices_table(&devices);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("LABEL     MODEL   LAST CONNECTED  COUNT  BATTERY"));
+        assert!(lines[1].contains("v1.2.3"));
+        assert!(lines[1].contains("80% 3.90V"));
+        assert!(lines[2].starts_with("old       -       never           0"));
+    }
+
     #[test]
     fn trims_label_before_saving() {
         let mut config = AppConfig::default();
@@ -281,6 +1198,24 @@ mod tests {
         assert!(!config.devices.contains_key("  blackcat  "));
     }
 
+    #[test]
+    fn finds_label_for_connected_address() {
+        let mut config = AppConfig::default();
+        config.devices.insert(
+            "blackcat".to_string(),
+            SavedDevice {
+                address: "AA:BB:CC:DD:EE:FF".to_string(),
+                ..SavedDevice::default()
+            },
+        );
+
+        assert_eq!(
+            config.label_for_address("aa:bb:cc:dd:ee:ff"),
+            Some("blackcat")
+        );
+        assert_eq!(config.label_for_address("11:22:33:44:55:66"), None);
+    }
+
     #[test]
     fn removes_device_with_trimmed_label() {
         let mut config = AppConfig::default();
@@ -288,9 +1223,7 @@ mod tests {
             "blackcat".to_string(),
             SavedDevice {
                 address: "AA:BB:CC:DD:EE:FF".to_string(),
-                local_name: None,
-                model: None,
-                serial_number: None,
+                ..SavedDevice::default()
             },
         );
 
@@ -299,7 +1232,164 @@ mod tests {
     }
 
     #[test]
-    fn rejects_label_that_matches_model_selector() {
+    fn default_label_follows_renames_and_address_changes() {
+        let mut config = AppConfig::default();
+        let device = ConnectedDevice {
+            address: "AA:BB:CC:DD:EE:01".to_string(),
+            local_name: None,
+            model: DeviceModel::IlumaI,
+            serial_number: None,
+        };
+        config.save_device("minera".to_string(), &device).unwrap();
+        config.schedule.push(ScheduleRule {
+            name: None,
+            at: Some("07:00".to_string()),
+            days: Vec::new(),
+            cron: None,
+            command: "lock".to_string(),
+            device: Some("minera".to_string()),
+        });
+        config.set_default_label("minera").unwrap();
+
+        assert_eq!(config.rename_device("minera", "daily").unwrap(), 1);
+        assert_eq!(config.schedule[0].device.as_deref(), Some("daily"));
+        assert_eq!(
+            config.default_target(),
+            Some((Some("daily"), "AA:BB:CC:DD:EE:01", None))
+        );
+
+        config.devices.get_mut("daily").unwrap().address = "AA:BB:CC:DD:EE:02".to_string();
+        assert_eq!(
+            config.default_target(),
+            Some((Some("daily"), "AA:BB:CC:DD:EE:02", None))
+        );
+
+        config.remove_device("daily").unwrap();
+        assert_eq!(
+            config.default_target(),
+            Some((None, "AA:BB:CC:DD:EE:01", None))
+        );
+    }
+
+    #[test]
+    fn rename_refuses_taken_and_missing_labels() {
+        let mut config = AppConfig::default();
+        for label in ["minera", "blackcat"] {
+            config
+                .devices
+                .insert(label.to_string(), SavedDevice::default());
+        }
+
+        assert!(config.rename_device("minera", "blackcat").is_err());
+        assert!(config.rename_device("missing", "other").is_err());
+        assert!(config.rename_device("minera", "label:other").is_err());
+        assert!(config.devices.contains_key("minera"));
+    }
+
+    #[test]
+    fn prunes_devices_not_seen_since_cutoff() {
+        let mut config = AppConfig::default();
+        let seen = |day| {
+            Some(
+                Local
+                    .with_ymd_and_hms(2024, 5, day, 9, 0, 0)
+                    .unwrap()
+                    .fixed_offset(),
+            )
+        };
+        config.devices.insert(
+            "old".to_string(),
+            SavedDevice {
+                last_connected: seen(1),
+                ..SavedDevice::default()
+            },
+        );
+        config.devices.insert(
+            "recent".to_string(),
+            SavedDevice {
+                last_connected: seen(20),
+                ..SavedDevice::default()
+            },
+        );
+        config
+            .devices
+            .insert("never".to_string(), SavedDevice::default());
+
+        let pruned = config.prune_devices(Local.with_ymd_and_hms(2024, 5, 10, 0, 0, 0).unwrap());
+
+        assert_eq!(
+            pruned
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect::<Vec<_>>(),
+            vec!["old"]
+        );
+        assert_eq!(
+            config.devices.keys().collect::<Vec<_>>(),
+            vec!["never", "recent"]
+        );
+    }
+
+    #[test]
+    fn annotates_owner_notes_and_tags() {
+        let mut saved = SavedDevice {
+            tags: vec!["lab".to_string()],
+            ..SavedDevice::default()
+        };
+
+        saved.annotate(&Annotation {
+            owner: Some("Mika".to_string()),
+            notes: Some("holder has a cracked cap".to_string()),
+            add_tags: vec!["loaner".to_string(), "LAB".to_string()],
+            remove_tags: Vec::new(),
+        });
+        assert_eq!(saved.owner.as_deref(), Some("Mika"));
+        assert_eq!(saved.tags, vec!["lab", "loaner"]);
+        assert!(saved.has_tag("Loaner"));
+
+        saved.annotate(&Annotation {
+            notes: Some(" ".to_string()),
+            remove_tags: vec!["Lab".to_string()],
+            ..Annotation::default()
+        });
+        assert_eq!(saved.owner.as_deref(), Some("Mika"));
+        assert_eq!(saved.notes, None);
+        assert_eq!(saved.tags, vec!["loaner"]);
+
+        assert!(validate_tag("two words").is_err());
+        assert!(validate_tag("a,b").is_err());
+    }
+
+    #[test]
+    fn selects_labels_by_tag() {
+        let mut config = AppConfig::default();
+        for (label, tags) in [
+            ("a", vec!["loaner"]),
+            ("b", vec![]),
+            ("c", vec!["loaner", "lab"]),
+        ] {
+            config.devices.insert(
+                label.to_string(),
+                SavedDevice {
+                    tags: tags.into_iter().map(str::to_string).collect(),
+                    ..SavedDevice::default()
+                },
+            );
+        }
+
+        let selectors = ["b", "tag:loaner", "label:c"].map(str::to_string);
+        assert_eq!(
+            config.select_labels(&selectors).unwrap(),
+            vec!["b", "a", "c"]
+        );
+        let selectors = ["TAG:lab", "Label: b"].map(str::to_string);
+        assert_eq!(config.select_labels(&selectors).unwrap(), vec!["c", "b"]);
+        assert!(config.select_labels(&["tag:missing".to_string()]).is_err());
+        assert!(config.select_labels(&["missing".to_string()]).is_err());
+    }
+
+    #[test]
+    fn saves_label_that_matches_model_selector() {
         let mut config = AppConfig::default();
         let device = ConnectedDevice {
             address: "AA:BB:CC:DD:EE:FF".to_string(),
@@ -308,12 +1398,203 @@ mod tests {
             serial_number: Some("SN123".to_string()),
         };
 
-        let error = config
-            .save_device("iluma-i".to_string(), &device)
-            .unwrap_err();
+        config.save_device("iluma-i".to_string(), &device).unwrap();
 
-        assert!(error.to_string().contains("reserved for model selection"));
-        assert!(config.devices.is_empty());
+        assert!(config.devices.contains_key("iluma-i"));
+        assert!(shadowed_label_note("iluma-i")
+            .unwrap()
+            .contains("label:iluma-i"));
+        assert!(shadowed_label_note("minera").is_none());
+    }
+
+    #[test]
+    fn rejects_label_with_selector_prefix() {
+        for label in ["label:minera", "addr:x", "Model:iluma", "tag:work"] {
+            assert!(validate_device_label(label)
+                .unwrap_err()
+                .to_string()
+                .contains("selector prefix"));
+        }
+        assert_eq!(validate_device_label("minera:2").unwrap(), "minera:2");
+    }
+
+    #[test]
+    fn parallel_updates_keep_every_change() {
+        let temp = unique_temp_dir("parallel-updates");
+        let path = temp.join("config.toml");
+
+        let writers: Vec<_> = (0..16)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for round in 0..5 {
+                        AppConfig::update_at(path.clone(), |config| {
+                            config.devices.insert(
+                                format!("device-{writer}-{round}"),
+                                SavedDevice {
+                                    address: format!("AA:BB:CC:DD:{writer:02}:{round:02}"),
+                                    ..SavedDevice::default()
+                                },
+                            );
+                            config.update_default(&ConnectedDevice {
+                                address: format!("AA:BB:CC:DD:{writer:02}:{round:02}"),
+                                local_name: None,
+                                model: DeviceModel::IlumaI,
+                                serial_number: None,
+                            });
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let config = AppConfig::load_from(path).unwrap();
+        assert_eq!(config.devices.len(), 16 * 5);
+        assert!(config.default.is_some());
+
+        fs::remove_dir_all(temp).unwrap();
+    }
+
+    #[test]
+    fn failed_update_leaves_the_file_alone() {
+        let temp = unique_temp_dir("failed-update");
+        let path = temp.join("config.toml");
+
+        let result: Result<()> = AppConfig::update_at(path.clone(), |config| {
+            config
+                .devices
+                .insert("x".to_string(), SavedDevice::default());
+            anyhow::bail!("nope")
+        });
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+
+        fs::remove_dir_all(temp).unwrap();
+    }
+
+    #[test]
+    fn migrates_unversioned_documents() {
+        let config = AppConfig::parse(
+            r#"
+            [default]
+            address = "AA:BB:CC:DD:EE:FF"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(toml::to_string(&config)
+            .unwrap()
+            .starts_with(&format!("version = {CONFIG_VERSION}")));
+        assert!(AppConfig::parse("version = 99\n")
+            .unwrap_err()
+            .to_string()
+            .contains("newer than this iqos_cli supports"));
+    }
+
+    #[test]
+    fn migrate_moves_legacy_config_and_state_files() {
+        let temp = unique_temp_dir("migrate-legacy");
+        let legacy = temp.join(".iqos_cli").join("config.toml");
+        let target = temp.join(".config").join("iqos_cli").join("config.toml");
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "[default]\naddress = \"AA:BB:CC:DD:EE:FF\"\n").unwrap();
+        fs::write(legacy.with_file_name("audit.jsonl"), "{}\n").unwrap();
+
+        let planned = migrate_config_at(Some(&legacy), &target, true).unwrap();
+        assert!(matches!(planned, Migration::Moved { .. }));
+        assert!(!target.exists());
+
+        let migration = migrate_config_at(Some(&legacy), &target, false).unwrap();
+
+        assert_eq!(
+            migration,
+            Migration::Moved {
+                from: legacy.clone(),
+                to: target.clone(),
+                backup: legacy.with_extension("toml.bak"),
+                state_files: vec!["audit.jsonl".to_string()],
+            }
+        );
+        assert!(!legacy.exists());
+        assert!(legacy.with_extension("toml.bak").exists());
+        assert!(target.with_file_name("audit.jsonl").exists());
+        let migrated = AppConfig::load_from(target.clone()).unwrap();
+        assert_eq!(
+            migrated.default.map(|default| default.address).as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        assert!(fs::read_to_string(&target)
+            .unwrap()
+            .starts_with("version = 1"));
+        assert_eq!(config_file_from(None, Some(temp.clone())), target);
+
+        fs::remove_dir_all(temp).unwrap();
+    }
+
+    #[test]
+    fn migrate_waits_for_writers_on_the_legacy_config() {
+        let temp = unique_temp_dir("migrate-concurrent");
+        let legacy = temp.join(".iqos_cli").join("config.toml");
+        let target = temp.join(".config").join("iqos_cli").join("config.toml");
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "[default]\naddress = \"AA:BB:CC:DD:EE:FF\"\n").unwrap();
+
+        let (started, wait) = std::sync::mpsc::channel();
+        let writer = {
+            let legacy = legacy.clone();
+            std::thread::spawn(move || {
+                AppConfig::update_at(legacy, |config| {
+                    started.send(()).unwrap();
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                    config
+                        .devices
+                        .insert("desk".to_string(), SavedDevice::default());
+                    Ok(())
+                })
+                .unwrap();
+            })
+        };
+        wait.recv().unwrap();
+
+        migrate_config_at(Some(&legacy), &target, false).unwrap();
+        writer.join().unwrap();
+
+        let migrated = AppConfig::load_from(target.clone()).unwrap();
+        assert!(migrated.devices.contains_key("desk"));
+        assert!(!legacy.exists());
+
+        fs::remove_dir_all(temp).unwrap();
+    }
+
+    #[test]
+    fn migrate_upgrades_an_old_file_in_place() {
+        let temp = unique_temp_dir("migrate-version");
+        let target = temp.join("config.toml");
+        fs::write(&target, "[default]\naddress = \"AA:BB:CC:DD:EE:FF\"\n").unwrap();
+
+        let migration = migrate_config_at(None, &target, false).unwrap();
+
+        assert_eq!(
+            migration,
+            Migration::Upgraded {
+                path: target.clone(),
+                from_version: 0,
+                backup: temp.join("config.toml.v0.bak"),
+            }
+        );
+        assert_eq!(
+            migrate_config_at(None, &target, false).unwrap(),
+            Migration::UpToDate(target.clone())
+        );
+
+        fs::remove_dir_all(temp).unwrap();
     }
 
     #[test]
@@ -364,17 +1645,4 @@ mod tests {
 
         fs::remove_dir_all(temp).unwrap();
     }
-
-    fn unique_temp_dir(name: &str) -> PathBuf {
-        let path = std::env::temp_dir().join(format!(
-            "iqos_cli_{name}_{}_{}",
-            std::process::id(),
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        ));
-        fs::create_dir_all(&path).unwrap();
-        path
-    }
 }
@@ -0,0 +1,45 @@
+use anyhow::{bail, Result};
+use chrono::Duration;
+
+/// Parse a short duration such as `30m`, `12h`, `7d` or `2w`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let Some(unit) = value.chars().last() else {
+        bail!("Invalid duration: value must not be empty");
+    };
+    let amount: i64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| {
+            anyhow::anyhow!("Invalid duration: {value} (expected e.g. 30m, 12h, 7d, 2w)")
+        })?;
+
+    match unit {
+        's' => Ok(Duration::seconds(amount)),
+        'm' => Ok(Duration::minutes(amount)),
+        'h' => Ok(Duration::hours(amount)),
+        'd' => Ok(Duration::days(amount)),
+        'w' => Ok(Duration::weeks(amount)),
+        _ => bail!("Invalid duration: {value} (expected e.g. 30m, 12h, 7d, 2w)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_supported_units() {
+        assert_eq!(parse_duration("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("90d").unwrap(), Duration::days(90));
+        assert_eq!(parse_duration(" 2w ").unwrap(), Duration::weeks(2));
+    }
+
+    #[test]
+    fn rejects_missing_amount_or_unit() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("7y").is_err());
+    }
+}
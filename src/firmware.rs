@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_file;
+
+/// Firmware versions seen on one device, from a given connection onwards.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FirmwareRecord {
+    pub seen_at: DateTime<FixedOffset>,
+    pub stick_firmware: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder_firmware: Option<String>,
+}
+
+/// Firmware records per serial number, oldest first.
+pub type FirmwareHistory = BTreeMap<String, Vec<FirmwareRecord>>;
+
+/// A connection that reported different firmware than the one before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareChange {
+    pub previous: FirmwareRecord,
+    pub current: FirmwareRecord,
+}
+
+pub fn history_file() -> PathBuf {
+    config_file().with_file_name("firmware.json")
+}
+
+/// Record the firmware a device reported on connect. Returns the change
+/// when it differs from the last record; the first sighting of a serial is
+/// recorded silently. A missing holder version (no holder attached) is not
+/// a change and keeps the last known one.
+pub fn observe(
+    history: &mut FirmwareHistory,
+    serial_number: &str,
+    stick_firmware: &str,
+    holder_firmware: Option<&str>,
+    now: DateTime<Local>,
+) -> Option<FirmwareChange> {
+    let records = history.entry(serial_number.to_string()).or_default();
+    let last = records.last().cloned();
+    let current = FirmwareRecord {
+        seen_at: now.fixed_offset(),
+        stick_firmware: stick_firmware.to_string(),
+        holder_firmware: holder_firmware
+            .map(str::to_string)
+            .or_else(|| last.as_ref().and_then(|last| last.holder_firmware.clone())),
+    };
+
+    match last {
+        None => {
+            records.push(current);
+            None
+        }
+        Some(previous)
+            if previous.stick_firmware != current.stick_firmware
+                || previous.holder_firmware != current.holder_firmware =>
+        {
+            records.push(current.clone());
+            Some(FirmwareChange { previous, current })
+        }
+        Some(_) => None,
+    }
+}
+
+impl fmt::Display for FirmwareChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.previous.stick_firmware != self.current.stick_firmware {
+            parts.push(format!(
+                "stick {} -> {}",
+                self.previous.stick_firmware, self.current.stick_firmware
+            ));
+        }
+        if self.previous.holder_firmware != self.current.holder_firmware {
+            parts.push(format!(
+                "holder {} -> {}",
+                self.previous
+                    .holder_firmware
+                    .as_deref()
+                    .unwrap_or("unknown"),
+                self.current.holder_firmware.as_deref().unwrap_or("unknown")
+            ));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+pub fn print_history(label: &str, serial_number: &str, records: &[FirmwareRecord]) {
+    println!("{label} ({serial_number})");
+    if records.is_empty() {
+        println!("  No firmware recorded yet; connect to the device to record it");
+        return;
+    }
+
+    for record in records {
+        let seen_at = record
+            .seen_at
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M");
+        match &record.holder_firmware {
+            Some(holder) => println!(
+                "  {seen_at}  stick {}  holder {holder}",
+                record.stick_firmware
+            ),
+            None => println!("  {seen_at}  stick {}", record.stick_firmware),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(day: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, day, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn first_sighting_is_recorded_without_a_change() {
+        let mut history = FirmwareHistory::new();
+
+        assert_eq!(
+            observe(&mut history, "SN1", "v1.0", Some("h1.0"), local(1)),
+            None
+        );
+        assert_eq!(
+            observe(&mut history, "SN1", "v1.0", Some("h1.0"), local(2)),
+            None
+        );
+        assert_eq!(history["SN1"].len(), 1);
+    }
+
+    #[test]
+    fn reports_and_appends_firmware_updates() {
+        let mut history = FirmwareHistory::new();
+        observe(&mut history, "SN1", "v1.0", Some("h1.0"), local(1));
+
+        let change = observe(&mut history, "SN1", "v1.1", Some("h1.0"), local(2)).unwrap();
+
+        assert_eq!(change.to_string(), "stick v1.0 -> v1.1");
+        assert_eq!(history["SN1"].len(), 2);
+        assert_eq!(history["SN1"][1].seen_at, local(2).fixed_offset());
+    }
+
+    #[test]
+    fn missing_holder_is_not_a_change() {
+        let mut history = FirmwareHistory::new();
+        observe(&mut history, "SN1", "v1.0", Some("h1.0"), local(1));
+
+        assert_eq!(observe(&mut history, "SN1", "v1.0", None, local(2)), None);
+        let change = observe(&mut history, "SN1", "v1.0", Some("h2.0"), local(3)).unwrap();
+        assert_eq!(change.to_string(), "holder h1.0 -> h2.0");
+    }
+}
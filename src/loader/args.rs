@@ -0,0 +1,120 @@
+use anyhow::Result;
+use clap::error::ErrorKind;
+use clap::{Parser, ValueEnum};
+
+use crate::loader::parser::invalid_arguments;
+
+/// Boolean argument accepted by every toggle command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Switch {
+    #[value(aliases = ["enable", "true"])]
+    On,
+    #[value(aliases = ["disable", "false"])]
+    Off,
+}
+
+impl Switch {
+    pub fn enabled(self) -> bool {
+        self == Self::On
+    }
+}
+
+/// Toggle argument for settings that can also be read back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SwitchOrStatus {
+    #[default]
+    Status,
+    #[value(aliases = ["enable", "true"])]
+    On,
+    #[value(aliases = ["disable", "false"])]
+    Off,
+}
+
+impl SwitchOrStatus {
+    pub fn switch(self) -> Option<Switch> {
+        match self {
+            Self::Status => None,
+            Self::On => Some(Switch::On),
+            Self::Off => Some(Switch::Off),
+        }
+    }
+}
+
+/// Parse console arguments (`args[0]` is the command name) with clap.
+///
+/// Returns `Ok(None)` when clap already printed `--help`, so the caller
+/// should stop without touching the device.
+pub fn parse_args<T: Parser>(args: &[String]) -> Result<Option<T>> {
+    match T::try_parse_from(args) {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(error) if error.kind() == ErrorKind::DisplayHelp => {
+            error.print()?;
+            Ok(None)
+        }
+        Err(error) => Err(clap_error(&error)),
+    }
+}
+
+/// Convert a clap error into an invalid-arguments command error.
+pub fn clap_error(error: &clap::Error) -> anyhow::Error {
+    let message = error.render().to_string();
+    let message = message.trim_end();
+    invalid_arguments(message.strip_prefix("error: ").unwrap_or(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    #[command(name = "toggle")]
+    struct ToggleArgs {
+        #[arg(value_enum, ignore_case = true)]
+        state: Switch,
+    }
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| (*part).to_owned()).collect()
+    }
+
+    #[test]
+    fn accepts_every_boolean_spelling() {
+        for (value, expected) in [
+            ("on", Switch::On),
+            ("enable", Switch::On),
+            ("true", Switch::On),
+            ("off", Switch::Off),
+            ("disable", Switch::Off),
+            ("false", Switch::Off),
+            ("ON", Switch::On),
+            ("Disable", Switch::Off),
+        ] {
+            let parsed = parse_args::<ToggleArgs>(&args(&["toggle", value]))
+                .unwrap()
+                .unwrap();
+            assert_eq!(parsed.state, expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn reports_clap_errors_as_invalid_arguments() {
+        let error = parse_args::<ToggleArgs>(&args(&["toggle", "maybe"])).unwrap_err();
+        let message = error.to_string();
+
+        assert!(error
+            .downcast_ref::<crate::loader::parser::CommandError>()
+            .is_some_and(|error| matches!(
+                error,
+                crate::loader::parser::CommandError::InvalidArguments(_)
+            )));
+        assert!(message.starts_with("invalid value 'maybe'"), "{message}");
+        assert!(message.contains("[possible values: on, off]"), "{message}");
+    }
+
+    #[test]
+    fn help_is_printed_instead_of_running() {
+        assert!(parse_args::<ToggleArgs>(&args(&["toggle", "--help"]))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,45 +1,76 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceCapability, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceCapability;
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::{parse_args, SwitchOrStatus};
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "autostart",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum AutostartAction {
-    Enable,
-    Disable,
-    Status,
+/// Configure auto-start.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "autostart", after_help = console_after_help("autostart"))]
+pub struct AutostartArgs {
+    /// Show the setting, or turn it on/off (also enable/disable, true/false).
+    #[arg(value_enum, ignore_case = true, default_value_t)]
+    pub action: SwitchOrStatus,
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let action = parse_action(&args)?;
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
 
-    if !model.supports(DeviceCapability::AutoStart) {
+pub async fn run(session: Arc<Session>, args: AutostartArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    let profile = session.profile();
+    let model = profile.model;
+
+    if !profile.supports(DeviceCapability::AutoStart) {
         println!("AutoStart is not supported on this device");
         return Ok(());
     }
 
-    match action {
-        AutostartAction::Enable => {
-            iqos.set_autostart(model, true).await?;
-            println!("Autostart enabled");
-        }
-        AutostartAction::Disable => {
-            iqos.set_autostart(model, false).await?;
-            println!("Autostart disabled");
+    match args.action.switch() {
+        Some(switch) => {
+            let previous = iqos
+                .read_autostart(model)
+                .await
+                .ok()
+                .map(Setting::AutoStart);
+            if session
+                .write(
+                    &iqos,
+                    "autostart",
+                    previous,
+                    Setting::AutoStart(switch.enabled()),
+                )
+                .await?
+                .is_written()
+            {
+                println!(
+                    "Autostart {}",
+                    if switch.enabled() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
         }
-        AutostartAction::Status => {
+        None => {
             let enabled = iqos.read_autostart(model).await?;
             println!(
                 "Autostart: {}",
@@ -51,76 +82,62 @@ async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<(
     Ok(())
 }
 
-fn parse_action(args: &[String]) -> Result<AutostartAction> {
-    match args.get(1).map(String::as_str) {
-        None => Ok(AutostartAction::Status),
-        Some("status") if args.len() == 2 => Ok(AutostartAction::Status),
-        Some("on") | Some("enable") if args.len() == 2 => Ok(AutostartAction::Enable),
-        Some("off") | Some("disable") if args.len() == 2 => Ok(AutostartAction::Disable),
-        Some(_) if args.len() > 2 => Err(invalid_arguments(
-            "Usage: autostart [enable|on|disable|off|status]",
-        )),
-        Some(opt) => Err(invalid_arguments(format!(
-            "Invalid option: {opt}. Use enable/on, disable/off, or status (default)"
-        ))),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use iqos::DeviceModel;
 
-    fn args(parts: &[&str]) -> Vec<String> {
-        parts.iter().map(|s| (*s).to_owned()).collect()
+    fn parse_action(parts: &[&str]) -> Result<SwitchOrStatus> {
+        let args: Vec<String> = parts.iter().map(|s| (*s).to_owned()).collect();
+        Ok(parse_args::<AutostartArgs>(&args)?.unwrap().action)
     }
 
     #[test]
     fn parses_enable() {
         assert_eq!(
-            parse_action(&args(&["autostart", "enable"])).unwrap(),
-            AutostartAction::Enable
+            parse_action(&["autostart", "enable"]).unwrap(),
+            SwitchOrStatus::On
         );
         assert_eq!(
-            parse_action(&args(&["autostart", "on"])).unwrap(),
-            AutostartAction::Enable
+            parse_action(&["autostart", "on"]).unwrap(),
+            SwitchOrStatus::On
         );
     }
 
     #[test]
     fn parses_disable() {
         assert_eq!(
-            parse_action(&args(&["autostart", "disable"])).unwrap(),
-            AutostartAction::Disable
+            parse_action(&["autostart", "disable"]).unwrap(),
+            SwitchOrStatus::Off
         );
         assert_eq!(
-            parse_action(&args(&["autostart", "off"])).unwrap(),
-            AutostartAction::Disable
+            parse_action(&["autostart", "off"]).unwrap(),
+            SwitchOrStatus::Off
         );
     }
 
     #[test]
     fn parses_status() {
         assert_eq!(
-            parse_action(&args(&["autostart", "status"])).unwrap(),
-            AutostartAction::Status
+            parse_action(&["autostart", "status"]).unwrap(),
+            SwitchOrStatus::Status
         );
         assert_eq!(
-            parse_action(&args(&["autostart"])).unwrap(),
-            AutostartAction::Status
+            parse_action(&["autostart"]).unwrap(),
+            SwitchOrStatus::Status
         );
     }
 
     #[test]
     fn rejects_trailing_args() {
-        assert!(parse_action(&args(&["autostart", "enable", "typo"])).is_err());
-        assert!(parse_action(&args(&["autostart", "disable", "typo"])).is_err());
-        assert!(parse_action(&args(&["autostart", "status", "typo"])).is_err());
+        assert!(parse_action(&["autostart", "enable", "typo"]).is_err());
+        assert!(parse_action(&["autostart", "disable", "typo"]).is_err());
+        assert!(parse_action(&["autostart", "status", "typo"]).is_err());
     }
 
     #[test]
     fn rejects_invalid() {
-        assert!(parse_action(&args(&["autostart", "bogus"])).is_err());
+        assert!(parse_action(&["autostart", "bogus"]).is_err());
     }
 
     #[test]
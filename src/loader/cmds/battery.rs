@@ -1,20 +1,34 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
 
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
 use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "battery",
-        Box::new(|iqos, _| Box::pin(async move { execute(iqos).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>) -> Result<()> {
-    let iqos = iqos.lock().await;
+/// Display battery level.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "battery", after_help = console_after_help("battery"))]
+pub struct BatteryArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, _args: BatteryArgs) -> Result<()> {
+    let iqos = session.iqos().await;
     let level = iqos.transport().read_battery_level().await?;
     println!("Battery: {level}%");
     Ok(())
@@ -1,36 +1,72 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{BrightnessLevel, DeviceCapability, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::{Parser, ValueEnum};
+use iqos::{BrightnessLevel, DeviceCapability};
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "brightness",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let iqos = iqos.lock().await;
+/// Set display brightness.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "brightness", after_help = console_after_help("brightness"))]
+pub struct BrightnessArgs {
+    /// New brightness level; shows the current level when omitted.
+    #[arg(value_enum, ignore_case = true)]
+    pub level: Option<BrightnessArg>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BrightnessArg {
+    High,
+    Low,
+}
+
+impl From<BrightnessArg> for BrightnessLevel {
+    fn from(value: BrightnessArg) -> Self {
+        match value {
+            BrightnessArg::High => Self::High,
+            BrightnessArg::Low => Self::Low,
+        }
+    }
+}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, args: BrightnessArgs) -> Result<()> {
+    let iqos = session.iqos().await;
 
-    if !iqos
-        .transport()
-        .model()
-        .supports(DeviceCapability::Brightness)
-    {
+    if !session.profile().supports(DeviceCapability::Brightness) {
         println!("Brightness not supported on this device");
         return Ok(());
     }
 
-    match args.get(1).map(|s| s.parse::<BrightnessLevel>()) {
-        Some(Ok(level)) => {
-            iqos.set_brightness(level).await?;
-            println!("Brightness set to {level}");
+    match args.level.map(BrightnessLevel::from) {
+        Some(level) => {
+            let previous = iqos.read_brightness().await.ok().map(Setting::Brightness);
+            if session
+                .write(&iqos, "brightness", previous, Setting::Brightness(level))
+                .await?
+                .is_written()
+            {
+                println!("Brightness set to {level}");
+            }
         }
-        Some(Err(e)) => return Err(invalid_arguments(e.to_string())),
         None => {
             let level = iqos.read_brightness().await?;
             println!("Brightness: {level}");
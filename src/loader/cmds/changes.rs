@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+
+pub fn register_command(console: &mut IQOSConsole) {
+    console.register_command(
+        "changes",
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
+    );
+}
+
+/// List the settings changed in this session.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "changes", after_help = console_after_help("changes"))]
+pub struct ChangesArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    if let Some(ChangesArgs {}) = parse_args(&args)? {
+        print_changes(&session);
+    }
+    Ok(())
+}
+
+pub fn print_changes(session: &Session) {
+    let changes = session.changes();
+    if changes.is_empty() {
+        println!("No changes in this session");
+        return;
+    }
+
+    for (index, change) in changes.iter().enumerate() {
+        println!("  {}. {change}", index + 1);
+    }
+}
@@ -4,14 +4,11 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
-use tokio::sync::Mutex;
+
+use crate::loader::session::Session;
 
 pub type CommandFn = Box<
-    dyn Fn(
-            Arc<Mutex<Iqos<IqosBle>>>,
-            Vec<String>,
-        ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
+    dyn Fn(Arc<Session>, Vec<String>) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
         + Send
         + Sync,
 >;
@@ -1,55 +1,608 @@
-use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::{Parser, Subcommand};
+
+use crate::audit::{self, AuditRecord, AuditTarget};
 use crate::config::{
-    normalize_device_label, print_saved_devices, validate_device_label, AppConfig, ConnectedDevice,
+    normalize_device_label, print_saved_devices, saved_device_details, shadowed_label_note,
+    validate_device_label, validate_tag, Annotation, AppConfig, DeviceMetadata, SavedDevice,
 };
+use crate::duration::parse_duration;
+use crate::firmware;
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
 use crate::loader::parser::invalid_arguments;
+use crate::loader::session::Session;
+use crate::registry::{self, ImportMode, ImportReport};
+use crate::state;
+
+/// Manage saved devices.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(
+    name = "device",
+    disable_help_subcommand = true,
+    after_help = console_after_help("device")
+)]
+pub struct DeviceArgs {
+    #[command(subcommand)]
+    pub command: DeviceCommand,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum DeviceCommand {
+    /// Save the currently targeted device under a label.
+    Save {
+        #[arg(value_name = "label")]
+        label: String,
+    },
+    /// List saved device labels.
+    List {
+        /// Show a table with last connection, battery and firmware.
+        #[arg(long)]
+        long: bool,
+        /// Only list devices with this tag; repeat to require several.
+        #[arg(long = "tag", value_name = "tag")]
+        tags: Vec<String>,
+    },
+    /// Set the owner, notes or tags of a saved device.
+    #[command(arg_required_else_help = true)]
+    Annotate {
+        #[arg(value_name = "label")]
+        label: String,
+        /// Who has the device; an empty value clears it.
+        #[arg(long, value_name = "name")]
+        owner: Option<String>,
+        /// Free-form notes; an empty value clears them.
+        #[arg(long, value_name = "text")]
+        notes: Option<String>,
+        /// Add a tag; may be repeated.
+        #[arg(long = "tag", value_name = "tag")]
+        tags: Vec<String>,
+        /// Remove a tag; may be repeated.
+        #[arg(long = "untag", value_name = "tag")]
+        untags: Vec<String>,
+    },
+    /// Remove a saved device label.
+    Remove {
+        #[arg(value_name = "label")]
+        label: String,
+    },
+    /// Show the firmware versions seen on a saved device.
+    Firmware {
+        #[arg(value_name = "label")]
+        label: String,
+    },
+    /// Give a saved device a new label.
+    Rename {
+        #[arg(value_name = "old")]
+        old: String,
+        #[arg(value_name = "new")]
+        new: String,
+    },
+    /// Make a saved label the default device without connecting.
+    SetDefault {
+        #[arg(value_name = "label")]
+        label: String,
+    },
+    /// Show everything stored for a saved device.
+    Show {
+        #[arg(value_name = "label")]
+        label: String,
+    },
+    /// Remove saved devices not connected for a while.
+    Prune {
+        /// Age of the last connection, e.g. `90d` or `12w`.
+        #[arg(long, value_name = "duration")]
+        older_than: String,
+    },
+    /// Print saved labels as a TOML file others can import.
+    Export {
+        /// Labels or `tag:<name>` selectors; everything when none are given.
+        #[arg(value_name = "label")]
+        labels: Vec<String>,
+        /// Leave serial numbers out of the file.
+        #[arg(long)]
+        no_serial: bool,
+    },
+    /// Add saved labels from a file written by `device export`.
+    Import {
+        #[arg(value_name = "file")]
+        file: PathBuf,
+        /// Keep the saved labels and add the imported ones (default).
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Replace every saved label with the imported ones.
+        #[arg(long)]
+        replace: bool,
+        /// Put this in front of every imported label, e.g. `team-`.
+        #[arg(long, value_name = "prefix")]
+        prefix: Option<String>,
+        /// Do not import serial numbers.
+        #[arg(long)]
+        no_serial: bool,
+    },
+}
+
+pub async fn execute(args: Vec<String>, session: &Session) -> Result<()> {
+    let Some(args) = parse_args::<DeviceArgs>(&args)? else {
+        return Ok(());
+    };
 
-pub async fn execute(args: Vec<String>, connected_device: Option<&ConnectedDevice>) -> Result<()> {
-    match args.get(1).map(String::as_str) {
-        Some("list") if args.len() == 2 => list_devices(),
-        Some("list") => Err(invalid_arguments("Usage: device list")),
-        Some("save") if args.len() == 3 => save_device(&args[2], connected_device),
-        Some("save") => Err(invalid_arguments("Usage: device save <label>")),
-        Some("remove") if args.len() == 3 => remove_device(&args[2]),
-        Some("remove") => Err(invalid_arguments("Usage: device remove <label>")),
-        Some(subcommand) => Err(invalid_arguments(format!(
-            "Invalid option: {subcommand}. Use list/save/remove"
-        ))),
-        None => Err(invalid_arguments("Usage: device [list|save|remove]")),
+    let dry_run = session.options().dry_run;
+    match args.command {
+        DeviceCommand::List { long, tags } => list_devices(long, &tags),
+        DeviceCommand::Annotate {
+            label,
+            owner,
+            notes,
+            tags,
+            untags,
+        } => annotate_device(
+            &label,
+            &Annotation {
+                owner,
+                notes,
+                add_tags: tags,
+                remove_tags: untags,
+            },
+            dry_run,
+        ),
+        DeviceCommand::Save { label } => save_device(&label, session, dry_run).await,
+        DeviceCommand::Remove { label } => remove_device(&label, dry_run),
+        DeviceCommand::Firmware { label } => show_firmware(&label),
+        DeviceCommand::Rename { old, new } => rename_device(&old, &new, dry_run),
+        DeviceCommand::SetDefault { label } => set_default_device(&label, dry_run),
+        DeviceCommand::Show { label } => show_device(&label),
+        DeviceCommand::Prune { older_than } => prune_devices(&older_than, dry_run),
+        DeviceCommand::Export { labels, no_serial } => export_devices(&labels, !no_serial),
+        DeviceCommand::Import {
+            file,
+            replace,
+            prefix,
+            no_serial,
+            ..
+        } => import_devices(
+            &file,
+            import_mode(replace),
+            prefix.as_deref(),
+            !no_serial,
+            dry_run,
+        ),
     }
 }
 
-fn list_devices() -> Result<()> {
+fn list_devices(long: bool, tags: &[String]) -> Result<()> {
+    let config = AppConfig::load()?;
+    print_saved_devices(&config, long, tags);
+    Ok(())
+}
+
+pub fn annotate_device(label: &str, annotation: &Annotation, dry_run: bool) -> Result<()> {
+    let label =
+        normalize_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
+    if annotation.is_empty() {
+        return Err(invalid_arguments(
+            "Usage: device annotate <label> [--owner <name>] [--notes <text>] [--tag <tag>]... [--untag <tag>]...",
+        ));
+    }
+    let annotation = Annotation {
+        add_tags: validate_tags(&annotation.add_tags)?,
+        remove_tags: validate_tags(&annotation.remove_tags)?,
+        ..annotation.clone()
+    };
+
     let config = AppConfig::load()?;
-    print_saved_devices(&config);
+    let Some(saved) = config.devices.get(&label) else {
+        bail!("Device label not found: {label}");
+    };
+    let saved = if dry_run {
+        let mut annotated = saved.clone();
+        annotated.annotate(&annotation);
+        annotated
+    } else {
+        AppConfig::update(|config| {
+            let Some(saved) = config.devices.get_mut(&label) else {
+                bail!("Device label not found: {label}");
+            };
+            saved.annotate(&annotation);
+            Ok(saved.clone())
+        })?
+    };
+
+    if dry_run {
+        println!("Dry run: {label} would be annotated as");
+    }
+    println!("{label}");
+    println!("  owner: {}", saved.owner.as_deref().unwrap_or("-"));
+    println!(
+        "  tags: {}",
+        Some(saved.tags.join(", "))
+            .filter(|tags| !tags.is_empty())
+            .unwrap_or_else(|| "-".to_string())
+    );
+    println!("  notes: {}", saved.notes.as_deref().unwrap_or("-"));
     Ok(())
 }
 
-fn save_device(label: &str, connected_device: Option<&ConnectedDevice>) -> Result<()> {
-    let Some(device) = connected_device else {
+fn validate_tags(tags: &[String]) -> Result<Vec<String>> {
+    tags.iter()
+        .map(|tag| validate_tag(tag).map_err(|error| invalid_arguments(error.to_string())))
+        .collect()
+}
+
+async fn save_device(label: &str, session: &Session, dry_run: bool) -> Result<()> {
+    let Some(device) = session.device() else {
         bail!("No connected device metadata available");
     };
 
-    let mut config = AppConfig::load()?;
+    let config = AppConfig::load()?;
     let label =
         validate_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
-    config.save_device(label.clone(), device)?;
-    config.update_default(device);
-    config.save()?;
+    let previous = config
+        .devices
+        .get(&label)
+        .map(|saved| saved.address.clone());
+    if dry_run {
+        println!(
+            "Dry run: would save {label} as {} (currently {})",
+            device.address,
+            previous.as_deref().unwrap_or("unset")
+        );
+        return Ok(());
+    }
+    // A label that is new for this device counts the current connection.
+    // Its metadata is read before taking the config lock, so other writers
+    // are not held up by the device.
+    let metadata = if config.is_new_label_for(&label, device) {
+        Some(DeviceMetadata::read(&*session.iqos().await, session.profile()).await)
+    } else {
+        None
+    };
+    let result = AppConfig::update(|config| {
+        config.save_device(label.clone(), device)?;
+        if let Some(metadata) = &metadata {
+            if config.devices[&label].connect_count == 0 {
+                config.update_saved_device_metadata(&label, device, metadata, chrono::Local::now());
+            }
+        }
+        config.update_default(device);
+        Ok(())
+    });
+    record_label_change(
+        "device save",
+        &label,
+        device.serial_number.clone(),
+        previous,
+        Some(device.address.clone()),
+        &result,
+    );
+    result?;
     println!("Saved device label: {label}");
+    if let Some(note) = shadowed_label_note(&label) {
+        println!("{note}");
+    }
     Ok(())
 }
 
-fn remove_device(label: &str) -> Result<()> {
+fn remove_device(label: &str, dry_run: bool) -> Result<()> {
+    let config = AppConfig::load()?;
+    let label =
+        normalize_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
+    let Some(removed) = config.devices.get(&label).cloned() else {
+        bail!("Device label not found: {label}");
+    };
+    if dry_run {
+        println!("Dry run: would remove {label} ({})", removed.address);
+        return Ok(());
+    }
+    let result = AppConfig::update(|config| {
+        if !config.remove_device(&label)? {
+            bail!("Device label not found: {label}");
+        }
+        Ok(())
+    });
+    record_label_change(
+        "device remove",
+        &label,
+        removed.serial_number,
+        Some(removed.address),
+        None,
+        &result,
+    );
+    result?;
+    println!("Removed device label: {label}");
+    Ok(())
+}
+
+fn show_firmware(label: &str) -> Result<()> {
+    let config = AppConfig::load()?;
+    let label =
+        normalize_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
+    let Some(saved) = config.devices.get(&label) else {
+        bail!("Device label not found: {label}");
+    };
+    print_firmware_history(&label, saved)
+}
+
+pub fn rename_device(old: &str, new: &str, dry_run: bool) -> Result<()> {
+    let config = AppConfig::load()?;
+    let old = normalize_device_label(old).map_err(|error| invalid_arguments(error.to_string()))?;
+    let new = validate_device_label(new).map_err(|error| invalid_arguments(error.to_string()))?;
+    let Some(saved) = config.devices.get(&old).cloned() else {
+        bail!("Device label not found: {old}");
+    };
+    if config.devices.contains_key(&new) {
+        return Err(invalid_arguments(format!(
+            "Device label already exists: {new}"
+        )));
+    }
+    if dry_run {
+        println!("Dry run: would rename {old} to {new}");
+        return Ok(());
+    }
+
+    // The old label goes in the command, so the recorded values stay
+    // addresses like every other label change.
+    let result = AppConfig::update(|config| config.rename_device(&old, &new));
+    record_label_change(
+        &format!("device rename {old}"),
+        &new,
+        saved.serial_number,
+        Some(saved.address.clone()),
+        Some(saved.address.clone()),
+        &result
+            .as_ref()
+            .map(|_| ())
+            .map_err(|error| anyhow!("{error:#}")),
+    );
+    let rules = result?;
+    println!("Renamed {old} to {new}");
+    if let Some(note) = shadowed_label_note(&new) {
+        println!("{note}");
+    }
+    if rules > 0 {
+        println!("Updated {rules} schedule rule(s) to use {new}");
+    }
+    Ok(())
+}
+
+pub fn set_default_device(label: &str, dry_run: bool) -> Result<()> {
     let mut config = AppConfig::load()?;
     let label =
         normalize_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
-    if !config.remove_device(&label)? {
+    let previous = config.default_label().map(str::to_string);
+    config.set_default_label(&label)?;
+    if dry_run {
+        println!(
+            "Dry run: would make {label} the default (currently {})",
+            previous.as_deref().unwrap_or("unset")
+        );
+        return Ok(());
+    }
+    AppConfig::update(|config| config.set_default_label(&label))?;
+    println!("Default device: {label}");
+    Ok(())
+}
+
+pub fn show_device(label: &str) -> Result<()> {
+    let config = AppConfig::load()?;
+    let label =
+        normalize_device_label(label).map_err(|error| invalid_arguments(error.to_string()))?;
+    let Some(saved) = config.devices.get(&label) else {
         bail!("Device label not found: {label}");
+    };
+    print!(
+        "{}",
+        saved_device_details(
+            &label,
+            saved,
+            config.default_label() == Some(label.as_str())
+        )
+    );
+    Ok(())
+}
+
+pub fn prune_devices(older_than: &str, dry_run: bool) -> Result<()> {
+    let age = parse_duration(older_than)
+        .ok()
+        .filter(|age| *age > chrono::Duration::zero())
+        .ok_or_else(|| {
+            invalid_arguments(format!(
+                "Invalid --older-than: {older_than} (expected e.g. 90d, 12w)"
+            ))
+        })?;
+    let cutoff = chrono::Local::now() - age;
+
+    let mut config = AppConfig::load()?;
+    let never_seen = config
+        .devices
+        .values()
+        .filter(|saved| saved.last_connected.is_none())
+        .count();
+    let pruned = if dry_run {
+        config.prune_devices(cutoff)
+    } else {
+        AppConfig::update(|config| Ok(config.prune_devices(cutoff)))?
+    };
+
+    let verb = if dry_run {
+        "Dry run: would remove"
+    } else {
+        "Removed"
+    };
+    for (label, saved) in &pruned {
+        let last_connected = saved
+            .last_connected
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        println!(
+            "{verb} {label} ({}, last connected {last_connected})",
+            saved.address
+        );
+        let rules = config
+            .schedule
+            .iter()
+            .filter(|rule| rule.device.as_deref().map(str::trim) == Some(label.as_str()))
+            .count();
+        if rules > 0 {
+            eprintln!("Warning: {rules} schedule rule(s) still use {label}");
+        }
+        if !dry_run {
+            record_label_change(
+                "device prune",
+                label,
+                saved.serial_number.clone(),
+                Some(saved.address.clone()),
+                None,
+                &Ok(()),
+            );
+        }
+    }
+    if pruned.is_empty() {
+        println!("No saved devices last connected more than {older_than} ago");
     }
+    if never_seen > 0 {
+        println!("Kept {never_seen} device(s) with no recorded connection");
+    }
+    Ok(())
+}
 
-    config.save()?;
-    println!("Removed device label: {label}");
+pub fn import_mode(replace: bool) -> ImportMode {
+    if replace {
+        ImportMode::Replace
+    } else {
+        ImportMode::Merge
+    }
+}
+
+pub fn export_devices(labels: &[String], include_serial: bool) -> Result<()> {
+    let config = AppConfig::load()?;
+    let labels = config.select_labels(labels)?;
+    print!("{}", registry::export(&config, &labels, include_serial)?);
     Ok(())
 }
+
+/// Import a registry file into config.toml and report each label. The file
+/// is checked as a whole first, so an invalid label changes nothing.
+pub fn import_devices(
+    file: &Path,
+    mode: ImportMode,
+    prefix: Option<&str>,
+    include_serial: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let imported = registry::parse(&contents, prefix, include_serial)
+        .map_err(|error| invalid_arguments(format!("{error:#}")))?;
+
+    if dry_run {
+        let mut config = AppConfig::load()?;
+        let previous = config.devices.clone();
+        let report = registry::import(&mut config, imported, mode);
+        print_import_report(&report, &previous, &config.devices, true);
+        return Ok(());
+    }
+
+    let (report, previous, devices) = AppConfig::update(|config| {
+        let previous = config.devices.clone();
+        let report = registry::import(config, imported, mode);
+        Ok((report, previous, config.devices.clone()))
+    })?;
+    for label in report.added.iter().chain(&report.updated) {
+        record_label_change(
+            "device import",
+            label,
+            devices[label].serial_number.clone(),
+            previous.get(label).map(|saved| saved.address.clone()),
+            Some(devices[label].address.clone()),
+            &Ok(()),
+        );
+    }
+    for label in &report.removed {
+        record_label_change(
+            "device import",
+            label,
+            previous[label].serial_number.clone(),
+            Some(previous[label].address.clone()),
+            None,
+            &Ok(()),
+        );
+    }
+    print_import_report(&report, &previous, &devices, false);
+    Ok(())
+}
+
+fn print_import_report(
+    report: &ImportReport,
+    previous: &BTreeMap<String, SavedDevice>,
+    devices: &BTreeMap<String, SavedDevice>,
+    dry_run: bool,
+) {
+    let (added, updated, removed) = if dry_run {
+        (
+            "Dry run: would add",
+            "Dry run: would update",
+            "Dry run: would remove",
+        )
+    } else {
+        ("Added", "Updated", "Removed")
+    };
+    for label in &report.added {
+        println!("{added} {label} ({})", devices[label].address);
+    }
+    for label in &report.updated {
+        println!("{updated} {label} ({})", devices[label].address);
+    }
+    for label in &report.removed {
+        println!("{removed} {label} ({})", previous[label].address);
+    }
+    for conflict in &report.conflicts {
+        eprintln!("Skipped {conflict}");
+    }
+    for note in report.shadowed_label_notes() {
+        println!("{note}");
+    }
+    println!(
+        "{} added, {} updated, {} unchanged, {} removed, {} skipped",
+        report.added.len(),
+        report.updated.len(),
+        report.unchanged.len(),
+        report.removed.len(),
+        report.conflicts.len()
+    );
+}
+
+/// Print the firmware history of a saved device, found by its serial number.
+pub fn print_firmware_history(label: &str, saved: &SavedDevice) -> Result<()> {
+    let Some(serial_number) = &saved.serial_number else {
+        bail!("No serial number saved for {label}; reconnect to it to record one");
+    };
+    let history: firmware::FirmwareHistory = state::load_json(&firmware::history_file())?;
+    let records = history.get(serial_number).map(Vec::as_slice).unwrap_or(&[]);
+    firmware::print_history(label, serial_number, records);
+    Ok(())
+}
+
+/// Audit a change to a saved label; the recorded values are device addresses.
+pub fn record_label_change(
+    command: &str,
+    label: &str,
+    serial_number: Option<String>,
+    previous: Option<String>,
+    new: Option<String>,
+    result: &Result<()>,
+) {
+    let target = AuditTarget {
+        label: Some(label.to_string()),
+        serial_number,
+    };
+    audit::record(AuditRecord::new(&target, command, previous, new, result));
+}
@@ -1,20 +1,34 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
 
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
 use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "diagnosis",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, _args: Vec<String>) -> Result<()> {
-    let iqos = iqos.lock().await;
+/// Retrieve telemetry data.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "diagnosis", after_help = console_after_help("diagnosis"))]
+pub struct DiagnosisArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, _args: DiagnosisArgs) -> Result<()> {
+    let iqos = session.iqos().await;
     let data = iqos.read_diagnosis().await?;
     println!("Diagnosis:");
     if let Some(count) = data.total_smoking_count {
@@ -1,24 +1,38 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
+use clap::Parser;
 use rustyline::DefaultEditor;
-use tokio::sync::Mutex;
 
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
 use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "findmyiqos",
-        Box::new(|iqos, _| Box::pin(async move { execute(iqos).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>) -> Result<()> {
+/// Activate find-my-device vibration.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "findmyiqos", after_help = console_after_help("findmyiqos"))]
+pub struct FindMyIqosArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, _args: FindMyIqosArgs) -> Result<()> {
     println!("Starting Find My IQOS...");
 
     {
-        let iqos = iqos.lock().await;
+        let iqos = session.iqos().await;
         iqos.find_my_iqos_start().await?;
     }
 
@@ -29,7 +43,7 @@ async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>) -> Result<()> {
     });
 
     let stop_result = {
-        let iqos = iqos.lock().await;
+        let iqos = session.iqos().await;
         iqos.find_my_iqos_stop().await
     };
 
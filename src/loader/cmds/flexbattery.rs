@@ -1,29 +1,80 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceCapability, FlexBatteryMode, FlexBatterySettings, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use iqos::{DeviceCapability, FlexBatteryMode, FlexBatterySettings};
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::{clap_error, parse_args, Switch};
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "flexbattery",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+/// Configure FlexBattery.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "flexbattery", after_help = console_after_help("flexbattery"))]
+pub struct FlexBatteryArgs {
+    /// Mode to switch to, or `pause` and a state; shows the current settings when omitted.
+    #[arg(value_enum, ignore_case = true)]
+    pub action: Option<FlexBatteryAction>,
+    /// With `pause`: on/off (also enable/disable, true/false).
+    #[arg(value_enum, ignore_case = true, required_if_eq("action", "pause"))]
+    pub state: Option<Switch>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FlexBatteryAction {
+    /// Switch to Performance mode.
+    Performance,
+    /// Switch to Eco mode.
+    Eco,
+    /// Turn pause mode on/off.
+    Pause,
+}
+
+impl FlexBatteryArgs {
+    /// Only `pause` takes a state.
+    pub fn validate(&self) -> Result<()> {
+        match (self.action, self.state) {
+            (Some(action), Some(state)) if action != FlexBatteryAction::Pause => {
+                let state = state.to_possible_value().expect("no skipped values");
+                Err(clap_error(&Self::command().error(
+                    ErrorKind::UnknownArgument,
+                    format!("unexpected argument '{}' found", state.get_name()),
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, args: FlexBatteryArgs) -> Result<()> {
+    args.validate()?;
+    let iqos = session.iqos().await;
+    let profile = session.profile();
+    let model = profile.model;
 
-    if !model.supports(DeviceCapability::FlexBattery) {
+    if !profile.supports(DeviceCapability::FlexBattery) {
         println!("FlexBattery is only available on ILUMA i and ILUMA i PRIME devices");
         return Ok(());
     }
 
-    let cmd = args.get(1).map(|s| s.to_ascii_lowercase());
-    let settings = match cmd.as_deref() {
+    let (previous, settings) = match args.action {
         None => {
             let s = iqos.read_flexbattery(model).await?;
             println!(
@@ -33,71 +84,116 @@ async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<(
             );
             return Ok(());
         }
-        Some("pause") => {
-            if args.len() != 3 {
-                return Err(invalid_arguments("Usage: flexbattery pause [on|off]"));
-            }
-            let value = args.get(2).map(|s| s.to_ascii_lowercase());
-            let pause = parse_on_off(value.as_deref())?;
+        Some(FlexBatteryAction::Pause) => {
+            let enabled = args.state.is_some_and(Switch::enabled);
             let current = iqos.read_flexbattery(model).await?;
-            FlexBatterySettings::new(current.mode(), pause)
-        }
-        Some("performance") => {
-            if args.len() != 2 {
-                return Err(invalid_arguments("Usage: flexbattery performance"));
-            }
-            FlexBatterySettings::new(FlexBatteryMode::Performance, None)
-        }
-        Some("eco") => {
-            if args.len() != 2 {
-                return Err(invalid_arguments("Usage: flexbattery eco"));
-            }
-            FlexBatterySettings::new(FlexBatteryMode::Eco, None)
-        }
-        Some(s) => {
-            return Err(invalid_arguments(format!(
-                "Invalid option: {s}. Use performance/eco/pause [on|off]"
-            )));
+            (
+                Some(current),
+                FlexBatterySettings::new(current.mode(), Some(enabled)),
+            )
         }
+        Some(FlexBatteryAction::Performance) => (
+            iqos.read_flexbattery(model).await.ok(),
+            FlexBatterySettings::new(FlexBatteryMode::Performance, None),
+        ),
+        Some(FlexBatteryAction::Eco) => (
+            iqos.read_flexbattery(model).await.ok(),
+            FlexBatterySettings::new(FlexBatteryMode::Eco, None),
+        ),
     };
 
-    iqos.set_flexbattery(model, settings).await?;
-    println!("FlexBattery settings updated");
-    Ok(())
-}
-
-fn parse_on_off(value: Option<&str>) -> Result<Option<bool>> {
-    match value {
-        Some("on") => Ok(Some(true)),
-        Some("off") => Ok(Some(false)),
-        None => Ok(None),
-        Some(s) => Err(invalid_arguments(format!(
-            "Invalid pause value: {s}. Use on/off"
-        ))),
+    if session
+        .write(
+            &iqos,
+            "flexbattery",
+            previous.map(Setting::FlexBattery),
+            Setting::FlexBattery(settings),
+        )
+        .await?
+        .is_written()
+    {
+        println!("FlexBattery settings updated");
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn parse(parts: &[&str]) -> Result<FlexBatteryArgs> {
+        let args: Vec<String> = parts.iter().map(|part| (*part).to_owned()).collect();
+        let args = parse_args::<FlexBatteryArgs>(&args)?.unwrap();
+        args.validate()?;
+        Ok(args)
+    }
+
+    fn pause(state: Switch) -> FlexBatteryArgs {
+        FlexBatteryArgs {
+            action: Some(FlexBatteryAction::Pause),
+            state: Some(state),
+        }
+    }
+
     #[test]
     fn pause_on_parsed() {
-        assert_eq!(parse_on_off(Some("on")).unwrap(), Some(true));
+        assert_eq!(
+            parse(&["flexbattery", "pause", "on"]).unwrap(),
+            pause(Switch::On)
+        );
     }
 
     #[test]
     fn pause_off_parsed() {
-        assert_eq!(parse_on_off(Some("off")).unwrap(), Some(false));
+        assert_eq!(
+            parse(&["flexbattery", "pause", "false"]).unwrap(),
+            pause(Switch::Off)
+        );
     }
 
     #[test]
-    fn pause_absent_is_none() {
-        assert_eq!(parse_on_off(None).unwrap(), None);
+    fn pause_requires_value() {
+        assert!(parse(&["flexbattery", "pause"]).is_err());
     }
 
     #[test]
     fn pause_invalid_returns_err() {
-        assert!(parse_on_off(Some("yes")).is_err());
+        assert!(parse(&["flexbattery", "pause", "yes"]).is_err());
+    }
+
+    #[test]
+    fn no_action_reads_settings() {
+        assert_eq!(parse(&["flexbattery"]).unwrap().action, None);
+    }
+
+    #[test]
+    fn rejects_mode_with_trailing_args() {
+        assert!(parse(&["flexbattery", "eco", "now"]).is_err());
+        let error = parse(&["flexbattery", "eco", "on"]).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("unexpected argument 'on' found"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn modes_and_states_ignore_case() {
+        assert_eq!(
+            parse(&["flexbattery", "ECO"]).unwrap().action,
+            Some(FlexBatteryAction::Eco)
+        );
+        assert_eq!(
+            parse(&["flexbattery", "Pause", "ON"]).unwrap(),
+            pause(Switch::On)
+        );
+    }
+
+    #[test]
+    fn suggests_close_mode() {
+        let error = parse(&["flexbattery", "ecco"]).unwrap_err();
+
+        assert!(error.to_string().contains("'eco'"), "{error}");
     }
 }
@@ -1,46 +1,76 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceCapability, FlexPuffSetting, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceCapability;
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::{parse_args, SwitchOrStatus};
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "flexpuff",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum FlexPuffAction {
-    Enable,
-    Disable,
-    Status,
+/// Configure FlexPuff.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "flexpuff", after_help = console_after_help("flexpuff"))]
+pub struct FlexPuffArgs {
+    /// Show the setting, or turn it on/off (also enable/disable, true/false).
+    #[arg(value_enum, ignore_case = true, default_value_t)]
+    pub action: SwitchOrStatus,
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let action = parse_action(&args)?;
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, args: FlexPuffArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    let profile = session.profile();
+    let model = profile.model;
 
-    if !model.supports(DeviceCapability::FlexPuff) {
+    if !profile.supports(DeviceCapability::FlexPuff) {
         println!("FlexPuff is not supported on this device");
         return Ok(());
     }
 
-    match action {
-        FlexPuffAction::Enable => {
-            iqos.set_flexpuff(model, FlexPuffSetting::new(true)).await?;
-            println!("FlexPuff enabled");
+    match args.action.switch() {
+        Some(switch) => {
+            let previous = iqos
+                .read_flexpuff(model)
+                .await
+                .ok()
+                .map(|setting| Setting::FlexPuff(setting.is_enabled()));
+            if session
+                .write(
+                    &iqos,
+                    "flexpuff",
+                    previous,
+                    Setting::FlexPuff(switch.enabled()),
+                )
+                .await?
+                .is_written()
+            {
+                println!(
+                    "FlexPuff {}",
+                    if switch.enabled() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
         }
-        FlexPuffAction::Disable => {
-            iqos.set_flexpuff(model, FlexPuffSetting::new(false))
-                .await?;
-            println!("FlexPuff disabled");
-        }
-        FlexPuffAction::Status => {
+        None => {
             let s = iqos.read_flexpuff(model).await?;
             println!(
                 "FlexPuff: {}",
@@ -56,71 +86,57 @@ async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<(
     Ok(())
 }
 
-fn parse_action(args: &[String]) -> Result<FlexPuffAction> {
-    match args.get(1).map(String::as_str) {
-        None if args.len() == 1 => Ok(FlexPuffAction::Status),
-        Some("enable") if args.len() == 2 => Ok(FlexPuffAction::Enable),
-        Some("enable") => Err(invalid_arguments("Usage: flexpuff enable")),
-        Some("disable") if args.len() == 2 => Ok(FlexPuffAction::Disable),
-        Some("disable") => Err(invalid_arguments("Usage: flexpuff disable")),
-        Some("status") if args.len() == 2 => Ok(FlexPuffAction::Status),
-        Some("status") => Err(invalid_arguments("Usage: flexpuff status")),
-        Some(opt) => Err(invalid_arguments(format!(
-            "Invalid option: {opt}. Use enable/disable/status"
-        ))),
-        None => Err(invalid_arguments("Usage: flexpuff [enable|disable|status]")),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn args(parts: &[&str]) -> Vec<String> {
-        parts.iter().map(|part| (*part).to_owned()).collect()
+    fn parse_action(parts: &[&str]) -> Result<SwitchOrStatus> {
+        let args: Vec<String> = parts.iter().map(|part| (*part).to_owned()).collect();
+        Ok(parse_args::<FlexPuffArgs>(&args)?.unwrap().action)
     }
 
     #[test]
     fn parses_enable() {
         assert_eq!(
-            parse_action(&args(&["flexpuff", "enable"])).unwrap(),
-            FlexPuffAction::Enable
+            parse_action(&["flexpuff", "enable"]).unwrap(),
+            SwitchOrStatus::On
+        );
+        assert_eq!(
+            parse_action(&["flexpuff", "on"]).unwrap(),
+            SwitchOrStatus::On
         );
     }
 
     #[test]
     fn parses_disable() {
         assert_eq!(
-            parse_action(&args(&["flexpuff", "disable"])).unwrap(),
-            FlexPuffAction::Disable
+            parse_action(&["flexpuff", "disable"]).unwrap(),
+            SwitchOrStatus::Off
         );
     }
 
     #[test]
     fn parses_status_subcommand() {
         assert_eq!(
-            parse_action(&args(&["flexpuff", "status"])).unwrap(),
-            FlexPuffAction::Status
+            parse_action(&["flexpuff", "status"]).unwrap(),
+            SwitchOrStatus::Status
         );
     }
 
     #[test]
     fn parses_default_status() {
-        assert_eq!(
-            parse_action(&args(&["flexpuff"])).unwrap(),
-            FlexPuffAction::Status
-        );
+        assert_eq!(parse_action(&["flexpuff"]).unwrap(), SwitchOrStatus::Status);
     }
 
     #[test]
     fn rejects_trailing_args() {
-        assert!(parse_action(&args(&["flexpuff", "enable", "typo"])).is_err());
-        assert!(parse_action(&args(&["flexpuff", "disable", "typo"])).is_err());
-        assert!(parse_action(&args(&["flexpuff", "status", "typo"])).is_err());
+        assert!(parse_action(&["flexpuff", "enable", "typo"]).is_err());
+        assert!(parse_action(&["flexpuff", "disable", "typo"]).is_err());
+        assert!(parse_action(&["flexpuff", "status", "typo"]).is_err());
     }
 
     #[test]
     fn rejects_invalid_subcommand() {
-        assert!(parse_action(&args(&["flexpuff", "invalid"])).is_err());
+        assert!(parse_action(&["flexpuff", "invalid"]).is_err());
     }
 }
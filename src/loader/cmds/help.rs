@@ -1,59 +1,442 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceCapability, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceCapability;
 
-use crate::loader::parser::IQOSConsole;
+use crate::loader::args::parse_args;
+use crate::loader::cmds::console_command;
+use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::session::Session;
+use crate::model_selector::{model_display_name, KNOWN_MODELS};
+use crate::overrides::DeviceProfile;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "help",
-        Box::new(|iqos, _| Box::pin(async move { execute(iqos).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>) -> Result<()> {
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Writes {
+    Never,
+    Always,
+    WithArguments,
+}
+
+/// Detailed help for one console command, shared by `help <command>` and
+/// `iqos <command> --help`.
+#[derive(Debug)]
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub details: &'static [&'static str],
+    pub examples: &'static [&'static str],
+    pub capability: Option<DeviceCapability>,
+    /// Arguments only some models accept, with the capability they need.
+    pub limits: &'static [(&'static str, DeviceCapability)],
+    pub writes: Writes,
+}
+
+pub const COMMAND_HELP: &[CommandHelp] = &[
+    CommandHelp {
+        name: "autostart",
+        details: &["Without an argument, shows whether heating starts automatically."],
+        examples: &["autostart", "autostart on"],
+        capability: Some(DeviceCapability::AutoStart),
+        limits: &[],
+        writes: Writes::WithArguments,
+    },
+    CommandHelp {
+        name: "battery",
+        details: &["Shows the battery level reported over GATT."],
+        examples: &["battery"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "brightness",
+        details: &["Without an argument, shows the current LED brightness."],
+        examples: &["brightness", "brightness low"],
+        capability: Some(DeviceCapability::Brightness),
+        limits: &[],
+        writes: Writes::WithArguments,
+    },
+    CommandHelp {
+        name: "changes",
+        details: &[
+            "Lists the settings changed in this console session, oldest first, with the value each had before.",
+        ],
+        examples: &["changes"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "device",
+        details: &[
+            "Manages device labels stored in config.toml; does not talk to the device.",
+            "Labels must not start with model:, label:, addr: or tag:.",
+            "A label named like a model selector, such as iluma, is targeted as label:iluma.",
+        ],
+        examples: &[
+            "device save minera",
+            "device list --long",
+            "device remove minera",
+            "device firmware minera",
+        ],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "diagnosis",
+        details: &["Shows total puff count, days used and battery voltage."],
+        examples: &["diagnosis"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "findmyiqos",
+        details: &["Vibrates the device until Enter is pressed."],
+        examples: &["findmyiqos"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "flexbattery",
+        details: &[
+            "Without an argument, shows the FlexBattery mode and pause state.",
+            "Setting a mode leaves the pause state unchanged; `pause` keeps the current mode.",
+        ],
+        examples: &["flexbattery", "flexbattery eco", "flexbattery pause on"],
+        capability: Some(DeviceCapability::FlexBattery),
+        limits: &[],
+        writes: Writes::WithArguments,
+    },
+    CommandHelp {
+        name: "flexpuff",
+        details: &["Without an argument, shows whether FlexPuff is enabled."],
+        examples: &["flexpuff", "flexpuff enable"],
+        capability: Some(DeviceCapability::FlexPuff),
+        limits: &[],
+        writes: Writes::WithArguments,
+    },
+    CommandHelp {
+        name: "help",
+        details: &["Without an argument, lists the commands available on the connected device."],
+        examples: &["help", "help vibration"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "info",
+        details: &["Shows model, GATT metadata, product numbers, firmware and battery voltage."],
+        examples: &["info"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "lock",
+        details: &["Locks the device so it cannot heat until unlocked."],
+        examples: &["lock"],
+        capability: Some(DeviceCapability::DeviceLock),
+        limits: &[],
+        writes: Writes::Always,
+    },
+    CommandHelp {
+        name: "set",
+        details: &[
+            "Changes several settings as one transaction. Previous values are read first, then each setting is written in order.",
+            "If a write fails, the settings already changed are restored and each one is reported as committed, rolled back, not applied or unknown.",
+            "Settings: brightness, flexpuff, autostart, smartgesture, flexbattery, flexbattery.pause and vibration.<flag>.",
+            "Smart Gesture cannot be rolled back because its previous state cannot be read.",
+        ],
+        examples: &[
+            "set brightness=low flexbattery=eco",
+            "set vibration.heating=off vibration.puffend=on autostart=on",
+        ],
+        capability: None,
+        limits: &[],
+        writes: Writes::Always,
+    },
+    CommandHelp {
+        name: "smartgesture",
+        details: &["The current Smart Gesture state cannot be read back from the device."],
+        examples: &["smartgesture enable"],
+        capability: Some(DeviceCapability::SmartGesture),
+        limits: &[],
+        writes: Writes::Always,
+    },
+    CommandHelp {
+        name: "undo",
+        details: &[
+            "Restores the value the most recently changed setting had before this session changed it.",
+            "Smart Gesture and lock changes cannot be undone because their previous state cannot be read.",
+            "On exit the console offers to roll back every change made in the session.",
+        ],
+        examples: &["undo"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Always,
+    },
+    CommandHelp {
+        name: "unlock",
+        details: &["Unlocks a locked device."],
+        examples: &["unlock"],
+        capability: Some(DeviceCapability::DeviceLock),
+        limits: &[],
+        writes: Writes::Always,
+    },
+    CommandHelp {
+        name: "version",
+        details: &["Shows the IQOS CLI version."],
+        examples: &["version"],
+        capability: None,
+        limits: &[],
+        writes: Writes::Never,
+    },
+    CommandHelp {
+        name: "vibration",
+        details: &[
+            "Without arguments, shows the current vibration settings.",
+            "Flags: heating, starting, puffend, terminated, charge.",
+            "Flags that are not given keep their current value.",
+        ],
+        examples: &["vibration", "vibration heating on puffend off"],
+        capability: Some(DeviceCapability::Vibration),
+        limits: &[("charge", DeviceCapability::ChargeStartVibration)],
+        writes: Writes::WithArguments,
+    },
+];
+
+pub fn command_help(name: &str) -> Option<&'static CommandHelp> {
+    COMMAND_HELP.iter().find(|help| help.name == name)
+}
+
+impl CommandHelp {
+    pub fn supported_models(&self) -> String {
+        self.capability
+            .map_or_else(|| "All models".to_string(), models_supporting)
+    }
+
+    /// Body shared by the console and clap; `prefix` is prepended to every
+    /// example line (`"iqos "` for the one-shot CLI).
+    pub fn render(&self, prefix: &str) -> String {
+        let mut text = String::new();
+
+        for line in self.details {
+            text.push_str(line);
+            text.push('\n');
+        }
+        for (argument, capability) in self.limits {
+            text.push_str(&format!(
+                "`{argument}` is only available on: {}.\n",
+                models_supporting(*capability)
+            ));
+        }
+
+        text.push_str("\nExamples:\n");
+        for example in self.examples {
+            text.push_str(&format!("  {prefix}{example}\n"));
+        }
+
+        text.push_str(&format!(
+            "\nSupported models: {}\n",
+            self.supported_models()
+        ));
+        text.push_str(match self.writes {
+            Writes::Never => "Writes device state: no",
+            Writes::Always => "Writes device state: yes",
+            Writes::WithArguments => "Writes device state: only when arguments are given",
+        });
+
+        text
+    }
+}
+
+/// Known models with `capability`, or "All models".
+fn models_supporting(capability: DeviceCapability) -> String {
+    let models: Vec<&str> = KNOWN_MODELS
+        .iter()
+        .filter(|model| model.supports(capability))
+        .map(|model| model_display_name(*model))
+        .collect();
+
+    if models.len() == KNOWN_MODELS.len() {
+        "All models".to_string()
+    } else {
+        models.join(", ")
+    }
+}
+
+/// `after_help` text for a console command.
+pub fn console_after_help(name: &str) -> String {
+    command_help(name)
+        .map(|help| help.render(""))
+        .unwrap_or_default()
+}
+
+/// `after_help` text for a one-shot subcommand.
+pub fn cli_after_help(name: &str) -> String {
+    command_help(name)
+        .map(|help| help.render("iqos "))
+        .unwrap_or_default()
+}
+
+/// Display the command list, or detailed help for one command.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "help", after_help = console_after_help("help"))]
+pub struct HelpArgs {
+    /// Command to describe.
+    #[arg(value_name = "command")]
+    pub command: Option<String>,
+}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    let profile = session.profile();
+
+    let Some(args) = parse_args::<HelpArgs>(&args)? else {
+        return Ok(());
+    };
+
+    match args.command {
+        None => {
+            print_overview(profile);
+            Ok(())
+        }
+        Some(name) => {
+            let name = name.to_ascii_lowercase();
+            let mut command = console_command(&name)
+                .ok_or_else(|| invalid_arguments(format!("Unknown command: {name}")))?;
+
+            print!("{}", command.render_long_help());
+            if command_help(&name)
+                .and_then(|help| help.capability)
+                .is_some_and(|capability| !profile.supports(capability))
+            {
+                println!(
+                    "\nNot supported on the connected device ({:?})",
+                    profile.model
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_overview(profile: DeviceProfile) {
     println!("Available commands:");
     println!("  battery            Display battery level");
     println!("  device             Manage saved device labels");
     println!("  findmyiqos         Activate find-my-device vibration");
     println!("  version            Display IQOS CLI version");
-    if model.supports(DeviceCapability::DeviceLock) {
+    if profile.supports(DeviceCapability::DeviceLock) {
         println!("  lock | unlock      Lock or unlock the device");
     }
-    if model.supports(DeviceCapability::AutoStart) {
+    if profile.supports(DeviceCapability::AutoStart) {
         println!("  autostart [on|off|status] Configure auto-start");
     }
     println!("  diagnosis          Retrieve telemetry data");
-    let has_device_commands = model.supports(DeviceCapability::Brightness)
-        || model.supports(DeviceCapability::SmartGesture)
-        || model.supports(DeviceCapability::FlexPuff)
-        || model.supports(DeviceCapability::Vibration)
-        || model.supports(DeviceCapability::FlexBattery);
+    let has_device_commands = profile.supports(DeviceCapability::Brightness)
+        || profile.supports(DeviceCapability::SmartGesture)
+        || profile.supports(DeviceCapability::FlexPuff)
+        || profile.supports(DeviceCapability::Vibration)
+        || profile.supports(DeviceCapability::FlexBattery);
 
     if has_device_commands {
         println!("\nDevice commands:");
     }
-    if model.supports(DeviceCapability::Brightness) {
+    if profile.supports(DeviceCapability::Brightness) {
         println!("  brightness [high|low]                     Set brightness");
     }
-    if model.supports(DeviceCapability::SmartGesture) {
+    if profile.supports(DeviceCapability::SmartGesture) {
         println!("  smartgesture [enable|disable]             Configure SmartGesture");
     }
-    if model.supports(DeviceCapability::FlexPuff) {
+    if profile.supports(DeviceCapability::FlexPuff) {
         println!("  flexpuff [enable|disable|status]          Configure FlexPuff");
     }
-    if model.supports(DeviceCapability::Vibration) {
+    if profile.supports(DeviceCapability::Vibration) {
         println!("  vibration [heating|starting|terminated|puffend] [on|off] ...");
     }
-    if model.supports(DeviceCapability::FlexBattery) {
+    if profile.supports(DeviceCapability::FlexBattery) {
         println!("  flexbattery [performance|eco|pause on|off]");
     }
     println!("\n  info               Device metadata, firmware, and voltage snapshot");
-    println!("  help               This help");
+    println!("  set SETTING=VALUE ... Change several settings, rolling back on failure");
+    println!("  changes | undo     List or undo settings changed in this session");
+    println!("  help [command]     This help, or details for one command");
     println!("  quit | exit        Exit");
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_console_command_has_help() {
+        for name in [
+            "autostart",
+            "battery",
+            "brightness",
+            "changes",
+            "device",
+            "diagnosis",
+            "findmyiqos",
+            "flexbattery",
+            "flexpuff",
+            "help",
+            "info",
+            "lock",
+            "set",
+            "smartgesture",
+            "undo",
+            "unlock",
+            "version",
+            "vibration",
+        ] {
+            assert!(command_help(name).is_some(), "{name}");
+        }
+    }
+
+    #[test]
+    fn lists_supported_models_from_capabilities() {
+        assert_eq!(
+            command_help("flexpuff").unwrap().supported_models(),
+            "ILUMA i, ILUMA i Prime"
+        );
+        assert_eq!(
+            command_help("vibration").unwrap().supported_models(),
+            "All models"
+        );
+        assert!(command_help("vibration").unwrap().render("").contains(
+            "`charge` is only available on: ILUMA i, ILUMA i Prime, ILUMA, ILUMA Prime.\n"
+        ));
+    }
+
+    #[test]
+    fn console_and_cli_help_share_the_body() {
+        let console = console_after_help("vibration");
+        let cli = cli_after_help("vibration");
+
+        assert!(console.contains("\n  vibration heating on puffend off\n"));
+        assert!(cli.contains("\n  iqos vibration heating on puffend off\n"));
+        assert_eq!(console.replace("iqos ", ""), cli.replace("iqos ", ""));
+    }
+
+    #[test]
+    fn every_console_command_has_a_parser() {
+        for help in COMMAND_HELP {
+            let mut command = console_command(help.name).unwrap();
+            assert!(command
+                .render_long_help()
+                .to_string()
+                .contains("Writes device state"));
+        }
+    }
 }
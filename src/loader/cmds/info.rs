@@ -1,25 +1,36 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceStatus, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceStatus;
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "info",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    if args.len() != 1 {
-        return Err(invalid_arguments("Usage: info"));
+/// Device metadata, firmware, and voltage snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "info", after_help = console_after_help("info"))]
+pub struct InfoArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
     }
+}
 
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+pub async fn run(session: Arc<Session>, _args: InfoArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    let model = session.profile().model;
     let device_info = iqos.transport().device_info().clone();
     let status = iqos.read_device_status(model, device_info).await?;
 
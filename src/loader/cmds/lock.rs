@@ -1,21 +1,46 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceCapability;
 
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
 use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "lock",
-        Box::new(|iqos, _| Box::pin(async move { execute(iqos).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>) -> Result<()> {
-    let iqos = iqos.lock().await;
-    iqos.lock(iqos.transport().model()).await?;
-    println!("Device locked");
+/// Lock the device.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "lock", after_help = console_after_help("lock"))]
+pub struct LockArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, _args: LockArgs) -> Result<()> {
+    if !session.profile().supports(DeviceCapability::DeviceLock) {
+        println!("Device lock is not supported on this device");
+        return Ok(());
+    }
+
+    let iqos = session.iqos().await;
+    let written = session
+        .write(&iqos, "lock", None, Setting::Locked(true))
+        .await?;
+    if written.is_written() {
+        println!("{}", written.report("Device locked"));
+    }
     Ok(())
 }
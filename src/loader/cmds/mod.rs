@@ -1,6 +1,7 @@
 pub mod autostart;
 pub mod battery;
 pub mod brightness;
+pub mod changes;
 pub mod command;
 pub mod device;
 pub mod diagnosis;
@@ -10,7 +11,105 @@ pub mod flexpuff;
 pub mod help;
 pub mod info;
 pub mod lock;
+pub mod set;
 pub mod smartgesture;
+pub mod undo;
 pub mod unlock;
 pub mod version;
 pub mod vibration;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::{CommandFactory, Subcommand};
+
+use crate::loader::session::Session;
+use help::cli_after_help;
+
+/// Device commands that can run one-shot from the shell as well as in the
+/// console. Both paths parse arguments with the same clap structs.
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum RegisteredCommand {
+    #[command(after_help = cli_after_help("autostart"))]
+    Autostart(autostart::AutostartArgs),
+    #[command(after_help = cli_after_help("battery"))]
+    Battery(battery::BatteryArgs),
+    #[command(after_help = cli_after_help("brightness"))]
+    Brightness(brightness::BrightnessArgs),
+    #[command(after_help = cli_after_help("diagnosis"))]
+    Diagnosis(diagnosis::DiagnosisArgs),
+    #[command(after_help = cli_after_help("findmyiqos"))]
+    Findmyiqos(findmyiqos::FindMyIqosArgs),
+    #[command(after_help = cli_after_help("flexbattery"))]
+    Flexbattery(flexbattery::FlexBatteryArgs),
+    #[command(after_help = cli_after_help("flexpuff"))]
+    Flexpuff(flexpuff::FlexPuffArgs),
+    #[command(after_help = cli_after_help("info"))]
+    Info(info::InfoArgs),
+    #[command(after_help = cli_after_help("lock"))]
+    Lock(lock::LockArgs),
+    #[command(after_help = cli_after_help("set"))]
+    Set(set::SetArgs),
+    #[command(after_help = cli_after_help("smartgesture"))]
+    Smartgesture(smartgesture::SmartGestureArgs),
+    #[command(after_help = cli_after_help("unlock"))]
+    Unlock(unlock::UnlockArgs),
+    #[command(after_help = cli_after_help("vibration"))]
+    Vibration(vibration::VibrationArgs),
+}
+
+impl RegisteredCommand {
+    /// Checks that need no device, run before connecting in one-shot mode.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            // `charge` support depends on the model, so it is checked after connecting.
+            Self::Vibration(args) => args.changes(true).map(|_| ()),
+            Self::Flexbattery(args) => args.validate(),
+            Self::Set(args) => args.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn run(self, session: Arc<Session>) -> Result<()> {
+        match self {
+            Self::Autostart(args) => autostart::run(session, args).await,
+            Self::Battery(args) => battery::run(session, args).await,
+            Self::Brightness(args) => brightness::run(session, args).await,
+            Self::Diagnosis(args) => diagnosis::run(session, args).await,
+            Self::Findmyiqos(args) => findmyiqos::run(session, args).await,
+            Self::Flexbattery(args) => flexbattery::run(session, args).await,
+            Self::Flexpuff(args) => flexpuff::run(session, args).await,
+            Self::Info(args) => info::run(session, args).await,
+            Self::Lock(args) => lock::run(session, args).await,
+            Self::Set(args) => set::run(session, args).await,
+            Self::Smartgesture(args) => smartgesture::run(session, args).await,
+            Self::Unlock(args) => unlock::run(session, args).await,
+            Self::Vibration(args) => vibration::run(session, args).await,
+        }
+    }
+}
+
+/// Clap definition of a console command, used for `help <command>`.
+pub fn console_command(name: &str) -> Option<clap::Command> {
+    Some(match name {
+        "autostart" => autostart::AutostartArgs::command(),
+        "battery" => battery::BatteryArgs::command(),
+        "brightness" => brightness::BrightnessArgs::command(),
+        "changes" => changes::ChangesArgs::command(),
+        "device" => device::DeviceArgs::command(),
+        "diagnosis" => diagnosis::DiagnosisArgs::command(),
+        "findmyiqos" => findmyiqos::FindMyIqosArgs::command(),
+        "flexbattery" => flexbattery::FlexBatteryArgs::command(),
+        "flexpuff" => flexpuff::FlexPuffArgs::command(),
+        "help" => help::HelpArgs::command(),
+        "info" => info::InfoArgs::command(),
+        "lock" => lock::LockArgs::command(),
+        "set" => set::SetArgs::command(),
+        "smartgesture" => smartgesture::SmartGestureArgs::command(),
+        "undo" => undo::UndoArgs::command(),
+        "unlock" => unlock::UnlockArgs::command(),
+        "version" => version::VersionArgs::command(),
+        "vibration" => vibration::VibrationArgs::command(),
+        _ => return None,
+    })
+}
@@ -0,0 +1,306 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use iqos::{FlexBatteryMode, FlexBatterySettings, Iqos, IqosBle};
+
+use crate::loader::args::{parse_args, Switch};
+use crate::loader::cmds::brightness::BrightnessArg;
+use crate::loader::cmds::help::{command_help, console_after_help};
+use crate::loader::cmds::vibration::{apply_changes, VibrationFlag};
+use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
+use crate::loader::transaction::{Outcome, Transaction};
+use crate::overrides::DeviceProfile;
+
+pub fn register_command(console: &mut IQOSConsole) {
+    console.register_command(
+        "set",
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
+    );
+}
+
+/// Change several settings at once, rolling back if any write fails.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "set", after_help = console_after_help("set"))]
+pub struct SetArgs {
+    /// Settings such as `brightness=low vibration.heating=off flexbattery=eco`.
+    #[arg(value_name = "SETTING=VALUE", required = true, value_parser = parse_assignment)]
+    pub assignments: Vec<Assignment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    Brightness(BrightnessArg),
+    FlexPuff(Switch),
+    AutoStart(Switch),
+    SmartGesture(Switch),
+    FlexBattery(FlexBatteryModeArg),
+    FlexBatteryPause(Switch),
+    Vibration(VibrationFlag, Switch),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FlexBatteryModeArg {
+    Performance,
+    Eco,
+}
+
+impl From<FlexBatteryModeArg> for FlexBatteryMode {
+    fn from(value: FlexBatteryModeArg) -> Self {
+        match value {
+            FlexBatteryModeArg::Performance => Self::Performance,
+            FlexBatteryModeArg::Eco => Self::Eco,
+        }
+    }
+}
+
+const SETTINGS: &str = "brightness, flexpuff, autostart, smartgesture, flexbattery, flexbattery.pause, vibration.<flag>";
+
+fn parse_assignment(value: &str) -> Result<Assignment, String> {
+    let Some((setting, state)) = value.split_once('=') else {
+        return Err(format!("expected SETTING=VALUE\n  [settings: {SETTINGS}]"));
+    };
+
+    fn parse<T: ValueEnum>(setting: &str, state: &str) -> Result<T, String> {
+        T::from_str(state, true).map_err(|_| {
+            let values: Vec<String> = T::value_variants()
+                .iter()
+                .filter_map(|value| value.to_possible_value())
+                .map(|value| value.get_name().to_string())
+                .collect();
+            format!(
+                "invalid value '{state}' for '{setting}'\n  [possible values: {}]",
+                values.join(", ")
+            )
+        })
+    }
+
+    let setting = setting.to_ascii_lowercase();
+    Ok(match setting.as_str() {
+        "brightness" => Assignment::Brightness(parse(&setting, state)?),
+        "flexpuff" => Assignment::FlexPuff(parse(&setting, state)?),
+        "autostart" => Assignment::AutoStart(parse(&setting, state)?),
+        "smartgesture" => Assignment::SmartGesture(parse(&setting, state)?),
+        "flexbattery" => Assignment::FlexBattery(parse(&setting, state)?),
+        "flexbattery.pause" => Assignment::FlexBatteryPause(parse(&setting, state)?),
+        _ => match setting.strip_prefix("vibration.") {
+            Some(flag) => Assignment::Vibration(parse(&setting, flag)?, parse(&setting, state)?),
+            None => {
+                return Err(format!(
+                    "unknown setting '{setting}'\n  [settings: {SETTINGS}]"
+                ))
+            }
+        },
+    })
+}
+
+impl Assignment {
+    /// The command whose setting this changes; assignments to the same
+    /// command are combined into a single write.
+    pub fn command(self) -> &'static str {
+        match self {
+            Self::Brightness(_) => "brightness",
+            Self::FlexPuff(_) => "flexpuff",
+            Self::AutoStart(_) => "autostart",
+            Self::SmartGesture(_) => "smartgesture",
+            Self::FlexBattery(_) | Self::FlexBatteryPause(_) => "flexbattery",
+            Self::Vibration(..) => "vibration",
+        }
+    }
+
+    fn key(self) -> String {
+        match self {
+            Self::FlexBatteryPause(_) => "flexbattery.pause".to_string(),
+            Self::Vibration(flag, _) => format!(
+                "vibration.{}",
+                flag.to_possible_value()
+                    .expect("vibration flags are not skipped")
+                    .get_name()
+            ),
+            _ => self.command().to_string(),
+        }
+    }
+}
+
+impl SetArgs {
+    /// Reject settings given twice, before anything is read or written.
+    pub fn validate(&self) -> Result<()> {
+        for (index, assignment) in self.assignments.iter().enumerate() {
+            let key = assignment.key();
+            if self.assignments[..index]
+                .iter()
+                .any(|earlier| earlier.key() == key)
+            {
+                return Err(invalid_arguments(format!("'{key}' is set more than once")));
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, args: SetArgs) -> Result<()> {
+    args.validate()?;
+    let iqos = session.iqos().await;
+    let profile = session.profile();
+
+    for assignment in &args.assignments {
+        let supported = command_help(assignment.command())
+            .and_then(|help| help.capability)
+            .is_none_or(|capability| profile.supports(capability));
+        let charge = matches!(assignment, Assignment::Vibration(VibrationFlag::Charge, _));
+        if !supported || (charge && !profile.supports_charge_start_vibration()) {
+            return Err(invalid_arguments(format!(
+                "'{}' is not supported on this device",
+                assignment.key()
+            )));
+        }
+    }
+
+    let transaction = transaction(&iqos, profile, &args.assignments).await?;
+    let mut report = transaction.commit(&session, &iqos).await;
+    match report.error.take() {
+        None => {
+            if report
+                .steps
+                .iter()
+                .any(|step| matches!(step.outcome, Outcome::Committed | Outcome::Unverified))
+            {
+                println!("Settings updated:");
+                report.print();
+            }
+            Ok(())
+        }
+        Some(error) => {
+            println!("Settings not updated:");
+            report.print();
+            Err(error)
+        }
+    }
+}
+
+/// One write per command, in the order each command is first mentioned.
+async fn transaction(
+    iqos: &Iqos<IqosBle>,
+    profile: DeviceProfile,
+    assignments: &[Assignment],
+) -> Result<Transaction> {
+    let model = profile.model;
+    let mut transaction = Transaction::new();
+    let mut commands = Vec::new();
+
+    for assignment in assignments {
+        let command = assignment.command();
+        if commands.contains(&command) {
+            continue;
+        }
+        commands.push(command);
+
+        let setting = match *assignment {
+            Assignment::Brightness(level) => Setting::Brightness(level.into()),
+            Assignment::FlexPuff(state) => Setting::FlexPuff(state.enabled()),
+            Assignment::AutoStart(state) => Setting::AutoStart(state.enabled()),
+            Assignment::SmartGesture(state) => Setting::SmartGesture(state.enabled()),
+            Assignment::FlexBattery(_) | Assignment::FlexBatteryPause(_) => {
+                let pause = assignments.iter().find_map(|assignment| match assignment {
+                    Assignment::FlexBatteryPause(state) => Some(state.enabled()),
+                    _ => None,
+                });
+                let mode = match assignments.iter().find_map(|assignment| match assignment {
+                    Assignment::FlexBattery(mode) => Some(FlexBatteryMode::from(*mode)),
+                    _ => None,
+                }) {
+                    Some(mode) => mode,
+                    None => iqos.read_flexbattery(model).await?.mode(),
+                };
+                Setting::FlexBattery(FlexBatterySettings::new(mode, pause))
+            }
+            Assignment::Vibration(..) => {
+                let changes: Vec<(VibrationFlag, bool)> = assignments
+                    .iter()
+                    .filter_map(|assignment| match assignment {
+                        Assignment::Vibration(flag, state) => Some((*flag, state.enabled())),
+                        _ => None,
+                    })
+                    .collect();
+                let current = iqos.read_vibration_settings(model).await?;
+                Setting::Vibration(apply_changes(
+                    current,
+                    &changes,
+                    profile.supports_charge_start_vibration(),
+                ))
+            }
+        };
+        transaction = transaction.set(command, setting);
+    }
+
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parts: &[&str]) -> Result<SetArgs> {
+        let args: Vec<String> = parts.iter().map(|part| (*part).to_owned()).collect();
+        Ok(parse_args::<SetArgs>(&args)?.unwrap())
+    }
+
+    #[test]
+    fn parses_assignments_in_order() {
+        let args = parse(&[
+            "set",
+            "brightness=low",
+            "vibration.heating=off",
+            "FlexBattery=eco",
+            "flexbattery.pause=on",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.assignments,
+            vec![
+                Assignment::Brightness(BrightnessArg::Low),
+                Assignment::Vibration(VibrationFlag::Heating, Switch::Off),
+                Assignment::FlexBattery(FlexBatteryModeArg::Eco),
+                Assignment::FlexBatteryPause(Switch::On),
+            ]
+        );
+        assert!(args.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_settings_and_values() {
+        let error = parse(&["set", "volume=high"]).unwrap_err().to_string();
+        assert!(error.contains("unknown setting 'volume'"), "{error}");
+
+        let error = parse(&["set", "brightness=dim"]).unwrap_err().to_string();
+        assert!(error.contains("[possible values: high, low]"), "{error}");
+
+        assert!(parse(&["set", "vibration.buzz=on"]).is_err());
+        assert!(parse(&["set", "brightness"]).is_err());
+        assert!(parse(&["set"]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_setting_given_twice() {
+        let args = parse(&["set", "vibration.heating=on", "vibration.heating=off"]).unwrap();
+        let error = args.validate().unwrap_err().to_string();
+
+        assert_eq!(error, "'vibration.heating' is set more than once");
+        assert!(
+            parse(&["set", "vibration.heating=on", "vibration.puffend=off"])
+                .unwrap()
+                .validate()
+                .is_ok()
+        );
+    }
+}
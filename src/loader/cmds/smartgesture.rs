@@ -1,98 +1,109 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{DeviceCapability, Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
+use iqos::DeviceCapability;
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::{parse_args, Switch};
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "smartgesture",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum SmartGestureAction {
-    Enable,
-    Disable,
+/// Configure SmartGesture.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "smartgesture", after_help = console_after_help("smartgesture"))]
+pub struct SmartGestureArgs {
+    /// Turn Smart Gesture on/off (also enable/disable, true/false).
+    #[arg(value_enum, ignore_case = true)]
+    pub state: Switch,
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let action = parse_action(&args)?;
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
 
-    if !model.supports(DeviceCapability::SmartGesture) {
+pub async fn run(session: Arc<Session>, args: SmartGestureArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    if !session.profile().supports(DeviceCapability::SmartGesture) {
         println!("SmartGesture is not supported on this device");
         return Ok(());
     }
 
-    match action {
-        SmartGestureAction::Enable => {
-            iqos.set_smartgesture(model, true).await?;
-            println!("Smart Gesture enabled");
-        }
-        SmartGestureAction::Disable => {
-            iqos.set_smartgesture(model, false).await?;
-            println!("Smart Gesture disabled");
-        }
+    // The Smart Gesture state cannot be read back, so there is no previous value.
+    let written = session
+        .write(
+            &iqos,
+            "smartgesture",
+            None,
+            Setting::SmartGesture(args.state.enabled()),
+        )
+        .await?;
+    if written.is_written() {
+        println!(
+            "{}",
+            written.report(format_args!(
+                "Smart Gesture {}",
+                if args.state.enabled() {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            ))
+        );
     }
 
     Ok(())
 }
 
-fn parse_action(args: &[String]) -> Result<SmartGestureAction> {
-    match args.get(1).map(String::as_str) {
-        Some("enable") if args.len() == 2 => Ok(SmartGestureAction::Enable),
-        Some("enable") => Err(invalid_arguments("Usage: smartgesture enable")),
-        Some("disable") if args.len() == 2 => Ok(SmartGestureAction::Disable),
-        Some("disable") => Err(invalid_arguments("Usage: smartgesture disable")),
-        Some(opt) => Err(invalid_arguments(format!(
-            "Invalid option: {opt}. Use enable/disable"
-        ))),
-        None => Err(invalid_arguments("Usage: smartgesture [enable|disable]")),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn args(parts: &[&str]) -> Vec<String> {
-        parts.iter().map(|part| (*part).to_owned()).collect()
+    fn parse_state(parts: &[&str]) -> Result<Switch> {
+        let args: Vec<String> = parts.iter().map(|part| (*part).to_owned()).collect();
+        Ok(parse_args::<SmartGestureArgs>(&args)?.unwrap().state)
     }
 
     #[test]
     fn parses_enable() {
         assert_eq!(
-            parse_action(&args(&["smartgesture", "enable"])).unwrap(),
-            SmartGestureAction::Enable
+            parse_state(&["smartgesture", "enable"]).unwrap(),
+            Switch::On
         );
     }
 
     #[test]
     fn parses_disable() {
         assert_eq!(
-            parse_action(&args(&["smartgesture", "disable"])).unwrap(),
-            SmartGestureAction::Disable
+            parse_state(&["smartgesture", "disable"]).unwrap(),
+            Switch::Off
         );
     }
 
     #[test]
     fn rejects_trailing_args() {
-        assert!(parse_action(&args(&["smartgesture", "enable", "typo"])).is_err());
-        assert!(parse_action(&args(&["smartgesture", "disable", "typo"])).is_err());
+        assert!(parse_state(&["smartgesture", "enable", "typo"]).is_err());
+        assert!(parse_state(&["smartgesture", "disable", "typo"]).is_err());
     }
 
     #[test]
     fn rejects_invalid_subcommand() {
-        assert!(parse_action(&args(&["smartgesture", "status"])).is_err());
+        assert!(parse_state(&["smartgesture", "status"]).is_err());
     }
 
     #[test]
     fn rejects_missing_subcommand() {
-        assert!(parse_action(&args(&["smartgesture"])).is_err());
+        assert!(parse_state(&["smartgesture"]).is_err());
     }
 }
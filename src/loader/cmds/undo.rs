@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+
+pub fn register_command(console: &mut IQOSConsole) {
+    console.register_command(
+        "undo",
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
+    );
+}
+
+/// Restore the setting changed most recently in this session.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "undo", after_help = console_after_help("undo"))]
+pub struct UndoArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+async fn run(session: Arc<Session>, _args: UndoArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    match session.undo_last(&iqos).await? {
+        Some(change) => println!("Undid {change}"),
+        None => println!("Nothing to undo"),
+    }
+    Ok(())
+}
@@ -1,24 +1,34 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle};
-use tokio::sync::Mutex;
+use clap::Parser;
 
 use crate::cli::print_version;
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::parse_args;
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "version",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(_iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    if args.len() != 1 {
-        return Err(invalid_arguments("Usage: version"));
+/// Display IQOS CLI version.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "version", after_help = console_after_help("version"))]
+pub struct VersionArgs {}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
     }
+}
 
+pub async fn run(_session: Arc<Session>, _args: VersionArgs) -> Result<()> {
     print_version();
     Ok(())
 }
@@ -1,89 +1,201 @@
+use std::fmt;
 use std::sync::Arc;
 
 use anyhow::Result;
-use iqos::{Iqos, IqosBle, VibrationSettings};
-use tokio::sync::Mutex;
+use clap::builder::PossibleValue;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use iqos::{DeviceCapability, VibrationSettings};
 
-use crate::loader::parser::{invalid_arguments, IQOSConsole};
+use crate::loader::args::{clap_error, parse_args, Switch};
+use crate::loader::cmds::help::console_after_help;
+use crate::loader::parser::IQOSConsole;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
 
 pub fn register_command(console: &mut IQOSConsole) {
     console.register_command(
         "vibration",
-        Box::new(|iqos, args| Box::pin(async move { execute(iqos, args).await })),
+        Box::new(|session, args| Box::pin(async move { execute(session, args).await })),
     );
 }
 
-async fn execute(iqos: Arc<Mutex<Iqos<IqosBle>>>, args: Vec<String>) -> Result<()> {
-    let iqos = iqos.lock().await;
-    let model = iqos.transport().model();
-    let str_args: Vec<&str> = args.iter().map(String::as_str).collect();
+/// Configure vibration feedback.
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "vibration", after_help = console_after_help("vibration"))]
+pub struct VibrationArgs {
+    /// Flag/state pairs such as `heating on puffend off`; shows the current settings when omitted.
+    #[arg(
+        value_name = "FLAG STATE",
+        value_enum,
+        ignore_case = true,
+        hide_possible_values = true
+    )]
+    pub settings: Vec<VibrationWord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VibrationFlag {
+    Heating,
+    Starting,
+    Puffend,
+    Terminated,
+    Charge,
+}
+
+/// One word of a `FLAG STATE` pair. Flags and states share one list of
+/// arguments, so clap checks every word against both and suggests the
+/// closest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VibrationWord {
+    Flag(VibrationFlag),
+    State(Switch),
+}
+
+impl ValueEnum for VibrationWord {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Flag(VibrationFlag::Heating),
+            Self::Flag(VibrationFlag::Starting),
+            Self::Flag(VibrationFlag::Puffend),
+            Self::Flag(VibrationFlag::Terminated),
+            Self::Flag(VibrationFlag::Charge),
+            Self::State(Switch::On),
+            Self::State(Switch::Off),
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            Self::Flag(flag) => flag.to_possible_value(),
+            Self::State(state) => state.to_possible_value(),
+        }
+    }
+}
+
+impl fmt::Display for VibrationWord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped values");
+        f.write_str(value.get_name())
+    }
+}
+
+impl VibrationArgs {
+    /// Pair up flags with their on/off values, rejecting `charge` on models
+    /// without charge-start vibration.
+    pub fn changes(&self, has_charge: bool) -> Result<Vec<(VibrationFlag, bool)>> {
+        let mut words = self.settings.iter().copied();
+        let mut changes = Vec::new();
+        while let Some(word) = words.next() {
+            let VibrationWord::Flag(flag) = word else {
+                return Err(usage_error(
+                    ErrorKind::InvalidValue,
+                    format!(
+                        "expected a flag before '{word}'\n  [possible values: heating, starting, puffend, terminated, charge]"
+                    ),
+                ));
+            };
+            let state = match words.next() {
+                Some(VibrationWord::State(state)) => state,
+                Some(other) => {
+                    return Err(usage_error(
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "invalid value '{other}' for '{word}'\n  [possible values: on, off]"
+                        ),
+                    ))
+                }
+                None => {
+                    return Err(usage_error(
+                        ErrorKind::WrongNumberOfValues,
+                        format!("'{word}' requires an on/off value"),
+                    ))
+                }
+            };
+            if flag == VibrationFlag::Charge && !has_charge {
+                return Err(usage_error(
+                    ErrorKind::InvalidValue,
+                    "'charge' flag is not supported on this device",
+                ));
+            }
+            changes.push((flag, state.enabled()));
+        }
+        Ok(changes)
+    }
+}
+
+fn usage_error(kind: ErrorKind, message: impl std::fmt::Display) -> anyhow::Error {
+    clap_error(&VibrationArgs::command().error(kind, message))
+}
+
+async fn execute(session: Arc<Session>, args: Vec<String>) -> Result<()> {
+    match parse_args(&args)? {
+        Some(args) => run(session, args).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn run(session: Arc<Session>, args: VibrationArgs) -> Result<()> {
+    let iqos = session.iqos().await;
+    let profile = session.profile();
+    let model = profile.model;
 
-    if args.len() == 1 {
+    if !profile.supports(DeviceCapability::Vibration) {
+        println!("Vibration settings are not supported on this device");
+        return Ok(());
+    }
+
+    if args.settings.is_empty() {
         let s = iqos.read_vibration_settings(model).await?;
         println!("{s:?}");
         return Ok(());
     }
 
-    let param_args = &str_args[1..];
-    validate_flags(param_args, model.supports_charge_start_vibration())?;
+    let has_charge = profile.supports_charge_start_vibration();
+    let changes = args.changes(has_charge)?;
     let current = iqos.read_vibration_settings(model).await?;
-    let settings = apply_changes(current, param_args, model.supports_charge_start_vibration())?;
-    iqos.update_vibration_settings(model, settings).await?;
-    println!("Vibration settings updated");
-    Ok(())
-}
-
-fn validate_flags(args: &[&str], has_charge: bool) -> Result<()> {
-    const VALID: &[&str] = &["heating", "starting", "puffend", "terminated", "charge"];
-    if !args.len().is_multiple_of(2) {
-        return Err(invalid_arguments("Each flag requires a value. Usage: vibration [heating|starting|puffend|terminated|charge] [on|off] ..."));
-    }
-    for chunk in args.chunks(2) {
-        if !VALID.contains(&chunk[0]) {
-            return Err(invalid_arguments(format!(
-                "Unknown flag '{}'. Valid: heating, starting, puffend, terminated, charge",
-                chunk[0]
-            )));
-        }
-        if chunk[0] == "charge" && !has_charge {
-            return Err(invalid_arguments(
-                "'charge' flag is not supported on this device",
-            ));
-        }
-        if chunk[1] != "on" && chunk[1] != "off" {
-            return Err(invalid_arguments(format!(
-                "Invalid value '{}' for '{}'. Use on or off",
-                chunk[1], chunk[0]
-            )));
-        }
+    let settings = apply_changes(current, &changes, has_charge);
+    if session
+        .write(
+            &iqos,
+            "vibration",
+            Some(Setting::Vibration(current)),
+            Setting::Vibration(settings),
+        )
+        .await?
+        .is_written()
+    {
+        println!("Vibration settings updated");
     }
     Ok(())
 }
 
-fn flag_update(args: &[&str], key: &str) -> Option<bool> {
-    args.windows(2).find(|w| w[0] == key).map(|w| w[1] == "on")
+fn flag_update(changes: &[(VibrationFlag, bool)], flag: VibrationFlag) -> Option<bool> {
+    changes
+        .iter()
+        .find(|(changed, _)| *changed == flag)
+        .map(|(_, enabled)| *enabled)
 }
 
-fn apply_changes(
+pub fn apply_changes(
     current: VibrationSettings,
-    args: &[&str],
+    changes: &[(VibrationFlag, bool)],
     has_charge: bool,
-) -> Result<VibrationSettings> {
-    let heating = flag_update(args, "heating").unwrap_or(current.when_heating_start());
-    let starting = flag_update(args, "starting").unwrap_or(current.when_starting_to_use());
-    let puff_end = flag_update(args, "puffend").unwrap_or(current.when_puff_end());
-    let terminated = flag_update(args, "terminated").unwrap_or(current.when_manually_terminated());
+) -> VibrationSettings {
+    let heating =
+        flag_update(changes, VibrationFlag::Heating).unwrap_or(current.when_heating_start());
+    let starting =
+        flag_update(changes, VibrationFlag::Starting).unwrap_or(current.when_starting_to_use());
+    let puff_end = flag_update(changes, VibrationFlag::Puffend).unwrap_or(current.when_puff_end());
+    let terminated = flag_update(changes, VibrationFlag::Terminated)
+        .unwrap_or(current.when_manually_terminated());
 
     if has_charge {
-        let charge =
-            flag_update(args, "charge").unwrap_or(current.when_charging_start().unwrap_or(false));
-        Ok(VibrationSettings::with_charge_start(
-            heating, starting, puff_end, terminated, charge,
-        ))
+        let charge = flag_update(changes, VibrationFlag::Charge)
+            .unwrap_or(current.when_charging_start().unwrap_or(false));
+        VibrationSettings::with_charge_start(heating, starting, puff_end, terminated, charge)
     } else {
-        Ok(VibrationSettings::new(
-            heating, starting, puff_end, terminated,
-        ))
+        VibrationSettings::new(heating, starting, puff_end, terminated)
     }
 }
 
@@ -91,72 +203,137 @@ fn apply_changes(
 mod tests {
     use super::*;
 
+    fn changes(settings: &[&str], has_charge: bool) -> Result<Vec<(VibrationFlag, bool)>> {
+        let args: Vec<String> = std::iter::once(&"vibration")
+            .chain(settings)
+            .map(|value| (*value).to_owned())
+            .collect();
+        parse_args::<VibrationArgs>(&args)?
+            .unwrap()
+            .changes(has_charge)
+    }
+
     #[test]
     fn flag_update_on() {
-        assert_eq!(flag_update(&["heating", "on"], "heating"), Some(true));
+        assert_eq!(
+            flag_update(&[(VibrationFlag::Heating, true)], VibrationFlag::Heating),
+            Some(true)
+        );
     }
 
     #[test]
     fn flag_update_off() {
-        assert_eq!(flag_update(&["heating", "off"], "heating"), Some(false));
+        assert_eq!(
+            flag_update(&[(VibrationFlag::Heating, false)], VibrationFlag::Heating),
+            Some(false)
+        );
     }
 
     #[test]
     fn flag_update_absent_is_none() {
-        assert_eq!(flag_update(&["starting", "on"], "heating"), None);
+        assert_eq!(
+            flag_update(&[(VibrationFlag::Starting, true)], VibrationFlag::Heating),
+            None
+        );
     }
 
     #[test]
     fn flag_update_empty_is_none() {
-        assert_eq!(flag_update(&[], "heating"), None);
+        assert_eq!(flag_update(&[], VibrationFlag::Heating), None);
+    }
+
+    #[test]
+    fn changes_rejects_odd_args() {
+        assert!(changes(&["heating"], false).is_err());
+    }
+
+    #[test]
+    fn changes_rejects_unknown_key() {
+        assert!(changes(&["turbo", "on"], false).is_err());
+    }
+
+    #[test]
+    fn changes_rejects_invalid_value() {
+        assert!(changes(&["heating", "yes"], false).is_err());
+    }
+
+    #[test]
+    fn changes_accepts_valid_pairs() {
+        assert_eq!(
+            changes(&["heating", "on", "starting", "disable"], false).unwrap(),
+            vec![
+                (VibrationFlag::Heating, true),
+                (VibrationFlag::Starting, false)
+            ]
+        );
     }
 
     #[test]
-    fn validate_flags_rejects_odd_args() {
-        assert!(validate_flags(&["heating"], false).is_err());
+    fn changes_rejects_misplaced_words() {
+        let error = changes(&["heating", "puffend"], false).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("invalid value 'puffend' for 'heating'"),
+            "{error}"
+        );
+        let error = changes(&["on", "heating"], false).unwrap_err();
+        assert!(
+            error.to_string().starts_with("expected a flag before 'on'"),
+            "{error}"
+        );
     }
 
     #[test]
-    fn validate_flags_rejects_unknown_key() {
-        assert!(validate_flags(&["turbo", "on"], false).is_err());
+    fn clap_suggests_misspelled_flags() {
+        let error = changes(&["heatng", "on"], false).unwrap_err();
+        assert!(error.to_string().contains("'heating'"), "{error}");
     }
 
     #[test]
-    fn validate_flags_rejects_invalid_value() {
-        assert!(validate_flags(&["heating", "yes"], false).is_err());
+    fn changes_ignore_case() {
+        assert_eq!(
+            changes(&["Heating", "OFF"], false).unwrap(),
+            vec![(VibrationFlag::Heating, false)]
+        );
     }
 
     #[test]
-    fn validate_flags_accepts_valid_pairs() {
-        assert!(validate_flags(&["heating", "on", "starting", "off"], false).is_ok());
+    fn changes_accepts_empty() {
+        assert!(changes(&[], false).unwrap().is_empty());
     }
 
     #[test]
-    fn validate_flags_accepts_empty() {
-        assert!(validate_flags(&[], false).is_ok());
+    fn changes_rejects_charge_without_support() {
+        assert!(changes(&["charge", "on"], false).is_err());
     }
 
     #[test]
-    fn validate_flags_rejects_charge_without_support() {
-        assert!(validate_flags(&["charge", "on"], false).is_err());
+    fn changes_accepts_charge_with_support() {
+        assert!(changes(&["charge", "on"], true).is_ok());
     }
 
     #[test]
-    fn validate_flags_accepts_charge_with_support() {
-        assert!(validate_flags(&["charge", "on"], true).is_ok());
+    fn clap_rejects_option_like_values() {
+        let args: Vec<String> = ["vibration", "heating", "-badflag"]
+            .iter()
+            .map(|value| (*value).to_owned())
+            .collect();
+
+        assert!(parse_args::<VibrationArgs>(&args).is_err());
     }
 
     #[test]
     fn apply_changes_updates_only_specified_flag() {
         let current = VibrationSettings::new(true, true, false, false);
-        let result = apply_changes(current, &["heating", "off"], false).unwrap();
+        let result = apply_changes(current, &[(VibrationFlag::Heating, false)], false);
         assert_eq!(result, VibrationSettings::new(false, true, false, false));
     }
 
     #[test]
     fn apply_changes_preserves_all_when_no_args() {
         let current = VibrationSettings::new(true, false, true, false);
-        let result = apply_changes(current, &[], false).unwrap();
+        let result = apply_changes(current, &[], false);
         assert_eq!(result, current);
     }
 }
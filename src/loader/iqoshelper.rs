@@ -1,15 +1,21 @@
+use colored::Colorize;
 use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
 use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
-use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::hint::{Hint, Hinter, HistoryHinter};
 use rustyline::validate::Validator;
 use rustyline::Context;
 use rustyline::Helper;
 
+use crate::config::AppConfig;
+use crate::loader::cmds::help::command_help;
+use crate::overrides::DeviceProfile;
+
 const COMMANDS: &[&str] = &[
     "autostart",
     "battery",
     "brightness",
+    "changes",
     "device",
     "diagnosis",
     "exit",
@@ -20,23 +26,61 @@ const COMMANDS: &[&str] = &[
     "info",
     "lock",
     "quit",
+    "set",
     "smartgesture",
+    "undo",
     "unlock",
     "version",
     "vibration",
 ];
 const AUTOSTART_ARGS: &[&str] = &["on", "off", "enable", "disable", "status"];
 const BRIGHTNESS_ARGS: &[&str] = &["high", "low"];
-const DEVICE_ARGS: &[&str] = &["list", "save", "remove"];
+const DEVICE_ARGS: &[&str] = &[
+    "list",
+    "save",
+    "show",
+    "rename",
+    "set-default",
+    "remove",
+    "annotate",
+    "prune",
+    "firmware",
+    "export",
+    "import",
+];
+/// `device` subcommands whose argument is a saved label.
+const DEVICE_LABEL_ARGS: &[&str] = &[
+    "show",
+    "rename",
+    "set-default",
+    "remove",
+    "annotate",
+    "firmware",
+];
 const FLEXBATTERY_ARGS: &[&str] = &["performance", "eco", "pause"];
-const FLEXPUFF_ARGS: &[&str] = &["enable", "disable", "status"];
-const SMART_GESTURE_ARGS: &[&str] = &["enable", "disable"];
+const FLEXPUFF_ARGS: &[&str] = &["on", "off", "enable", "disable", "status"];
+const SET_ARGS: &[&str] = &[
+    "autostart=",
+    "brightness=",
+    "flexbattery=",
+    "flexbattery.pause=",
+    "flexpuff=",
+    "smartgesture=",
+    "vibration.charge=",
+    "vibration.heating=",
+    "vibration.puffend=",
+    "vibration.starting=",
+    "vibration.terminated=",
+];
+const SMART_GESTURE_ARGS: &[&str] = &["on", "off", "enable", "disable"];
 const VIBRATION_ARGS: &[&str] = &["charge", "heating", "starting", "terminated", "puffend"];
 const ON_OFF_ARGS: &[&str] = &["on", "off"];
 
 pub struct IqosHelper {
     highlighter: MatchingBracketHighlighter,
     hinter: HistoryHinter,
+    profile: Option<DeviceProfile>,
+    saved_labels: fn() -> Vec<String>,
 }
 
 impl IqosHelper {
@@ -44,8 +88,75 @@ impl IqosHelper {
         IqosHelper {
             highlighter: MatchingBracketHighlighter::new(),
             hinter: HistoryHinter {},
+            profile: None,
+            saved_labels,
+        }
+    }
+
+    pub fn with_profile(profile: DeviceProfile) -> Self {
+        IqosHelper {
+            profile: Some(profile),
+            ..Self::new()
         }
     }
+
+    fn supports_command(&self, command: &str) -> bool {
+        self.profile
+            .is_none_or(|profile| command_supported(profile, command))
+    }
+
+    fn supported_commands(&self) -> Vec<&'static str> {
+        COMMANDS
+            .iter()
+            .copied()
+            .filter(|command| self.supports_command(command))
+            .collect()
+    }
+
+    fn vibration_args(&self) -> Vec<&'static str> {
+        let has_charge = self
+            .profile
+            .is_none_or(DeviceProfile::supports_charge_start_vibration);
+
+        VIBRATION_ARGS
+            .iter()
+            .copied()
+            .filter(|flag| has_charge || *flag != "charge")
+            .collect()
+    }
+}
+
+fn command_supported(profile: DeviceProfile, command: &str) -> bool {
+    command_help(command)
+        .and_then(|help| help.capability)
+        .is_none_or(|capability| profile.supports(capability))
+}
+
+/// Hint shown to the right of the cursor.
+///
+/// History hints can be accepted with the right arrow; capability notices
+/// are display-only.
+pub struct CommandHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for CommandHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+/// Labels are re-read on every completion so `device save` in the same
+/// session is picked up immediately.
+fn saved_labels() -> Vec<String> {
+    AppConfig::load()
+        .map(|config| config.devices.into_keys().collect())
+        .unwrap_or_default()
 }
 
 fn matching_pairs(values: &[&str], prefix: &str) -> Vec<Pair> {
@@ -80,14 +191,18 @@ impl Completer for IqosHelper {
         }
 
         if args.is_empty() {
-            return Ok((0, matching_pairs(COMMANDS, "")));
+            return Ok((0, matching_pairs(&self.supported_commands(), "")));
         }
 
         if args.len() == 1 {
             let current = args[0];
             let start = pos - current.len();
 
-            return Ok((start, matching_pairs(COMMANDS, current)));
+            return Ok((start, matching_pairs(&self.supported_commands(), current)));
+        }
+
+        if !self.supports_command(args[0]) {
+            return Ok((pos, vec![]));
         }
 
         if args.len() == 2 {
@@ -101,16 +216,42 @@ impl Completer for IqosHelper {
                 "device" => matching_pairs(DEVICE_ARGS, subcmd),
                 "flexbattery" => matching_pairs(FLEXBATTERY_ARGS, subcmd),
                 "flexpuff" => matching_pairs(FLEXPUFF_ARGS, subcmd),
+                "set" => matching_pairs(SET_ARGS, subcmd),
                 "smartgesture" => matching_pairs(SMART_GESTURE_ARGS, subcmd),
-                "vibration" => matching_pairs(VIBRATION_ARGS, subcmd),
+                "vibration" => matching_pairs(&self.vibration_args(), subcmd),
                 _ => vec![],
             };
 
             return Ok((start, candidates));
         }
 
-        if args.len() == 3 && (args[0] == "vibration" || args[..2] == ["flexbattery", "pause"]) {
-            let option_value = args[2];
+        if args.len() == 3 && args[0] == "device" && DEVICE_LABEL_ARGS.contains(&args[1]) {
+            let label = args[2];
+            let start = pos - label.len();
+            let labels = (self.saved_labels)();
+            let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+
+            return Ok((start, matching_pairs(&labels, label)));
+        }
+
+        if args[0] == "set" {
+            let assignment = args[args.len() - 1];
+            let start = pos - assignment.len();
+
+            return Ok((start, matching_pairs(SET_ARGS, assignment)));
+        }
+
+        if args[0] == "vibration" && args.len().is_multiple_of(2) {
+            let flag = args[args.len() - 1];
+            let start = pos - flag.len();
+
+            return Ok((start, matching_pairs(&self.vibration_args(), flag)));
+        }
+
+        if (args[0] == "vibration" && !args.len().is_multiple_of(2))
+            || (args.len() == 3 && args[..2] == ["flexbattery", "pause"])
+        {
+            let option_value = args[args.len() - 1];
             let start = pos - option_value.len();
 
             return Ok((start, matching_pairs(ON_OFF_ARGS, option_value)));
@@ -123,10 +264,23 @@ impl Completer for IqosHelper {
 impl Helper for IqosHelper {}
 
 impl Hinter for IqosHelper {
-    type Hint = String;
+    type Hint = CommandHint;
 
     fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<Self::Hint> {
-        self.hinter.hint(line, pos, ctx)
+        if let (Some(profile), Some(command)) = (self.profile, line.split_whitespace().next()) {
+            let command = command.to_ascii_lowercase();
+            if COMMANDS.contains(&command.as_str()) && !command_supported(profile, &command) {
+                return Some(CommandHint {
+                    display: format!("  (not supported on {:?})", profile.model),
+                    completion: None,
+                });
+            }
+        }
+
+        self.hinter.hint(line, pos, ctx).map(|hint| CommandHint {
+            completion: Some(hint.clone()),
+            display: hint,
+        })
     }
 }
 
@@ -140,7 +294,7 @@ impl Highlighter for IqosHelper {
     }
 
     fn highlight_hint<'h>(&self, hint: &'h str) -> std::borrow::Cow<'h, str> {
-        self.highlighter.highlight_hint(hint)
+        std::borrow::Cow::Owned(hint.dimmed().to_string())
     }
 
     fn highlight<'l>(&self, line: &'l str, pos: usize) -> std::borrow::Cow<'l, str> {
@@ -157,11 +311,16 @@ impl Validator for IqosHelper {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::overrides::{resolve, ModelOverride};
+    use iqos::DeviceModel;
     use rustyline::completion::Completer;
     use rustyline::history::DefaultHistory;
 
     fn complete(line: &str) -> (usize, Vec<String>) {
-        let helper = IqosHelper::new();
+        complete_with(IqosHelper::new(), line)
+    }
+
+    fn complete_with(helper: IqosHelper, line: &str) -> (usize, Vec<String>) {
         let history = DefaultHistory::new();
         let ctx = Context::new(&history);
         let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
@@ -191,7 +350,20 @@ mod tests {
         let (start, candidates) = complete("device ");
 
         assert_eq!(start, "device ".len());
-        assert_eq!(candidates, vec!["list", "save", "remove"]);
+        assert_eq!(candidates, DEVICE_ARGS);
+    }
+
+    #[test]
+    fn completes_saved_labels_for_device_remove() {
+        let helper = IqosHelper {
+            saved_labels: || vec!["blackcat".to_string(), "minera".to_string()],
+            ..IqosHelper::new()
+        };
+
+        let (start, candidates) = complete_with(helper, "device remove m");
+
+        assert_eq!(start, "device remove ".len());
+        assert_eq!(candidates, vec!["minera"]);
     }
 
     #[test]
@@ -225,4 +397,82 @@ mod tests {
         assert_eq!(start, "flexbattery pause ".len());
         assert_eq!(candidates, vec!["on", "off"]);
     }
+
+    #[test]
+    fn completes_next_vibration_flag_after_value() {
+        let (start, candidates) = complete("vibration heating on p");
+
+        assert_eq!(start, "vibration heating on ".len());
+        assert_eq!(candidates, vec!["puffend"]);
+
+        let (start, candidates) = complete("vibration heating on puffend o");
+
+        assert_eq!(start, "vibration heating on puffend ".len());
+        assert_eq!(candidates, vec!["on", "off"]);
+    }
+
+    #[test]
+    fn hides_commands_unsupported_by_model() {
+        let (_, candidates) = complete_with(
+            IqosHelper::with_profile(DeviceProfile::detected(DeviceModel::IlumaOne)),
+            "",
+        );
+
+        for hidden in ["autostart", "flexbattery", "flexpuff", "smartgesture"] {
+            assert!(!candidates.contains(&hidden.to_string()), "{hidden}");
+        }
+        assert!(candidates.contains(&"vibration".to_string()));
+
+        let (_, candidates) = complete_with(
+            IqosHelper::with_profile(DeviceProfile::detected(DeviceModel::IlumaIOne)),
+            "flexbattery ",
+        );
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn hides_commands_denied_by_an_override() {
+        let denied: ModelOverride =
+            toml::from_str("local_name = \"IQOS*\"\ndeny = [\"lock\", \"vibration\"]").unwrap();
+        let profile = resolve(
+            &[denied],
+            "AA:BB:CC:DD:EE:01",
+            Some("IQOS ILUMA"),
+            DeviceModel::Iluma,
+        );
+        let (_, candidates) = complete_with(IqosHelper::with_profile(profile), "");
+
+        for hidden in ["lock", "unlock", "vibration"] {
+            assert!(!candidates.contains(&hidden.to_string()), "{hidden}");
+        }
+        assert!(candidates.contains(&"brightness".to_string()));
+    }
+
+    #[test]
+    fn omits_charge_flag_without_charge_start_support() {
+        let (_, candidates) = complete_with(
+            IqosHelper::with_profile(DeviceProfile::detected(DeviceModel::IlumaIOne)),
+            "vibration ",
+        );
+        assert!(!candidates.contains(&"charge".to_string()));
+
+        let (_, candidates) = complete_with(
+            IqosHelper::with_profile(DeviceProfile::detected(DeviceModel::IlumaI)),
+            "vibration c",
+        );
+        assert_eq!(candidates, vec!["charge"]);
+    }
+
+    #[test]
+    fn hints_unsupported_commands_without_completion() {
+        let helper = IqosHelper::with_profile(DeviceProfile::detected(DeviceModel::IlumaOne));
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+
+        let hint = helper.hint("flexpuff", "flexpuff".len(), &ctx).unwrap();
+        assert!(hint.display().contains("not supported on IlumaOne"));
+        assert_eq!(hint.completion(), None);
+
+        assert!(helper.hint("battery", "battery".len(), &ctx).is_none());
+    }
 }
@@ -1,6 +1,10 @@
+pub mod args;
 pub mod cmds;
 pub mod iqoshelper;
 pub mod parser;
+pub mod session;
+pub mod setting;
+pub mod transaction;
 
 // Re-export essential components for ease of use
 #[allow(unused_imports)]
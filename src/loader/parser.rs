@@ -7,22 +7,28 @@ use anyhow::Result;
 use iqos::{Iqos, IqosBle};
 use rustyline::error::ReadlineError;
 use rustyline::{Config, Editor};
-use tokio::sync::Mutex;
 
 use crate::config::ConnectedDevice;
 use crate::loader::cmds::command::{CommandFn, CommandRegistry};
+use crate::loader::cmds::RegisteredCommand;
 use crate::loader::iqoshelper::IqosHelper;
+use crate::loader::session::{Session, WriteOptions};
+use crate::settings::ConfirmPolicy;
 
 #[derive(Debug)]
 pub enum CommandError {
     InvalidArguments(String),
     DeviceFailure(String),
+    /// The device accepted a write but reads back a different value.
+    VerificationFailed(String),
 }
 
 impl fmt::Display for CommandError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::InvalidArguments(message) | Self::DeviceFailure(message) => f.write_str(message),
+            Self::InvalidArguments(message)
+            | Self::DeviceFailure(message)
+            | Self::VerificationFailed(message) => f.write_str(message),
         }
     }
 }
@@ -56,30 +62,23 @@ pub fn invalid_arguments(message: impl Into<String>) -> anyhow::Error {
 pub fn is_invalid_argument_message(message: &str) -> bool {
     message.starts_with("Usage:")
         || message.starts_with("Invalid option:")
-        || message.starts_with("Invalid pause value:")
-        || message.starts_with("Each flag requires")
         || message.starts_with("Unknown command:")
 }
 
 pub struct IQOSConsole {
     commands: CommandRegistry,
-    iqos: Arc<Mutex<Iqos<IqosBle>>>,
-    connected_device: Option<ConnectedDevice>,
+    session: Arc<Session>,
 }
 
 impl IQOSConsole {
     pub fn new(iqos: Iqos<IqosBle>) -> Self {
-        Self::with_connected_device(iqos, None)
+        Self::with_session(Session::new(iqos, None, None, WriteOptions::default()))
     }
 
-    pub fn with_connected_device(
-        iqos: Iqos<IqosBle>,
-        connected_device: Option<ConnectedDevice>,
-    ) -> Self {
+    pub fn with_session(session: Session) -> Self {
         Self {
             commands: HashMap::with_capacity(16),
-            iqos: Arc::new(Mutex::new(iqos)),
-            connected_device,
+            session: Arc::new(session),
         }
     }
 
@@ -89,12 +88,12 @@ impl IQOSConsole {
 
     pub async fn execute_command(&self, command: &str, args: Vec<String>) -> Result<bool> {
         if command == "device" {
-            crate::loader::cmds::device::execute(args, self.connected_device.as_ref()).await?;
+            crate::loader::cmds::device::execute(args, &self.session).await?;
             return Ok(true);
         }
 
         if let Some(cmd) = self.commands.get(command) {
-            cmd(self.iqos.clone(), args).await?;
+            cmd(self.session.clone(), args).await?;
             Ok(true)
         } else {
             Ok(false)
@@ -104,10 +103,13 @@ impl IQOSConsole {
     pub async fn run(&self) -> Result<()> {
         println!("IQOS Command Console v0.1.0");
         println!("Type 'help' to display available commands, 'exit' to quit");
+        if self.session.options().dry_run {
+            println!("Dry run: settings are read but nothing is written");
+        }
 
         let config = Config::builder().build();
         let mut rl = Editor::<IqosHelper, rustyline::history::DefaultHistory>::with_config(config)?;
-        rl.set_helper(Some(IqosHelper::new()));
+        rl.set_helper(Some(IqosHelper::with_profile(self.session.profile())));
 
         let history_path = history_file();
         if rl.load_history(&history_path).is_err() {
@@ -146,10 +148,56 @@ impl IQOSConsole {
         if let Err(e) = rl.save_history(&history_path) {
             eprintln!("Warning: could not save history: {e}");
         }
+
+        offer_rollback(&self.session, |prompt| {
+            rl.readline(prompt).unwrap_or_default()
+        })
+        .await;
         Ok(())
     }
 }
 
+/// Offer to undo every change made in `session`, asking through `ask`
+/// unless the `confirm` setting answers for the user. Used when the console
+/// or the dashboard exits.
+pub async fn offer_rollback(session: &Session, ask: impl FnOnce(&str) -> String) {
+    let count = session.changes().len();
+    if count == 0 {
+        return;
+    }
+
+    crate::loader::cmds::changes::print_changes(session);
+    let roll_back = match crate::settings::load().confirm() {
+        ConfirmPolicy::Yes => true,
+        ConfirmPolicy::No => false,
+        ConfirmPolicy::Ask => {
+            let prompt = format!("Roll back {count} change(s) made this session? [y/N] ");
+            let answer = tokio::task::block_in_place(|| ask(&prompt));
+            matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+        }
+    };
+    if !roll_back {
+        return;
+    }
+
+    let iqos = session.iqos().await;
+    loop {
+        let remaining = session.changes().len();
+        match session.undo_last(&iqos).await {
+            Ok(Some(change)) => println!("Undid {change}"),
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                // A failed restore stays in the history; stop rather than retry it forever.
+                if session.changes().len() == remaining {
+                    eprintln!("Rollback stopped; {remaining} change(s) left in place");
+                    break;
+                }
+            }
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub async fn run_console(iqos: Iqos<IqosBle>) -> Result<()> {
     let mut console = IQOSConsole::new(iqos);
@@ -157,26 +205,23 @@ pub async fn run_console(iqos: Iqos<IqosBle>) -> Result<()> {
     console.run().await
 }
 
-pub async fn run_console_with_device(iqos: Iqos<IqosBle>, device: ConnectedDevice) -> Result<()> {
-    let mut console = IQOSConsole::with_connected_device(iqos, Some(device));
+/// `label` is the saved label of the connected device, if it has one.
+pub async fn run_console_with_device(
+    iqos: Iqos<IqosBle>,
+    device: ConnectedDevice,
+    label: Option<String>,
+    options: WriteOptions,
+) -> Result<()> {
+    let mut console = IQOSConsole::with_session(Session::new(iqos, Some(device), label, options));
     register_all_commands(&mut console);
     console.run().await
 }
 
-pub async fn run_registered_command(
-    iqos: Iqos<IqosBle>,
-    command: &str,
-    args: Vec<String>,
-) -> Result<()> {
-    let mut console = IQOSConsole::new(iqos);
-    register_all_commands(&mut console);
-    match console.execute_command(command, args).await {
-        Ok(true) => Ok(()),
-        Ok(false) => {
-            Err(CommandError::invalid_arguments(format!("Unknown command: {command}")).into())
-        }
-        Err(error) => Err(CommandError::classify(error).into()),
-    }
+pub async fn run_registered_command(session: Session, command: RegisteredCommand) -> Result<()> {
+    command
+        .run(Arc::new(session))
+        .await
+        .map_err(|error| CommandError::classify(error).into())
 }
 
 fn register_all_commands(console: &mut IQOSConsole) {
@@ -194,10 +239,11 @@ fn register_all_commands(console: &mut IQOSConsole) {
     crate::loader::cmds::autostart::register_command(console);
     crate::loader::cmds::smartgesture::register_command(console);
     crate::loader::cmds::diagnosis::register_command(console);
+    crate::loader::cmds::changes::register_command(console);
+    crate::loader::cmds::undo::register_command(console);
+    crate::loader::cmds::set::register_command(console);
 }
 
 fn history_file() -> PathBuf {
-    dirs::home_dir()
-        .unwrap_or_else(std::env::temp_dir)
-        .join(".iqos_history")
+    crate::settings::load().history_file()
 }
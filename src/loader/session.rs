@@ -0,0 +1,337 @@
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use iqos::{Iqos, IqosBle};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::audit::{self, AuditRecord, AuditTarget};
+use crate::config::ConnectedDevice;
+use crate::loader::parser::CommandError;
+use crate::loader::setting::Setting;
+use crate::overrides::DeviceProfile;
+
+/// Pause before rewriting a setting that did not read back as written.
+const VERIFY_RETRY_DELAY: Duration = Duration::from_millis(300);
+
+/// How [`Session::write`] treats device writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Perform reads but print the computed settings instead of writing them.
+    pub dry_run: bool,
+    /// Read each setting back after writing it and fail if it differs.
+    pub verify: bool,
+    /// Rewrites attempted before a mismatch is reported.
+    pub verify_retries: u32,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            verify: true,
+            verify_retries: 2,
+        }
+    }
+}
+
+/// What became of one write through [`Session::write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Written {
+    /// `--dry-run` skipped the write; the planned change was printed instead.
+    Skipped,
+    /// The setting reads back as written.
+    Verified,
+    /// Written, but the device cannot report this setting, so nothing was
+    /// read back (Smart Gesture and the lock state).
+    Unverifiable,
+    /// Written without reading it back, as `--no-verify` asks.
+    Unchecked,
+}
+
+impl Written {
+    /// Whether the write reached the device.
+    pub fn is_written(self) -> bool {
+        self != Self::Skipped
+    }
+
+    /// `done`, followed by a hint when the write could not be verified.
+    pub fn report(self, done: impl fmt::Display) -> String {
+        match self {
+            Self::Unverifiable => format!("{done}: {UNVERIFIABLE}"),
+            _ => done.to_string(),
+        }
+    }
+}
+
+/// How a write the device cannot read back is reported.
+pub const UNVERIFIABLE: &str = "written (not verified: device cannot report this setting)";
+
+/// A write made during this session, kept so the console can undo it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub command: String,
+    pub previous: Option<Setting>,
+    pub new: Setting,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.previous {
+            Some(previous) => write!(f, "{}: {previous} -> {}", self.command, self.new),
+            None => write!(f, "{}: unknown -> {}", self.command, self.new),
+        }
+    }
+}
+
+/// A connected device together with what is known about which saved device
+/// it is. Shared by every command run against the connection.
+pub struct Session {
+    iqos: Mutex<Iqos<IqosBle>>,
+    device: Option<ConnectedDevice>,
+    profile: DeviceProfile,
+    target: AuditTarget,
+    options: WriteOptions,
+    changes: std::sync::Mutex<Vec<Change>>,
+}
+
+impl Session {
+    pub fn new(
+        iqos: Iqos<IqosBle>,
+        device: Option<ConnectedDevice>,
+        label: Option<String>,
+        options: WriteOptions,
+    ) -> Self {
+        let serial_number = device
+            .as_ref()
+            .and_then(|device| device.serial_number.clone())
+            .or_else(|| iqos.transport().device_info().serial_number.clone());
+
+        let profile = device.as_ref().map_or_else(
+            || DeviceProfile::detected(iqos.transport().model()),
+            DeviceProfile::of,
+        );
+
+        Self {
+            iqos: Mutex::new(iqos),
+            device,
+            profile,
+            target: AuditTarget {
+                label,
+                serial_number,
+            },
+            options,
+            changes: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    pub async fn iqos(&self) -> MutexGuard<'_, Iqos<IqosBle>> {
+        self.iqos.lock().await
+    }
+
+    pub fn device(&self) -> Option<&ConnectedDevice> {
+        self.device.as_ref()
+    }
+
+    /// The model and capabilities commands should assume, with any
+    /// `[[model_overrides]]` applied.
+    pub fn profile(&self) -> DeviceProfile {
+        self.profile
+    }
+
+    /// The saved-device label of the connected device, if it has one.
+    pub fn label(&self) -> Option<&str> {
+        self.target.label.as_deref()
+    }
+
+    pub fn options(&self) -> WriteOptions {
+        self.options
+    }
+
+    /// Write `new` to the device, verify it (see [`WriteOptions::verify`])
+    /// and record the change in the audit log. `previous` is the value read
+    /// before the write, when it can be read.
+    ///
+    /// Returns [`Written::Skipped`] when `--dry-run` skipped the write; the
+    /// planned change has been printed instead and nothing is audited.
+    pub async fn write(
+        &self,
+        iqos: &Iqos<IqosBle>,
+        command: &str,
+        previous: Option<Setting>,
+        new: Setting,
+    ) -> Result<Written> {
+        let written = self.apply(iqos, command, previous, new).await?;
+        if written.is_written() {
+            self.record_change(Change {
+                command: command.to_string(),
+                previous,
+                new,
+            });
+        }
+        Ok(written)
+    }
+
+    /// Add a change to the undo history. [`Session::write`] does this itself.
+    pub fn record_change(&self, change: Change) {
+        self.changes_mut().push(change);
+    }
+
+    /// Changes written during this session, oldest first.
+    pub fn changes(&self) -> Vec<Change> {
+        self.changes_mut().clone()
+    }
+
+    /// Restore the value the most recently changed setting had before that
+    /// change. Returns `None` when nothing has been changed.
+    ///
+    /// A change whose previous value was never read cannot be undone and is
+    /// dropped from the history; a failed restore keeps it so it can be retried.
+    pub async fn undo_last(&self, iqos: &Iqos<IqosBle>) -> Result<Option<Change>> {
+        let Some(change) = self.changes_mut().pop() else {
+            return Ok(None);
+        };
+        let Some(previous) = change.previous else {
+            bail!(
+                "Cannot undo {}: its previous value could not be read",
+                change.command
+            );
+        };
+
+        let command = format!("undo {}", change.command);
+        match self.apply(iqos, &command, Some(change.new), previous).await {
+            Ok(_) => Ok(Some(change)),
+            Err(error) => {
+                self.changes_mut().push(change);
+                Err(error)
+            }
+        }
+    }
+
+    fn changes_mut(&self) -> std::sync::MutexGuard<'_, Vec<Change>> {
+        self.changes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Like [`Session::write`], but leaves the undo history alone.
+    pub async fn apply(
+        &self,
+        iqos: &Iqos<IqosBle>,
+        command: &str,
+        previous: Option<Setting>,
+        new: Setting,
+    ) -> Result<Written> {
+        if self.options.dry_run {
+            print!("{}", dry_run_report(command, previous, new));
+            return Ok(Written::Skipped);
+        }
+
+        let result = self.write_verified(iqos, command, new).await;
+        audit::record(AuditRecord::new(
+            &self.target,
+            command,
+            previous.map(|setting| setting.to_string()),
+            Some(new.to_string()),
+            &result,
+        ));
+        result
+    }
+
+    async fn write_verified(
+        &self,
+        iqos: &Iqos<IqosBle>,
+        command: &str,
+        new: Setting,
+    ) -> Result<Written> {
+        new.write(iqos, self.profile.model).await?;
+        if !self.options.verify {
+            return Ok(Written::Unchecked);
+        }
+
+        let mut retries = 0;
+        loop {
+            let Some(actual) = new
+                .read_current(iqos, self.profile.model)
+                .await
+                .with_context(|| format!("could not read back {command} after writing it"))?
+            else {
+                return Ok(Written::Unverifiable);
+            };
+
+            if new.is_applied_in(actual) {
+                return Ok(Written::Verified);
+            }
+
+            if retries == self.options.verify_retries {
+                return Err(CommandError::VerificationFailed(format!(
+                    "Verification failed: {command} reads back {actual}, expected {new} ({} attempts)",
+                    retries + 1
+                ))
+                .into());
+            }
+
+            retries += 1;
+            tokio::time::sleep(VERIFY_RETRY_DELAY).await;
+            new.write(iqos, self.profile.model).await?;
+        }
+    }
+}
+
+fn dry_run_report(command: &str, previous: Option<Setting>, new: Setting) -> String {
+    let current = previous
+        .map(|setting| setting.to_string())
+        .unwrap_or_else(|| "unknown (not readable)".to_string());
+
+    format!("Dry run: {command} not written\n  current: {current}\n  new:     {new}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iqos::BrightnessLevel;
+
+    #[test]
+    fn describes_changes_for_the_console() {
+        let change = Change {
+            command: "brightness".to_string(),
+            previous: Some(Setting::Brightness(BrightnessLevel::High)),
+            new: Setting::Brightness(BrightnessLevel::Low),
+        };
+        assert_eq!(change.to_string(), "brightness: high -> low");
+
+        let change = Change {
+            command: "smartgesture".to_string(),
+            previous: None,
+            new: Setting::SmartGesture(true),
+        };
+        assert_eq!(change.to_string(), "smartgesture: unknown -> on");
+    }
+
+    #[test]
+    fn reports_writes_that_could_not_be_verified() {
+        assert_eq!(Written::Verified.report("Device locked"), "Device locked");
+        assert_eq!(
+            Written::Unverifiable.report("Device locked"),
+            "Device locked: written (not verified: device cannot report this setting)"
+        );
+        assert!(!Written::Skipped.is_written());
+        assert!(Written::Unchecked.is_written());
+    }
+
+    #[test]
+    fn dry_run_report_shows_current_next_to_new() {
+        assert_eq!(
+            dry_run_report(
+                "brightness",
+                Some(Setting::Brightness(BrightnessLevel::High)),
+                Setting::Brightness(BrightnessLevel::Low)
+            ),
+            "Dry run: brightness not written\n  current: high\n  new:     low\n"
+        );
+        assert_eq!(
+            dry_run_report("lock", None, Setting::Locked(true)),
+            "Dry run: lock not written\n  current: unknown (not readable)\n  new:     locked\n"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+
+use anyhow::Result;
+use iqos::{
+    BrightnessLevel, DeviceModel, FlexBatterySettings, FlexPuffSetting, Iqos, IqosBle,
+    VibrationSettings,
+};
+
+/// A device setting value that a command writes. Every state-changing
+/// command goes through [`Setting::write`] so that auditing and other
+/// cross-cutting behaviour lives in one place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Brightness(BrightnessLevel),
+    Vibration(VibrationSettings),
+    FlexPuff(bool),
+    FlexBattery(FlexBatterySettings),
+    AutoStart(bool),
+    SmartGesture(bool),
+    Locked(bool),
+}
+
+impl Setting {
+    pub async fn write(self, iqos: &Iqos<IqosBle>, model: DeviceModel) -> Result<()> {
+        match self {
+            Self::Brightness(level) => iqos.set_brightness(level).await?,
+            Self::Vibration(settings) => iqos.update_vibration_settings(model, settings).await?,
+            Self::FlexPuff(enabled) => {
+                iqos.set_flexpuff(model, FlexPuffSetting::new(enabled))
+                    .await?
+            }
+            Self::FlexBattery(settings) => iqos.set_flexbattery(model, settings).await?,
+            Self::AutoStart(enabled) => iqos.set_autostart(model, enabled).await?,
+            Self::SmartGesture(enabled) => iqos.set_smartgesture(model, enabled).await?,
+            Self::Locked(true) => iqos.lock(model).await?,
+            Self::Locked(false) => iqos.unlock(model).await?,
+        }
+        Ok(())
+    }
+
+    /// Read the device's current value of the same setting, or `None` for
+    /// settings the device cannot report (Smart Gesture and the lock state).
+    pub async fn read_current(
+        self,
+        iqos: &Iqos<IqosBle>,
+        model: DeviceModel,
+    ) -> Result<Option<Self>> {
+        Ok(Some(match self {
+            Self::Brightness(_) => Self::Brightness(iqos.read_brightness().await?),
+            Self::Vibration(_) => Self::Vibration(iqos.read_vibration_settings(model).await?),
+            Self::FlexPuff(_) => Self::FlexPuff(iqos.read_flexpuff(model).await?.is_enabled()),
+            Self::FlexBattery(_) => Self::FlexBattery(iqos.read_flexbattery(model).await?),
+            Self::AutoStart(_) => Self::AutoStart(iqos.read_autostart(model).await?),
+            Self::SmartGesture(_) | Self::Locked(_) => return Ok(None),
+        }))
+    }
+
+    /// Whether `actual`, read back after writing `self`, shows the write took
+    /// effect. Parts of the request that were left unset are not compared.
+    pub fn is_applied_in(self, actual: Self) -> bool {
+        match (self, actual) {
+            (Self::Vibration(requested), Self::Vibration(actual)) => {
+                requested.when_heating_start() == actual.when_heating_start()
+                    && requested.when_starting_to_use() == actual.when_starting_to_use()
+                    && requested.when_puff_end() == actual.when_puff_end()
+                    && requested.when_manually_terminated() == actual.when_manually_terminated()
+                    && match (
+                        requested.when_charging_start(),
+                        actual.when_charging_start(),
+                    ) {
+                        (Some(requested), Some(actual)) => requested == actual,
+                        _ => true,
+                    }
+            }
+            (Self::FlexBattery(requested), Self::FlexBattery(actual)) => {
+                requested.mode() == actual.mode()
+                    && requested
+                        .pause_mode()
+                        .is_none_or(|pause| actual.pause_mode() == Some(pause))
+            }
+            (requested, actual) => requested == actual,
+        }
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Brightness(level) => write!(f, "{level}"),
+            Self::Vibration(settings) => {
+                write!(
+                    f,
+                    "heating={} starting={} puffend={} terminated={}",
+                    on_off(settings.when_heating_start()),
+                    on_off(settings.when_starting_to_use()),
+                    on_off(settings.when_puff_end()),
+                    on_off(settings.when_manually_terminated())
+                )?;
+                if let Some(charge) = settings.when_charging_start() {
+                    write!(f, " charge={}", on_off(charge))?;
+                }
+                Ok(())
+            }
+            Self::FlexBattery(settings) => {
+                write!(f, "mode={:?}", settings.mode())?;
+                if let Some(pause) = settings.pause_mode() {
+                    write!(f, " pause={}", on_off(pause))?;
+                }
+                Ok(())
+            }
+            Self::FlexPuff(enabled) | Self::AutoStart(enabled) | Self::SmartGesture(enabled) => {
+                f.write_str(on_off(*enabled))
+            }
+            Self::Locked(true) => f.write_str("locked"),
+            Self::Locked(false) => f.write_str("unlocked"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iqos::FlexBatteryMode;
+
+    #[test]
+    fn displays_values_for_the_audit_log() {
+        assert_eq!(
+            Setting::Vibration(VibrationSettings::with_charge_start(
+                true, false, true, false, true
+            ))
+            .to_string(),
+            "heating=on starting=off puffend=on terminated=off charge=on"
+        );
+        assert_eq!(
+            Setting::FlexBattery(FlexBatterySettings::new(FlexBatteryMode::Eco, Some(false)))
+                .to_string(),
+            "mode=Eco pause=off"
+        );
+        assert_eq!(Setting::AutoStart(true).to_string(), "on");
+        assert_eq!(Setting::Locked(true).to_string(), "locked");
+    }
+
+    #[test]
+    fn compares_only_requested_parts_when_verifying() {
+        let mode_only = Setting::FlexBattery(FlexBatterySettings::new(FlexBatteryMode::Eco, None));
+        assert!(
+            mode_only.is_applied_in(Setting::FlexBattery(FlexBatterySettings::new(
+                FlexBatteryMode::Eco,
+                Some(true)
+            )))
+        );
+        assert!(
+            !mode_only.is_applied_in(Setting::FlexBattery(FlexBatterySettings::new(
+                FlexBatteryMode::Performance,
+                Some(true)
+            )))
+        );
+
+        let with_charge = Setting::Vibration(VibrationSettings::with_charge_start(
+            true, true, false, false, true,
+        ));
+        assert!(
+            with_charge.is_applied_in(Setting::Vibration(VibrationSettings::new(
+                true, true, false, false
+            )))
+        );
+        assert!(!with_charge.is_applied_in(Setting::Vibration(
+            VibrationSettings::with_charge_start(true, true, false, false, false)
+        )));
+
+        assert!(!Setting::AutoStart(true).is_applied_in(Setting::AutoStart(false)));
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+
+use anyhow::{Error, Result};
+use iqos::{Iqos, IqosBle};
+
+use crate::loader::session::{Change, Session, Written, UNVERIFIABLE};
+use crate::loader::setting::Setting;
+
+/// Several settings written as one unit: if any write fails, the settings
+/// already changed are restored to the values read before the first write.
+///
+/// Rollback is best effort. A setting whose previous value cannot be read
+/// (Smart Gesture, lock state) or whose restore also fails is reported as
+/// unknown rather than guessed at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    steps: Vec<(String, Setting)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Written (and verified, unless `--no-verify`) and kept.
+    Committed,
+    /// Written and kept, but the device cannot report the setting, so it
+    /// was not read back.
+    Unverified,
+    /// Written, then restored to its previous value after a later failure.
+    RolledBack,
+    /// The device may hold either value; the reason says why.
+    Unknown(String),
+    /// Never written, because an earlier step failed or of `--dry-run`.
+    NotApplied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepReport {
+    pub command: String,
+    pub previous: Option<Setting>,
+    pub new: Setting,
+    pub outcome: Outcome,
+}
+
+/// What happened to each setting of a [`Transaction`], in the order given.
+#[derive(Debug)]
+pub struct TransactionReport {
+    pub steps: Vec<StepReport>,
+    /// The failure that aborted the transaction, if any.
+    pub error: Option<Error>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a write; `command` names it in reports, the audit log and `undo`.
+    pub fn set(mut self, command: impl Into<String>, setting: Setting) -> Self {
+        self.steps.push((command.into(), setting));
+        self
+    }
+
+    /// Read every previous value, then write the settings in order through
+    /// `session`. Settings that stay changed are added to the session's
+    /// undo history.
+    pub async fn commit(self, session: &Session, iqos: &Iqos<IqosBle>) -> TransactionReport {
+        let mut steps = Vec::with_capacity(self.steps.len());
+        for (command, new) in self.steps {
+            let previous = new
+                .read_current(iqos, session.profile().model)
+                .await
+                .ok()
+                .flatten();
+            steps.push(StepReport {
+                command,
+                previous,
+                new,
+                outcome: Outcome::NotApplied,
+            });
+        }
+
+        let error = apply_steps(&SessionWriter { session, iqos }, &mut steps).await;
+
+        for step in &steps {
+            if !matches!(step.outcome, Outcome::NotApplied | Outcome::RolledBack) {
+                session.record_change(Change {
+                    command: step.command.clone(),
+                    previous: step.previous,
+                    new: step.new,
+                });
+            }
+        }
+
+        TransactionReport { steps, error }
+    }
+}
+
+/// Where a transaction's writes go: the session, or a stand-in in tests.
+trait Writer {
+    async fn apply(
+        &self,
+        command: &str,
+        previous: Option<Setting>,
+        new: Setting,
+    ) -> Result<Written>;
+}
+
+struct SessionWriter<'a> {
+    session: &'a Session,
+    iqos: &'a Iqos<IqosBle>,
+}
+
+impl Writer for SessionWriter<'_> {
+    async fn apply(
+        &self,
+        command: &str,
+        previous: Option<Setting>,
+        new: Setting,
+    ) -> Result<Written> {
+        self.session.apply(self.iqos, command, previous, new).await
+    }
+}
+
+/// Write the steps in order. After a failure the failed step, then the
+/// steps already written, newest first, are restored. Returns the failure.
+async fn apply_steps(writer: &impl Writer, steps: &mut [StepReport]) -> Option<Error> {
+    let mut error = None;
+    let mut applied = 0;
+    for step in steps.iter_mut() {
+        match writer.apply(&step.command, step.previous, step.new).await {
+            Ok(Written::Skipped) => {}
+            Ok(Written::Unverifiable) => {
+                step.outcome = Outcome::Unverified;
+                applied += 1;
+            }
+            Ok(_) => {
+                step.outcome = Outcome::Committed;
+                applied += 1;
+            }
+            Err(failure) => {
+                step.outcome = rollback_failed_step(writer, step, &failure).await;
+                error = Some(failure);
+                break;
+            }
+        }
+    }
+
+    if error.is_some() {
+        for step in steps[..applied].iter_mut().rev() {
+            step.outcome = rollback(writer, step).await;
+        }
+    }
+    error
+}
+
+/// Restore a setting written earlier in the transaction.
+async fn rollback(writer: &impl Writer, step: &StepReport) -> Outcome {
+    let Some(previous) = step.previous else {
+        return Outcome::Unknown(format!(
+            "left at {}: its previous value could not be read",
+            step.new
+        ));
+    };
+
+    match writer
+        .apply(
+            &format!("rollback {}", step.command),
+            Some(step.new),
+            previous,
+        )
+        .await
+    {
+        Ok(_) => Outcome::RolledBack,
+        Err(error) => Outcome::Unknown(format!("rollback failed: {error:#}")),
+    }
+}
+
+/// The failed write may still have reached the device (a dropped response,
+/// a verification mismatch), so it is restored as well when possible.
+async fn rollback_failed_step(writer: &impl Writer, step: &StepReport, failure: &Error) -> Outcome {
+    match rollback(writer, step).await {
+        Outcome::RolledBack => Outcome::RolledBack,
+        _ => Outcome::Unknown(format!("write failed: {failure:#}")),
+    }
+}
+
+impl fmt::Display for StepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Committed => match self.previous {
+                Some(previous) => write!(
+                    f,
+                    "{}: committed ({previous} -> {})",
+                    self.command, self.new
+                ),
+                None => write!(f, "{}: committed (unknown -> {})", self.command, self.new),
+            },
+            Outcome::Unverified => write!(f, "{}: {UNVERIFIABLE}", self.command),
+            Outcome::RolledBack => match self.previous {
+                Some(previous) => write!(f, "{}: rolled back to {previous}", self.command),
+                None => write!(f, "{}: rolled back", self.command),
+            },
+            Outcome::Unknown(reason) => write!(f, "{}: unknown, {reason}", self.command),
+            Outcome::NotApplied => write!(f, "{}: not applied", self.command),
+        }
+    }
+}
+
+impl TransactionReport {
+    pub fn print(&self) {
+        for step in &self.steps {
+            println!("  {step}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use futures::executor::block_on;
+    use iqos::BrightnessLevel;
+    use std::sync::Mutex;
+
+    /// Records every write and fails the commands listed in `failing`.
+    #[derive(Default)]
+    struct FakeWriter {
+        failing: Vec<&'static str>,
+        dry_run: bool,
+        log: Mutex<Vec<String>>,
+    }
+
+    impl Writer for FakeWriter {
+        async fn apply(
+            &self,
+            command: &str,
+            _previous: Option<Setting>,
+            new: Setting,
+        ) -> Result<Written> {
+            self.log.lock().unwrap().push(format!("{command} = {new}"));
+            if self.failing.contains(&command) {
+                bail!("{command} timed out");
+            }
+            Ok(match new {
+                _ if self.dry_run => Written::Skipped,
+                Setting::SmartGesture(_) | Setting::Locked(_) => Written::Unverifiable,
+                _ => Written::Verified,
+            })
+        }
+    }
+
+    fn pending(command: &str, previous: Option<Setting>, new: Setting) -> StepReport {
+        StepReport {
+            command: command.to_string(),
+            previous,
+            new,
+            outcome: Outcome::NotApplied,
+        }
+    }
+
+    fn brightness(level: BrightnessLevel) -> Setting {
+        Setting::Brightness(level)
+    }
+
+    #[test]
+    fn later_failure_rolls_back_in_reverse_order() {
+        let writer = FakeWriter {
+            failing: vec!["smartgesture"],
+            ..FakeWriter::default()
+        };
+        let mut steps = [
+            pending(
+                "brightness",
+                Some(brightness(BrightnessLevel::High)),
+                brightness(BrightnessLevel::Low),
+            ),
+            pending(
+                "autostart",
+                Some(Setting::AutoStart(false)),
+                Setting::AutoStart(true),
+            ),
+            pending(
+                "smartgesture",
+                Some(Setting::SmartGesture(false)),
+                Setting::SmartGesture(true),
+            ),
+            pending(
+                "flexpuff",
+                Some(Setting::FlexPuff(false)),
+                Setting::FlexPuff(true),
+            ),
+        ];
+
+        let error = block_on(apply_steps(&writer, &mut steps)).unwrap();
+
+        assert_eq!(error.to_string(), "smartgesture timed out");
+        assert_eq!(
+            *writer.log.lock().unwrap(),
+            vec![
+                "brightness = low",
+                "autostart = on",
+                "smartgesture = on",
+                "rollback smartgesture = off",
+                "rollback autostart = off",
+                "rollback brightness = high",
+            ]
+        );
+        let outcomes: Vec<&Outcome> = steps.iter().map(|step| &step.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                &Outcome::RolledBack,
+                &Outcome::RolledBack,
+                &Outcome::RolledBack,
+                &Outcome::NotApplied,
+            ]
+        );
+    }
+
+    #[test]
+    fn unreadable_previous_value_is_unknown() {
+        let writer = FakeWriter {
+            failing: vec!["autostart"],
+            ..FakeWriter::default()
+        };
+        let mut steps = [
+            pending("smartgesture", None, Setting::SmartGesture(true)),
+            pending(
+                "autostart",
+                Some(Setting::AutoStart(false)),
+                Setting::AutoStart(true),
+            ),
+        ];
+
+        block_on(apply_steps(&writer, &mut steps)).unwrap();
+
+        assert_eq!(
+            steps[0].outcome,
+            Outcome::Unknown("left at on: its previous value could not be read".to_string())
+        );
+        assert_eq!(steps[1].outcome, Outcome::RolledBack);
+    }
+
+    #[test]
+    fn failed_step_that_cannot_be_restored_is_unknown() {
+        let writer = FakeWriter {
+            failing: vec!["brightness", "rollback brightness"],
+            ..FakeWriter::default()
+        };
+        let mut steps = [pending(
+            "brightness",
+            Some(brightness(BrightnessLevel::High)),
+            brightness(BrightnessLevel::Low),
+        )];
+
+        block_on(apply_steps(&writer, &mut steps)).unwrap();
+
+        assert_eq!(
+            steps[0].outcome,
+            Outcome::Unknown("write failed: brightness timed out".to_string())
+        );
+    }
+
+    #[test]
+    fn unreadable_setting_is_committed_unverified() {
+        let writer = FakeWriter::default();
+        let mut steps = [
+            pending("smartgesture", None, Setting::SmartGesture(true)),
+            pending(
+                "autostart",
+                Some(Setting::AutoStart(false)),
+                Setting::AutoStart(true),
+            ),
+        ];
+
+        assert!(block_on(apply_steps(&writer, &mut steps)).is_none());
+        assert_eq!(steps[0].outcome, Outcome::Unverified);
+        assert_eq!(steps[1].outcome, Outcome::Committed);
+    }
+
+    #[test]
+    fn dry_run_applies_nothing() {
+        let writer = FakeWriter {
+            dry_run: true,
+            ..FakeWriter::default()
+        };
+        let mut steps = [pending("autostart", None, Setting::AutoStart(true))];
+
+        assert!(block_on(apply_steps(&writer, &mut steps)).is_none());
+        assert_eq!(steps[0].outcome, Outcome::NotApplied);
+    }
+
+    fn step(command: &str, previous: Option<Setting>, new: Setting, outcome: Outcome) -> String {
+        StepReport {
+            command: command.to_string(),
+            previous,
+            new,
+            outcome,
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn reports_each_outcome() {
+        let high = Setting::Brightness(BrightnessLevel::High);
+        let low = Setting::Brightness(BrightnessLevel::Low);
+
+        assert_eq!(
+            step("brightness", Some(high), low, Outcome::Committed),
+            "brightness: committed (high -> low)"
+        );
+        assert_eq!(
+            step("lock", None, Setting::Locked(true), Outcome::Unverified),
+            "lock: written (not verified: device cannot report this setting)"
+        );
+        assert_eq!(
+            step("brightness", Some(high), low, Outcome::RolledBack),
+            "brightness: rolled back to high"
+        );
+        assert_eq!(
+            step(
+                "smartgesture",
+                None,
+                Setting::SmartGesture(true),
+                Outcome::Unknown("left at on: its previous value could not be read".to_string())
+            ),
+            "smartgesture: unknown, left at on: its previous value could not be read"
+        );
+        assert_eq!(
+            step(
+                "autostart",
+                None,
+                Setting::AutoStart(false),
+                Outcome::NotApplied
+            ),
+            "autostart: not applied"
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::HashSet;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{anyhow, Context as _, Result};
+use anyhow::{anyhow, bail, Context as _, Result};
 use btleplug::api::{
     Central, CentralEvent, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter,
 };
@@ -12,23 +13,51 @@ use colored::Colorize;
 use futures::stream::StreamExt;
 use iqos::{DeviceModel, Iqos, IqosBle};
 
+mod audit;
+mod budget;
 mod cli;
+mod compare;
+mod completions;
 mod config;
+mod duration;
+mod firmware;
 mod loader;
 mod model_selector;
-
-use cli::{normalize_global_options, scan_timeout, Cli, OneShotCommand};
+mod overrides;
+mod registry;
+mod schedule;
+mod settings;
+mod state;
+mod target;
+#[cfg(test)]
+mod test_support;
+mod tui;
+
+use cli::{normalize_global_options, scan_timeout, Cli, ConfigCommand, OneShotCommand};
 use config::{
-    normalize_device_label, print_saved_devices, validate_device_label, AppConfig, ConnectedDevice,
+    normalize_device_label, print_saved_devices, shadowed_label_note, validate_device_label,
+    AppConfig, ConnectedDevice, DeviceMetadata,
+};
+use loader::cmds::device::{
+    annotate_device, export_devices, import_devices, import_mode, print_firmware_history,
+    prune_devices, record_label_change, rename_device, set_default_device, show_device,
 };
 use loader::parser::{is_invalid_argument_message, CommandError};
+use loader::session::{Session, WriteOptions};
 use loader::{run_console_with_device, run_registered_command};
 use model_selector::parse_device_model;
+use overrides::{DeviceProfile, ModelOverride};
+use settings::OutputFormat;
+use target::{PickPolicy, Pin, TargetSource};
 
 const EXIT_CONNECTION_FAILED: i32 = 1;
 const EXIT_INVALID_ARGUMENTS: i32 = 2;
 const EXIT_DEVICE_COMMAND_FAILED: i32 = 3;
 const EXIT_LABEL_NOT_FOUND: i32 = 4;
+const EXIT_VERIFICATION_FAILED: i32 = 5;
+
+/// How often `iqos scheduler` checks for due rules.
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 struct ExitError {
@@ -53,28 +82,51 @@ enum ScanTarget {
         address: String,
         cached_serial: Option<String>,
     },
+    /// A named `[[model_overrides]]` entry without an address: any device it
+    /// matches.
+    Profile(ModelOverride),
 }
 
 #[derive(Debug)]
 struct DiscoveredDevice {
     address: String,
     local_name: Option<String>,
+    rssi: Option<i16>,
 }
 
 #[derive(Debug)]
 struct ResolvedTarget {
     config: AppConfig,
     target: ScanTarget,
+    source: TargetSource,
     should_save_memory: bool,
 }
 
+/// The adapter named by the `adapter` setting, or the first one.
 async fn get_central(manager: &Manager) -> Result<Adapter> {
-    manager
-        .adapters()
-        .await?
-        .into_iter()
-        .next()
-        .context("No Bluetooth adapters found")
+    let adapters = manager.adapters().await?;
+    let Some(wanted) = settings::load().adapter() else {
+        return adapters
+            .into_iter()
+            .next()
+            .context("No Bluetooth adapters found");
+    };
+
+    let mut names = Vec::new();
+    for adapter in adapters {
+        let name = adapter.adapter_info().await.unwrap_or_default();
+        if name.to_lowercase().contains(&wanted.to_lowercase()) {
+            return Ok(adapter);
+        }
+        names.push(name);
+    }
+    if names.is_empty() {
+        bail!("No Bluetooth adapters found");
+    }
+    bail!(
+        "No Bluetooth adapter matches {wanted:?}; available: {}",
+        names.join(", ")
+    )
 }
 
 async fn prompt_for_connection(name: &str, addr: &str) -> Result<bool> {
@@ -113,6 +165,7 @@ const IQOS_CLI_ASCII_ART: &str = r"
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = std::env::args().collect();
+    settings::load().apply_color();
     let exit_code = if cli::should_use_cli(&args) {
         run_cli(args).await
     } else {
@@ -153,8 +206,23 @@ async fn run_cli(mut args: Vec<String>) -> i32 {
         return 0;
     }
 
+    target::set_verbose(cli.verbose);
+    target::set_pick_policy(cli.pick);
+    let settings = settings::load();
+    let options = WriteOptions {
+        dry_run: cli.dry_run,
+        verify: !cli.no_verify,
+        verify_retries: cli.verify_retries,
+    };
+    let target = cli.target();
     let Some(command) = cli.command else {
-        return match run_auto_connected_console(cli.model, scan_timeout(cli.timeout)).await {
+        return match run_auto_connected_console(
+            target,
+            scan_timeout(cli.timeout, &settings),
+            options,
+        )
+        .await
+        {
             Ok(()) => 0,
             Err(error) => {
                 eprintln!("Error: {:#}", error.error);
@@ -164,8 +232,9 @@ async fn run_cli(mut args: Vec<String>) -> i32 {
     };
 
     match run_one_shot(
-        cli.model,
-        scan_timeout(cli.timeout),
+        target,
+        scan_timeout(cli.timeout, &settings),
+        options,
         command.into_one_shot(),
     )
     .await
@@ -181,20 +250,23 @@ async fn run_cli(mut args: Vec<String>) -> i32 {
 async fn run_auto_connected_console(
     model_arg: Option<String>,
     timeout: Duration,
+    options: WriteOptions,
 ) -> std::result::Result<(), ExitError> {
     print_ascii_art();
 
     let ResolvedTarget {
         mut config,
         target,
+        source,
         should_save_memory,
     } = load_config_and_resolve_target(model_arg.as_deref(), true)?;
     let (iqos, device) = connect_target(&target, timeout).await?;
 
-    apply_connection_memory(&mut config, &target, &device);
-    save_connection_memory(&config, &target, should_save_memory, true)?;
+    let memory = apply_connection_memory(&mut config, &target, &source, &iqos, &device).await;
+    save_connection_memory(&memory, &target, should_save_memory, true)?;
+    let label = target_label(&target, &config, &device);
 
-    run_console_with_device(Iqos::new(iqos), device)
+    run_console_with_device(iqos, device, label, options)
         .await
         .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))
 }
@@ -202,81 +274,627 @@ async fn run_auto_connected_console(
 async fn run_one_shot(
     model_arg: Option<String>,
     timeout: Duration,
+    options: WriteOptions,
     command: OneShotCommand,
 ) -> std::result::Result<(), ExitError> {
     match command {
-        OneShotCommand::DeviceList => {
+        OneShotCommand::Completions { shell } => {
+            print!("{}", completions::script(shell));
+            Ok(())
+        }
+        OneShotCommand::Targets { labels_only } => {
+            completions::print_completion_targets(labels_only);
+            Ok(())
+        }
+        OneShotCommand::Audit { device, since } => show_audit_log(device, since),
+        OneShotCommand::Scheduler => run_scheduler(model_arg, timeout, options).await,
+        OneShotCommand::Compare { a, b, output } => {
+            let output = settings::load().output(output);
+            compare_devices(&a, &b, output, timeout, options).await
+        }
+        OneShotCommand::Config(command) => run_config_command(command, options.dry_run),
+        OneShotCommand::Tui => {
+            let session =
+                connect_session(model_arg.as_deref(), TargetSource::Flag, timeout, options).await?;
+            tui::run(Arc::new(session))
+                .await
+                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))
+        }
+        OneShotCommand::BudgetWatch { interval } => {
+            run_budget_watch(model_arg, timeout, options, &interval).await
+        }
+        OneShotCommand::DeviceList { long, tags } => {
             let config = AppConfig::load()
                 .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
-            print_saved_devices(&config);
+            print_saved_devices(&config, long, &tags);
             Ok(())
         }
+        OneShotCommand::DeviceAnnotate { label, annotation } => {
+            require_saved_label(&label)?;
+            annotate_device(&label, &annotation, options.dry_run)
+                .map_err(|error| ExitError::new(classify_command_error(&error), error))
+        }
         OneShotCommand::DeviceRemove { label } => {
             let label = normalize_device_label(&label)
                 .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
-            let mut config = AppConfig::load()
+            let config = AppConfig::load()
                 .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
-            if config
-                .remove_device(&label)
-                .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?
-            {
-                config
-                    .save()
-                    .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
-                println!("Removed device label: {label}");
-                Ok(())
-            } else {
-                Err(ExitError::new(
+            let Some(removed) = config.devices.get(&label).cloned() else {
+                return Err(ExitError::new(
                     EXIT_LABEL_NOT_FOUND,
                     anyhow!("Device label not found: {label}"),
-                ))
+                ));
+            };
+            if options.dry_run {
+                println!("Dry run: would remove {label} ({})", removed.address);
+                return Ok(());
             }
+            let result = AppConfig::update(|config| config.remove_device(&label));
+            let result = match result {
+                Ok(true) => Ok(()),
+                // Another process removed it in the meantime.
+                Ok(false) => {
+                    return Err(ExitError::new(
+                        EXIT_LABEL_NOT_FOUND,
+                        anyhow!("Device label not found: {label}"),
+                    ))
+                }
+                Err(error) => Err(error),
+            };
+            record_label_change(
+                "device remove",
+                &label,
+                removed.serial_number,
+                Some(removed.address),
+                None,
+                &result,
+            );
+            result.map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+            println!("Removed device label: {label}");
+            Ok(())
+        }
+        OneShotCommand::DeviceFirmware { label } => {
+            let label = normalize_device_label(&label)
+                .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+            let config = AppConfig::load()
+                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+            let saved = config.devices.get(&label).ok_or_else(|| {
+                ExitError::new(
+                    EXIT_LABEL_NOT_FOUND,
+                    anyhow!("Device label not found: {label}"),
+                )
+            })?;
+            print_firmware_history(&label, saved)
+                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))
+        }
+        OneShotCommand::DeviceRename { old, new } => {
+            require_saved_label(&old)?;
+            rename_device(&old, &new, options.dry_run)
+                .map_err(|error| ExitError::new(classify_command_error(&error), error))
+        }
+        OneShotCommand::DeviceSetDefault { label } => {
+            require_saved_label(&label)?;
+            set_default_device(&label, options.dry_run)
+                .map_err(|error| ExitError::new(classify_command_error(&error), error))
+        }
+        OneShotCommand::DeviceShow { label } => {
+            require_saved_label(&label)?;
+            show_device(&label)
+                .map_err(|error| ExitError::new(classify_command_error(&error), error))
+        }
+        OneShotCommand::DevicePrune { older_than } => prune_devices(&older_than, options.dry_run)
+            .map_err(|error| ExitError::new(classify_command_error(&error), error)),
+        OneShotCommand::DeviceExport { labels, no_serial } => {
+            AppConfig::load()
+                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?
+                .select_labels(&labels)
+                .map_err(|error| ExitError::new(EXIT_LABEL_NOT_FOUND, error))?;
+            export_devices(&labels, !no_serial)
+                .map_err(|error| ExitError::new(classify_command_error(&error), error))
         }
+        OneShotCommand::DeviceImport {
+            file,
+            replace,
+            prefix,
+            no_serial,
+        } => import_devices(
+            &file,
+            import_mode(replace),
+            prefix.as_deref(),
+            !no_serial,
+            options.dry_run,
+        )
+        .map_err(|error| ExitError::new(classify_command_error(&error), error)),
         OneShotCommand::DeviceSave { label } => {
             let label = validate_device_label(&label)
                 .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
             let ResolvedTarget {
-                mut config, target, ..
+                mut config,
+                target,
+                source,
+                ..
             } = load_config_and_resolve_target(model_arg.as_deref(), false)?;
             let (iqos, device) = connect_target(&target, timeout).await?;
-            apply_connection_memory(&mut config, &target, &device);
-            config
-                .save_device(label.clone(), &device)
-                .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
-            config
-                .save()
-                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+            let memory =
+                apply_connection_memory(&mut config, &target, &source, &iqos, &device).await;
+            let previous = config
+                .devices
+                .get(&label)
+                .map(|saved| saved.address.clone());
+            if options.dry_run {
+                println!(
+                    "Dry run: would save {label} as {} (currently {})",
+                    device.address,
+                    previous.as_deref().unwrap_or("unset")
+                );
+                return Ok(());
+            }
+            // Read before taking the config lock so other writers do not
+            // wait on the device.
+            let metadata = if config.is_new_label_for(&label, &device) {
+                Some(DeviceMetadata::read(&iqos, DeviceProfile::of(&device)).await)
+            } else {
+                None
+            };
+            let result = AppConfig::update(|config| {
+                memory.apply(config);
+                config.save_device(label.clone(), &device)?;
+                if let Some(metadata) = &metadata {
+                    if config.devices[&label].connect_count == 0 {
+                        config.update_saved_device_metadata(
+                            &label,
+                            &device,
+                            metadata,
+                            chrono::Local::now(),
+                        );
+                    }
+                }
+                Ok(())
+            });
+            record_label_change(
+                "device save",
+                &label,
+                device.serial_number.clone(),
+                previous,
+                Some(device.address.clone()),
+                &result,
+            );
+            result.map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
             drop(iqos);
             println!("Saved device label: {label}");
+            if let Some(note) = shadowed_label_note(&label) {
+                println!("{note}");
+            }
             Ok(())
         }
-        OneShotCommand::Registered { name, args } => {
+        OneShotCommand::Registered(command) => {
+            command
+                .validate()
+                .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
             let ResolvedTarget {
                 config: mut command_config,
                 target,
+                source,
                 should_save_memory,
             } = load_config_and_resolve_target(model_arg.as_deref(), true)?;
             let (iqos, device) = connect_target(&target, timeout).await?;
-            apply_connection_memory(&mut command_config, &target, &device);
-            save_connection_memory(&command_config, &target, should_save_memory, true)?;
+            let memory =
+                apply_connection_memory(&mut command_config, &target, &source, &iqos, &device)
+                    .await;
+            save_connection_memory(&memory, &target, should_save_memory, true)?;
+            let label = target_label(&target, &command_config, &device);
 
-            run_registered_command(Iqos::new(iqos), name, args)
+            run_registered_command(Session::new(iqos, Some(device), label, options), command)
                 .await
                 .map_err(|error| ExitError::new(classify_command_error(&error), error))
         }
     }
 }
 
+/// Keep watching the budget across disconnects: a lost connection is
+/// retried every `interval` until the process is stopped.
+async fn run_budget_watch(
+    model_arg: Option<String>,
+    timeout: Duration,
+    options: WriteOptions,
+    interval: &str,
+) -> std::result::Result<(), ExitError> {
+    let interval = duration::parse_duration(interval)
+        .and_then(|interval| {
+            interval
+                .to_std()
+                .ok()
+                .filter(|interval| !interval.is_zero())
+                .context("Invalid --interval: must be positive")
+        })
+        .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+
+    loop {
+        let ResolvedTarget {
+            mut config,
+            target,
+            source,
+            should_save_memory,
+        } = load_config_and_resolve_target(model_arg.as_deref(), true)?;
+        let (iqos, device) = match connect_target(&target, timeout).await {
+            Ok(connected) => connected,
+            Err(error) => {
+                eprintln!("Warning: {:#}; retrying in {interval:?}", error.error);
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+        };
+        let memory = apply_connection_memory(&mut config, &target, &source, &iqos, &device).await;
+        save_connection_memory(&memory, &target, should_save_memory, true)?;
+
+        let label = target_label(&target, &config, &device).ok_or_else(|| {
+            ExitError::new(
+                EXIT_LABEL_NOT_FOUND,
+                anyhow!("Budgets are set per saved device; save this device with `iqos device save <label>` first"),
+            )
+        })?;
+        let budget = config
+            .devices
+            .get(&label)
+            .and_then(|saved| saved.budget.clone())
+            .ok_or_else(|| {
+                ExitError::new(
+                    EXIT_INVALID_ARGUMENTS,
+                    anyhow!("No budget configured for {label}; add [devices.{label}.budget] to config.toml"),
+                )
+            })?;
+        budget
+            .validate()
+            .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+
+        println!("Watching the puff budget of {label}; press Ctrl-C to stop");
+        let session = Session::new(iqos, Some(device), Some(label.clone()), options);
+        if let Err(error) = budget::watch(&session, &label, &budget, interval).await {
+            eprintln!("Warning: {error:#}; reconnecting in {interval:?}");
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Run due `[[schedule]]` rules, connecting to each rule's device only when
+/// something is due. A device that is out of range is retried on the next
+/// tick, so missed runs are caught up once it is back.
+async fn run_scheduler(
+    model_arg: Option<String>,
+    timeout: Duration,
+    options: WriteOptions,
+) -> std::result::Result<(), ExitError> {
+    let config =
+        AppConfig::load().map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+    if config.schedule.is_empty() {
+        return Err(ExitError::new(
+            EXIT_INVALID_ARGUMENTS,
+            anyhow!(
+                "No [[schedule]] rules in {}",
+                config::config_file().display()
+            ),
+        ));
+    }
+    let mut rules = Vec::new();
+    for rule in config.schedule {
+        rule.validate()
+            .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+        let spec = rule.timing().expect("validated above");
+        rules.push((rule, spec));
+    }
+
+    let state_path = schedule::state_file();
+    let mut state: schedule::ScheduleState = state::load_json(&state_path)
+        .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+    println!(
+        "Scheduler running {} rule(s); press Ctrl-C to stop",
+        rules.len()
+    );
+
+    loop {
+        // Re-read each tick so runs recorded by another scheduler count. A
+        // dry run keeps its state in memory instead.
+        if !options.dry_run {
+            match state::load_json(&state_path) {
+                Ok(saved) => state = saved,
+                Err(error) => eprintln!("Warning: could not load scheduler state: {error:#}"),
+            }
+        }
+        let loaded = state.clone();
+        let now = chrono::Local::now();
+        let mut due: Vec<_> = rules
+            .iter()
+            .filter_map(|(rule, spec)| {
+                schedule::due(rule, spec, &mut state, now).map(|when| (when, rule))
+            })
+            .collect();
+        // Rules seen for the first time, then the runs that succeed below.
+        let mut updates: schedule::ScheduleState = state
+            .iter()
+            .filter(|(id, _)| !loaded.contains_key(*id))
+            .map(|(id, when)| (id.clone(), *when))
+            .collect();
+        // Oldest first, so the latest of two conflicting rules wins.
+        due.sort_by_key(|(when, _)| *when);
+
+        let mut devices: Vec<Option<String>> = Vec::new();
+        for (_, rule) in &due {
+            let device = rule.device.clone().or_else(|| model_arg.clone());
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+
+        for device in devices {
+            let runs: Vec<_> = due
+                .iter()
+                .filter(|(_, rule)| rule.device.clone().or_else(|| model_arg.clone()) == device)
+                .collect();
+            let name = device.as_deref().unwrap_or("default device");
+            let session =
+                match connect_session(device.as_deref(), TargetSource::Schedule, timeout, options)
+                    .await
+                {
+                    Ok(session) => Arc::new(session),
+                    Err(error) => {
+                        println!(
+                            "{}  {name}: not connected ({:#}); {} run(s) will be retried",
+                            log_time(),
+                            error.error,
+                            runs.len()
+                        );
+                        continue;
+                    }
+                };
+
+            for (when, rule) in runs {
+                if *when < now - chrono::Duration::minutes(1) {
+                    println!(
+                        "{}  {}: catching up on the run due at {}",
+                        log_time(),
+                        rule.id(),
+                        when.format("%Y-%m-%d %H:%M")
+                    );
+                }
+                println!(
+                    "{}  {}: running `{}` on {name}",
+                    log_time(),
+                    rule.id(),
+                    rule.command
+                );
+                let command = rule.parse_command().expect("validated above");
+                match command.run(session.clone()).await {
+                    Ok(()) => {
+                        println!("{}  {}: ok", log_time(), rule.id());
+                        state.insert(rule.id(), when.fixed_offset());
+                        updates.insert(rule.id(), when.fixed_offset());
+                    }
+                    Err(error) => println!(
+                        "{}  {}: failed: {error:#}; will be retried",
+                        log_time(),
+                        rule.id()
+                    ),
+                }
+            }
+        }
+
+        if !options.dry_run && !updates.is_empty() {
+            let result = state::update_json(&state_path, |saved: &mut schedule::ScheduleState| {
+                schedule::merge(saved, &updates);
+                Ok(())
+            });
+            if let Err(error) = result {
+                eprintln!("Warning: could not save scheduler state: {error:#}");
+            }
+        }
+        tokio::time::sleep(SCHEDULER_TICK).await;
+    }
+}
+
+fn run_config_command(command: ConfigCommand, dry_run: bool) -> std::result::Result<(), ExitError> {
+    let failed = |error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error);
+    let invalid = |error| ExitError::new(EXIT_INVALID_ARGUMENTS, error);
+    let path = config::config_file();
+
+    match command {
+        ConfigCommand::Path => println!("{}", path.display()),
+        ConfigCommand::List => {
+            let config = AppConfig::load().map_err(failed)?;
+            settings::print_settings(&config.settings);
+        }
+        ConfigCommand::Get { key } => {
+            let config = AppConfig::load().map_err(failed)?;
+            let (value, _) = config.settings.effective(&key).map_err(invalid)?;
+            println!("{value}");
+        }
+        ConfigCommand::Set { key, value } => {
+            let config = AppConfig::load().map_err(failed)?;
+            let previous = config.settings.get(&key).map_err(invalid)?;
+            let mut settings = config.settings;
+            settings.set(&key, &value).map_err(invalid)?;
+            let value = settings.get(&key).map_err(invalid)?.unwrap_or_default();
+            if dry_run {
+                println!(
+                    "Dry run: would set {key} = {value} (currently {})",
+                    previous.as_deref().unwrap_or("unset")
+                );
+                return Ok(());
+            }
+            AppConfig::update(|config| config.settings.set(&key, &value)).map_err(failed)?;
+            println!("{key} = {value}");
+        }
+        ConfigCommand::Unset { key } => {
+            let mut settings = AppConfig::load().map_err(failed)?.settings;
+            if !settings.unset(&key).map_err(invalid)? {
+                println!("{key} is not set in {}", path.display());
+                return Ok(());
+            }
+            if dry_run {
+                println!("Dry run: would unset {key}");
+                return Ok(());
+            }
+            AppConfig::update(|config| config.settings.unset(&key)).map_err(failed)?;
+            println!("Unset {key}");
+        }
+        ConfigCommand::Migrate => {
+            let (moved, kept, upgraded) = if dry_run {
+                (
+                    "Dry run: would move",
+                    "Dry run: would keep",
+                    "Dry run: would upgrade",
+                )
+            } else {
+                ("Moved", "Kept", "Upgraded")
+            };
+            match config::migrate_config(dry_run).map_err(failed)? {
+                config::Migration::Moved {
+                    from,
+                    to,
+                    backup,
+                    state_files,
+                } => {
+                    println!("{moved} {} to {}", from.display(), to.display());
+                    for name in state_files {
+                        println!("{moved} {name} along with it");
+                    }
+                    println!("{kept} the original as {}", backup.display());
+                }
+                config::Migration::Upgraded {
+                    path,
+                    from_version,
+                    backup,
+                } => {
+                    println!(
+                        "{upgraded} {} from version {from_version} to {}",
+                        path.display(),
+                        config::CONFIG_VERSION
+                    );
+                    println!("{kept} the original as {}", backup.display());
+                }
+                config::Migration::UpToDate(path) => {
+                    println!("{} is up to date", path.display());
+                }
+            }
+        }
+        ConfigCommand::Edit => {
+            // Create the file first so the editor opens it with 0600 permissions.
+            if !path.exists() {
+                AppConfig::update(|_| Ok(())).map_err(failed)?;
+            }
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string());
+            let mut words = editor.split_whitespace();
+            let program = words.next().unwrap_or("vi");
+            let status = std::process::Command::new(program)
+                .args(words)
+                .arg(&path)
+                .status()
+                .with_context(|| format!("failed to start editor {editor}"))
+                .map_err(failed)?;
+            if !status.success() {
+                return Err(failed(anyhow!("Editor {editor} exited with {status}")));
+            }
+            AppConfig::load()
+                .and_then(|config| {
+                    config.settings.validate()?;
+                    config
+                        .model_overrides
+                        .iter()
+                        .try_for_each(overrides::ModelOverride::validate)
+                })
+                .with_context(|| format!("{} is no longer valid; edit it again", path.display()))
+                .map_err(invalid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Connect to each device in turn; only one connection is open at a time.
+async fn compare_devices(
+    a: &str,
+    b: &str,
+    output: OutputFormat,
+    timeout: Duration,
+    options: WriteOptions,
+) -> std::result::Result<(), ExitError> {
+    let mut snapshots = Vec::with_capacity(2);
+    for target in [a, b] {
+        let session =
+            connect_session(Some(target), TargetSource::Compare, timeout, options).await?;
+        let iqos = session.iqos().await;
+        let device = session.device().expect("connected sessions have a device");
+        let snapshot = compare::read_snapshot(target.trim(), &iqos, device)
+            .await
+            .with_context(|| format!("failed to read {}", target.trim()))
+            .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+        snapshots.push(snapshot);
+    }
+
+    let comparison = compare::compare(&snapshots[0], &snapshots[1]);
+    match output {
+        OutputFormat::Text => print!("{}", comparison.render_text(true)),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&comparison)
+                .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?
+        ),
+    }
+    Ok(())
+}
+
+/// Connect to `device`, or the pinned or default device. `source` is where
+/// `device` came from, which decides whether it becomes the default.
+async fn connect_session(
+    device: Option<&str>,
+    source: TargetSource,
+    timeout: Duration,
+    options: WriteOptions,
+) -> std::result::Result<Session, ExitError> {
+    let ResolvedTarget {
+        mut config,
+        target,
+        source,
+        should_save_memory,
+    } = resolve_target_from(device, source, true)?;
+    let (iqos, device) = connect_target(&target, timeout).await?;
+    let memory = apply_connection_memory(&mut config, &target, &source, &iqos, &device).await;
+    save_connection_memory(&memory, &target, should_save_memory, true)?;
+    let label = target_label(&target, &config, &device);
+
+    Ok(Session::new(iqos, Some(device), label, options))
+}
+
+fn log_time() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
 fn load_config_and_resolve_target(
     model_arg: Option<&str>,
     allow_model_config_failure: bool,
 ) -> std::result::Result<ResolvedTarget, ExitError> {
-    if let Some(value) = model_arg {
+    resolve_target_from(model_arg, TargetSource::Flag, allow_model_config_failure)
+}
+
+fn resolve_target_from(
+    model_arg: Option<&str>,
+    arg_source: TargetSource,
+    allow_model_config_failure: bool,
+) -> std::result::Result<ResolvedTarget, ExitError> {
+    let pinned = match model_arg {
+        Some(value) => Some((target::parse_pin(value), arg_source)),
+        None => pinned_target()?,
+    };
+
+    if let Some((Pin::Any(value) | Pin::Model(value), source)) = &pinned {
         if let Some(model) = parse_device_model(value) {
             let (config, should_save_memory) = load_memory_config(allow_model_config_failure)?;
+            let target = ScanTarget::Model(model);
+            report_target(&target, source);
             return Ok(ResolvedTarget {
                 config,
-                target: ScanTarget::Model(model),
+                target,
+                source: source.clone(),
                 should_save_memory,
             });
         }
@@ -284,15 +902,31 @@ fn load_config_and_resolve_target(
 
     let config =
         AppConfig::load().map_err(|error| ExitError::new(EXIT_CONNECTION_FAILED, error))?;
-    let target = resolve_target(model_arg, &config)?;
+    let (target, source) = resolve_target(pinned, &config)?;
+    report_target(&target, &source);
 
     Ok(ResolvedTarget {
         config,
         target,
+        source,
         should_save_memory: true,
     })
 }
 
+/// The device pinned by `IQOS_DEVICE` or a `.iqos.toml` above the working
+/// directory, if any.
+fn pinned_target() -> std::result::Result<Option<(Pin, TargetSource)>, ExitError> {
+    let dir = std::env::current_dir().unwrap_or_default();
+    target::pinned_target(std::env::var(target::DEVICE_ENV).ok(), &dir)
+        .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))
+}
+
+fn report_target(target: &ScanTarget, source: &TargetSource) {
+    if target::verbose() {
+        eprintln!("Target: {} (from {source})", describe_target(target));
+    }
+}
+
 fn load_memory_config(allow_failure: bool) -> std::result::Result<(AppConfig, bool), ExitError> {
     match AppConfig::load() {
         Ok(config) => Ok((config, true)),
@@ -304,49 +938,101 @@ fn load_memory_config(allow_failure: bool) -> std::result::Result<(AppConfig, bo
     }
 }
 
+/// Turn a pin into a scan target. Without one the stored default device is
+/// used. A bare value is tried as a model selector, a saved label and an
+/// address, in that order; a `model:`, `label:` or `addr:` prefix picks one.
 fn resolve_target(
-    model_arg: Option<&str>,
+    pinned: Option<(Pin, TargetSource)>,
     config: &AppConfig,
-) -> std::result::Result<ScanTarget, ExitError> {
-    match model_arg {
-        Some(value) => {
-            if let Some(model) = parse_device_model(value) {
-                return Ok(ScanTarget::Model(model));
-            }
-            let label = value.trim();
-
-            let saved = config.devices.get(label).ok_or_else(|| {
-                ExitError::new(
-                    EXIT_LABEL_NOT_FOUND,
-                    anyhow!("Device label not found: {label}"),
-                )
-            })?;
+) -> std::result::Result<(ScanTarget, TargetSource), ExitError> {
+    let Some((pin, source)) = pinned else {
+        let (label, address, serial_number) = config.default_target().ok_or_else(|| {
+            ExitError::new(
+                EXIT_LABEL_NOT_FOUND,
+                anyhow!(
+                    "No default device stored. Connect interactively or use --model <DeviceModel>."
+                ),
+            )
+        })?;
+
+        return Ok((
+            ScanTarget::Address {
+                label: label.map(str::to_string),
+                address: address.to_string(),
+                cached_serial: serial_number.map(str::to_string),
+            },
+            TargetSource::Default,
+        ));
+    };
 
-            Ok(ScanTarget::Address {
-                label: Some(label.to_string()),
-                address: saved.address.clone(),
-                cached_serial: saved.serial_number.clone(),
-            })
+    let saved_label = |label: &str| {
+        let label = label.trim();
+        config.devices.get(label).map(|saved| ScanTarget::Address {
+            label: Some(label.to_string()),
+            address: saved.address.clone(),
+            cached_serial: saved.serial_number.clone(),
+        })
+    };
+    let address = |address: &str| {
+        let address = address.trim();
+        let saved = config
+            .devices
+            .iter()
+            .find(|(_, saved)| saved.address.eq_ignore_ascii_case(address));
+        ScanTarget::Address {
+            label: saved.map(|(label, _)| label.clone()),
+            address: address.to_string(),
+            cached_serial: saved.and_then(|(_, saved)| saved.serial_number.clone()),
         }
-        None => {
-            let default = config.default.as_ref().ok_or_else(|| {
-                ExitError::new(
-                    EXIT_LABEL_NOT_FOUND,
-                    anyhow!("No default device stored. Connect interactively or use --model <DeviceModel>."),
-                )
-            })?;
+    };
+    let label_not_found = |label: &str| {
+        ExitError::new(
+            EXIT_LABEL_NOT_FOUND,
+            anyhow!("Device label not found: {}", label.trim()),
+        )
+    };
 
-            Ok(ScanTarget::Address {
-                label: None,
-                address: default.address.clone(),
-                cached_serial: None,
-            })
+    let target = match &pin {
+        Pin::Model(value) => ScanTarget::Model(parse_device_model(value).ok_or_else(|| {
+            ExitError::new(
+                EXIT_INVALID_ARGUMENTS,
+                anyhow!("Unknown model selector in {source}: {value}"),
+            )
+        })?),
+        Pin::Label(label) => saved_label(label).ok_or_else(|| label_not_found(label))?,
+        Pin::Address(value) if target::looks_like_address(value.trim()) => address(value),
+        Pin::Address(value) => {
+            return Err(ExitError::new(
+                EXIT_INVALID_ARGUMENTS,
+                anyhow!("Invalid address in {source}: {value}"),
+            ))
         }
-    }
+        Pin::Profile(name) => match overrides::named(overrides::configured(), name) {
+            Some(entry) => match &entry.address {
+                Some(value) => address(value),
+                None => ScanTarget::Profile(entry.clone()),
+            },
+            None => {
+                return Err(ExitError::new(
+                    EXIT_LABEL_NOT_FOUND,
+                    anyhow!("Device profile not found: {}", name.trim()),
+                ))
+            }
+        },
+        Pin::Any(value) => match parse_device_model(value) {
+            Some(model) => ScanTarget::Model(model),
+            None => match saved_label(value) {
+                Some(target) => target,
+                None if target::looks_like_address(value.trim()) => address(value),
+                None => return Err(label_not_found(value)),
+            },
+        },
+    };
+    Ok((target, source))
 }
 
 fn save_connection_memory(
-    config: &AppConfig,
+    memory: &ConnectionMemory,
     target: &ScanTarget,
     should_save_memory: bool,
     allow_model_save_failure: bool,
@@ -355,7 +1041,10 @@ fn save_connection_memory(
         return Ok(());
     }
 
-    match config.save() {
+    match AppConfig::update(|config| {
+        memory.apply(config);
+        Ok(())
+    }) {
         Ok(()) => Ok(()),
         Err(error) if allow_model_save_failure && matches!(target, ScanTarget::Model(_)) => {
             eprintln!("Warning: could not save device config: {error:#}");
@@ -368,7 +1057,7 @@ fn save_connection_memory(
 async fn connect_target(
     target: &ScanTarget,
     timeout: Duration,
-) -> std::result::Result<(IqosBle, ConnectedDevice), ExitError> {
+) -> std::result::Result<(Iqos<IqosBle>, ConnectedDevice), ExitError> {
     let manager = Manager::new()
         .await
         .map_err(|error| ExitError::new(EXIT_CONNECTION_FAILED, error))?;
@@ -384,9 +1073,12 @@ async fn connect_target(
     let device = connected_device(&ble, discovered);
     warn_serial_mismatch(target, &device);
 
-    Ok((ble, device))
+    Ok((Iqos::new(ble), device))
 }
 
+/// Scan for the target. An address stops the scan at once; a model selector
+/// listens a little longer after the first match and then applies `--pick`
+/// when more than one device answered.
 async fn find_matching_peripheral(
     central: &Adapter,
     target: &ScanTarget,
@@ -402,57 +1094,152 @@ async fn find_matching_peripheral(
         .await
         .map_err(|error| ExitError::new(EXIT_CONNECTION_FAILED, error))?;
 
-    let result = tokio::time::timeout(timeout, async {
-        while let Some(event) = events.next().await {
-            let addr = match event {
-                CentralEvent::DeviceDiscovered(addr) | CentralEvent::DeviceUpdated(addr) => addr,
-                _ => continue,
-            };
-
-            let peripheral = match central.peripheral(&addr).await {
-                Ok(peripheral) => peripheral,
-                Err(error) => {
-                    eprintln!("Warning: could not query peripheral {addr}: {error}");
-                    continue;
-                }
-            };
-            let properties = match peripheral.properties().await {
-                Ok(properties) => properties,
-                Err(error) => {
-                    eprintln!("Warning: could not read properties for {addr}: {error}");
-                    continue;
-                }
-            };
-            let discovered = discovered_device(&addr, properties.as_ref());
+    let policy = target::pick_policy();
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut window_end = None;
+    let mut candidates: Vec<(Peripheral, DiscoveredDevice)> = Vec::new();
+    let mut stream_ended = false;
+
+    loop {
+        let until = window_end.map_or(deadline, |end: tokio::time::Instant| end.min(deadline));
+        let event = match tokio::time::timeout_at(until, events.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                stream_ended = true;
+                break;
+            }
+            Err(_) => break,
+        };
+        let addr = match event {
+            CentralEvent::DeviceDiscovered(addr) | CentralEvent::DeviceUpdated(addr) => addr,
+            _ => continue,
+        };
 
-            if target_matches(target, &discovered) {
-                return Ok::<_, anyhow::Error>(Some((peripheral, discovered)));
+        let peripheral = match central.peripheral(&addr).await {
+            Ok(peripheral) => peripheral,
+            Err(error) => {
+                eprintln!("Warning: could not query peripheral {addr}: {error}");
+                continue;
+            }
+        };
+        let properties = match peripheral.properties().await {
+            Ok(properties) => properties,
+            Err(error) => {
+                eprintln!("Warning: could not read properties for {addr}: {error}");
+                continue;
             }
+        };
+        let discovered = discovered_device(&addr, properties.as_ref());
+        if !target_matches(target, &discovered) {
+            continue;
         }
 
-        Ok::<_, anyhow::Error>(None)
-    })
-    .await;
+        match candidates
+            .iter_mut()
+            .find(|(_, seen)| seen.address == discovered.address)
+        {
+            Some(seen) => *seen = (peripheral, discovered),
+            None => candidates.push((peripheral, discovered)),
+        }
+        if matches!(target, ScanTarget::Address { .. }) || policy == PickPolicy::First {
+            break;
+        }
+        window_end.get_or_insert_with(|| tokio::time::Instant::now() + target::PICK_WINDOW);
+    }
 
     if let Err(error) = central.stop_scan().await {
         eprintln!("Warning: could not stop BLE scan: {error}");
     }
 
-    match result {
-        Ok(Ok(Some(found))) => Ok(found),
-        Ok(Ok(None)) => Err(ExitError::new(
-            EXIT_CONNECTION_FAILED,
-            anyhow!("BLE event stream ended before a matching IQOS device was found"),
-        )),
-        Ok(Err(error)) => Err(ExitError::new(EXIT_CONNECTION_FAILED, error)),
-        Err(_) => Err(ExitError::new(
+    if candidates.is_empty() {
+        return Err(ExitError::new(
             EXIT_CONNECTION_FAILED,
+            if stream_ended {
+                anyhow!("BLE event stream ended before a matching IQOS device was found")
+            } else {
+                anyhow!(
+                    "Device not found before scan timeout: {}",
+                    describe_target(target)
+                )
+            },
+        ));
+    }
+    let index = pick_candidate(target, &candidates, policy).await?;
+    Ok(candidates.swap_remove(index))
+}
+
+/// Which of several devices matching `target` to connect to.
+async fn pick_candidate(
+    target: &ScanTarget,
+    candidates: &[(Peripheral, DiscoveredDevice)],
+    policy: PickPolicy,
+) -> std::result::Result<usize, ExitError> {
+    if candidates.len() == 1 {
+        return Ok(0);
+    }
+    let devices: Vec<&DiscoveredDevice> = candidates.iter().map(|(_, device)| device).collect();
+
+    if policy != PickPolicy::Prompt {
+        let signals: Vec<Option<i16>> = devices.iter().map(|device| device.rssi).collect();
+        let index = target::strongest(&signals);
+        eprintln!(
+            "Warning: {} devices match {}; using the strongest signal, {}. Use --address or --pick prompt to choose.",
+            devices.len(),
+            describe_target(target),
+            describe_candidate(devices[index])
+        );
+        return Ok(index);
+    }
+
+    let list = devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| format!("  {}. {}", index + 1, describe_candidate(device)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !io::stdin().is_terminal() {
+        return Err(ExitError::new(
+            EXIT_INVALID_ARGUMENTS,
             anyhow!(
-                "Device not found before scan timeout: {}",
+                "{} devices match {}; pass --address with one of:\n{list}",
+                devices.len(),
                 describe_target(target)
             ),
-        )),
+        ));
     }
+
+    println!(
+        "{} devices match {}:\n{list}",
+        devices.len(),
+        describe_target(target)
+    );
+    let count = devices.len();
+    tokio::task::spawn_blocking(move || loop {
+        print!("Connect to which device? [1-{count}]: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            bail!("No device chosen");
+        }
+        if let Ok(choice @ 1..) = input.trim().parse::<usize>() {
+            if choice <= count {
+                return Ok(choice - 1);
+            }
+        }
+    })
+    .await
+    .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?
+    .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))
+}
+
+fn describe_candidate(device: &DiscoveredDevice) -> String {
+    format!(
+        "{} ({}, {})",
+        device.local_name.as_deref().unwrap_or("unknown"),
+        device.address,
+        target::describe_signal(device.rssi)
+    )
 }
 
 async fn run_interactive() -> Result<()> {
@@ -477,17 +1264,25 @@ async fn run_interactive() -> Result<()> {
                 let discovered = discovered_device(&addr, properties.as_ref());
                 let name = discovered.local_name.clone().unwrap_or_default();
 
-                if name.contains("IQOS") && !ignore_devices.contains(&discovered.address) {
+                let listed = overrides::is_listed(
+                    overrides::configured(),
+                    &discovered.address,
+                    discovered.local_name.as_deref(),
+                );
+                if (name.contains("IQOS") || listed)
+                    && !ignore_devices.contains(&discovered.address)
+                {
                     println!("Found IQOS: {name} ({})", discovered.address);
 
                     if prompt_for_connection(&name, &discovered.address).await? {
                         println!("Connecting...");
                         let ble = IqosBle::connect_and_discover(peripheral).await?;
                         let device = connected_device(&ble, discovered);
-                        remember_connected_device(&device);
                         let iqos = Iqos::new(ble);
+                        let label = remember_connected_device(&iqos, &device).await;
                         central.stop_scan().await?;
-                        run_console_with_device(iqos, device).await?;
+                        run_console_with_device(iqos, device, label, WriteOptions::default())
+                            .await?;
                         return Ok(());
                     }
 
@@ -509,18 +1304,105 @@ fn print_ascii_art() {
     println!("{}", IQOS_CLI_ASCII_ART.blue());
 }
 
-fn remember_connected_device(device: &ConnectedDevice) {
-    match AppConfig::load() {
-        Ok(mut config) => {
-            config.update_default(device);
-            if let Err(error) = config.save() {
-                eprintln!("Warning: could not save device config: {error:#}");
-            }
+/// Fail with the label-not-found exit code unless `label` is saved.
+fn require_saved_label(label: &str) -> std::result::Result<(), ExitError> {
+    let config =
+        AppConfig::load().map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+    let label = label.trim();
+    if config.devices.contains_key(label) {
+        Ok(())
+    } else {
+        Err(ExitError::new(
+            EXIT_LABEL_NOT_FOUND,
+            anyhow!("Device label not found: {label}"),
+        ))
+    }
+}
+
+/// Apply connection memory for a device chosen in the picker, as every
+/// other connection does, and return its saved label, if any.
+async fn remember_connected_device(
+    iqos: &Iqos<IqosBle>,
+    device: &ConnectedDevice,
+) -> Option<String> {
+    let mut config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Warning: could not load device config: {error:#}");
+            return None;
         }
-        Err(error) => eprintln!("Warning: could not load device config: {error:#}"),
+    };
+    let target = ScanTarget::Address {
+        label: None,
+        address: device.address.clone(),
+        cached_serial: None,
+    };
+    let memory =
+        apply_connection_memory(&mut config, &target, &TargetSource::Picker, iqos, device).await;
+    if let Err(error) = AppConfig::update(|config| {
+        memory.apply(config);
+        Ok(())
+    }) {
+        eprintln!("Warning: could not save device config: {error:#}");
+    }
+    config
+        .label_for_address(&device.address)
+        .map(str::to_string)
+}
+
+fn target_label(
+    target: &ScanTarget,
+    config: &AppConfig,
+    device: &ConnectedDevice,
+) -> Option<String> {
+    match target {
+        ScanTarget::Address {
+            label: Some(label), ..
+        } => Some(label.clone()),
+        _ => config
+            .label_for_address(&device.address)
+            .map(str::to_string),
     }
 }
 
+fn show_audit_log(
+    label: Option<String>,
+    since: Option<String>,
+) -> std::result::Result<(), ExitError> {
+    let since = since
+        .map(|value| audit::parse_since(&value, chrono::Local::now()))
+        .transpose()
+        .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+    let label = label
+        .map(|label| normalize_device_label(&label))
+        .transpose()
+        .map_err(|error| ExitError::new(EXIT_INVALID_ARGUMENTS, error))?;
+    // The config is only used to match records by serial number, so a
+    // missing or unreadable one just narrows the match to the label.
+    let serial_number = label.as_ref().and_then(|label| {
+        AppConfig::load()
+            .ok()?
+            .devices
+            .get(label)?
+            .serial_number
+            .clone()
+    });
+
+    let filter = audit::AuditFilter {
+        label,
+        serial_number,
+        since,
+    };
+    let records = audit::load_from(&audit::audit_file())
+        .map_err(|error| ExitError::new(EXIT_DEVICE_COMMAND_FAILED, error))?;
+    let records: Vec<_> = records
+        .into_iter()
+        .filter(|record| filter.matches(record))
+        .collect();
+    audit::print_records(&records);
+    Ok(())
+}
+
 fn discovered_device(
     addr: &PeripheralId,
     properties: Option<&PeripheralProperties>,
@@ -534,14 +1416,22 @@ fn discovered_device(
     DiscoveredDevice {
         address,
         local_name,
+        rssi: properties.and_then(|properties| properties.rssi),
     }
 }
 
 fn connected_device(ble: &IqosBle, discovered: DiscoveredDevice) -> ConnectedDevice {
+    let model = overrides::resolve(
+        overrides::configured(),
+        &discovered.address,
+        discovered.local_name.as_deref(),
+        ble.model(),
+    )
+    .model;
     ConnectedDevice {
         address: discovered.address,
         local_name: discovered.local_name,
-        model: ble.model(),
+        model,
         serial_number: ble.device_info().serial_number.clone(),
     }
 }
@@ -549,24 +1439,121 @@ fn connected_device(ble: &IqosBle, discovered: DiscoveredDevice) -> ConnectedDev
 fn target_matches(target: &ScanTarget, discovered: &DiscoveredDevice) -> bool {
     match target {
         ScanTarget::Model(model) => {
-            discovered
-                .local_name
-                .as_deref()
-                .map(DeviceModel::from_local_name)
-                == Some(*model)
+            overrides::scanned_model(
+                overrides::configured(),
+                &discovered.address,
+                discovered.local_name.as_deref(),
+            ) == Some(*model)
         }
         ScanTarget::Address { address, .. } => discovered.address.eq_ignore_ascii_case(address),
+        ScanTarget::Profile(entry) => {
+            entry.matches(&discovered.address, discovered.local_name.as_deref())
+        }
     }
 }
 
-fn apply_connection_memory(config: &mut AppConfig, target: &ScanTarget, device: &ConnectedDevice) {
-    config.update_default(device);
+/// What one connection changes in the config: the default device and the
+/// metadata of every saved label naming it. Kept apart from the config it was
+/// applied to so it can be replayed onto a freshly loaded one when saving.
+struct ConnectionMemory {
+    device: ConnectedDevice,
+    remember_default: bool,
+    labels: Vec<String>,
+    metadata: Option<DeviceMetadata>,
+    connected_at: chrono::DateTime<chrono::Local>,
+}
 
+impl ConnectionMemory {
+    fn apply(&self, config: &mut AppConfig) {
+        if self.remember_default {
+            config.update_default(&self.device);
+        }
+        if let Some(metadata) = &self.metadata {
+            for label in &self.labels {
+                config.update_saved_device_metadata(
+                    label,
+                    &self.device,
+                    metadata,
+                    self.connected_at,
+                );
+            }
+        }
+    }
+}
+
+/// Remember the device as the default unless it was pinned by the
+/// environment or a project file, refresh every saved label that names it
+/// and note firmware updates. Status and battery are only read when the
+/// device has a saved label or reports a serial number.
+async fn apply_connection_memory(
+    config: &mut AppConfig,
+    target: &ScanTarget,
+    source: &TargetSource,
+    iqos: &Iqos<IqosBle>,
+    device: &ConnectedDevice,
+) -> ConnectionMemory {
+    let mut labels = config.labels_for_address(&device.address);
     if let ScanTarget::Address {
         label: Some(label), ..
     } = target
     {
-        config.update_saved_device_metadata(label, device);
+        if !labels.contains(label) {
+            labels.push(label.clone());
+        }
+    }
+    let mut memory = ConnectionMemory {
+        device: device.clone(),
+        remember_default: source.remembers_default(),
+        labels,
+        metadata: None,
+        connected_at: chrono::Local::now(),
+    };
+
+    if !memory.labels.is_empty() || device.serial_number.is_some() {
+        let metadata = DeviceMetadata::read(iqos, DeviceProfile::of(device)).await;
+        note_firmware_change(
+            memory.labels.first(),
+            device,
+            &metadata,
+            memory.connected_at,
+        );
+        memory.metadata = Some(metadata);
+    }
+    memory.apply(config);
+    memory
+}
+
+/// Append new firmware to the history and tell the user it changed.
+fn note_firmware_change(
+    label: Option<&String>,
+    device: &ConnectedDevice,
+    metadata: &DeviceMetadata,
+    now: chrono::DateTime<chrono::Local>,
+) {
+    let (Some(serial_number), Some(stick_firmware)) =
+        (&device.serial_number, &metadata.stick_firmware)
+    else {
+        return;
+    };
+
+    let path = firmware::history_file();
+    let result = state::update_json(&path, |history: &mut firmware::FirmwareHistory| {
+        Ok(firmware::observe(
+            history,
+            serial_number,
+            stick_firmware,
+            metadata.holder_firmware.as_deref(),
+            now,
+        ))
+    });
+
+    match result {
+        Ok(Some(change)) => {
+            let name = label.map_or(serial_number, |label| label);
+            eprintln!("Notice: firmware of {name} changed since the last connection: {change}");
+        }
+        Ok(None) => {}
+        Err(error) => eprintln!("Warning: could not update firmware history: {error:#}"),
     }
 }
 
@@ -597,6 +1584,7 @@ fn classify_command_error(error: &anyhow::Error) -> i32 {
         return match command_error {
             CommandError::InvalidArguments(_) => EXIT_INVALID_ARGUMENTS,
             CommandError::DeviceFailure(_) => EXIT_DEVICE_COMMAND_FAILED,
+            CommandError::VerificationFailed(_) => EXIT_VERIFICATION_FAILED,
         };
     }
 
@@ -616,5 +1604,6 @@ fn describe_target(target: &ScanTarget) -> String {
             ..
         } => format!("{label} ({address})"),
         ScanTarget::Address { address, .. } => address.clone(),
+        ScanTarget::Profile(entry) => format!("profile {}", entry.name.as_deref().unwrap_or("?")),
     }
 }
@@ -1,5 +1,37 @@
 use iqos::DeviceModel;
 
+/// Canonical spellings accepted by [`parse_device_model`].
+pub const MODEL_SELECTORS: &[&str] = &[
+    "iluma",
+    "iluma-one",
+    "iluma-prime",
+    "iluma-i",
+    "iluma-i-one",
+    "iluma-i-prime",
+];
+
+/// Every known model, in the order used by the README compatibility table.
+pub const KNOWN_MODELS: &[DeviceModel] = &[
+    DeviceModel::IlumaI,
+    DeviceModel::IlumaIOne,
+    DeviceModel::IlumaIPrime,
+    DeviceModel::Iluma,
+    DeviceModel::IlumaOne,
+    DeviceModel::IlumaPrime,
+];
+
+pub fn model_display_name(model: DeviceModel) -> &'static str {
+    match model {
+        DeviceModel::IlumaI => "ILUMA i",
+        DeviceModel::IlumaIOne => "ILUMA i One",
+        DeviceModel::IlumaIPrime => "ILUMA i Prime",
+        DeviceModel::Iluma => "ILUMA",
+        DeviceModel::IlumaOne => "ILUMA ONE",
+        DeviceModel::IlumaPrime => "ILUMA Prime",
+        DeviceModel::Unknown => "Unknown",
+    }
+}
+
 pub fn parse_device_model(value: &str) -> Option<DeviceModel> {
     let normalized = value.trim().to_ascii_lowercase().replace(['_', ' '], "-");
 
@@ -35,6 +67,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn canonical_selectors_all_parse() {
+        for selector in MODEL_SELECTORS {
+            assert!(parse_device_model(selector).is_some(), "{selector}");
+        }
+    }
+
     #[test]
     fn recognizes_reserved_model_labels() {
         assert!(is_reserved_model_label("iluma i"));
@@ -0,0 +1,378 @@
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use iqos::{DeviceCapability, DeviceModel};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppConfig, ConnectedDevice};
+use crate::model_selector::{parse_device_model, MODEL_SELECTORS};
+
+/// Capability names accepted in `allow` and `deny`, after the commands they
+/// gate.
+pub const CAPABILITIES: &[(&str, DeviceCapability)] = &[
+    ("brightness", DeviceCapability::Brightness),
+    ("vibration", DeviceCapability::Vibration),
+    ("charge-vibration", DeviceCapability::ChargeStartVibration),
+    ("flexpuff", DeviceCapability::FlexPuff),
+    ("flexbattery", DeviceCapability::FlexBattery),
+    ("smartgesture", DeviceCapability::SmartGesture),
+    ("autostart", DeviceCapability::AutoStart),
+    ("lock", DeviceCapability::DeviceLock),
+];
+
+/// One `[[model_overrides]]` entry in config.toml: treat devices whose
+/// address or advertised name matches as `model`, with capabilities added
+/// or removed. The first matching entry applies.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelOverride {
+    /// Profile name, so a `.iqos.toml` can pin this entry with
+    /// `profile = "<name>"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Advertised local name; `*` matches any run of characters and `?` one
+    /// character, ignoring case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// A model selector such as `iluma-i`; the detected model otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl ModelOverride {
+    /// Name used in warnings.
+    fn id(&self) -> String {
+        match (&self.name, &self.address, &self.local_name) {
+            (Some(name), _, _) => name.clone(),
+            (None, Some(address), _) => address.clone(),
+            (None, None, Some(local_name)) => format!("\"{local_name}\""),
+            (None, None, None) => "without address or local_name".to_string(),
+        }
+    }
+
+    /// Both `address` and `local_name` must match when both are set.
+    pub fn matches(&self, address: &str, local_name: Option<&str>) -> bool {
+        let address_matches = self
+            .address
+            .as_deref()
+            .is_none_or(|wanted| wanted.trim().eq_ignore_ascii_case(address));
+        let name_matches = self.local_name.as_deref().is_none_or(|pattern| {
+            local_name.is_some_and(|name| glob_matches(pattern.trim(), name.trim()))
+        });
+        (self.address.is_some() || self.local_name.is_some()) && address_matches && name_matches
+    }
+
+    fn model(&self) -> Option<DeviceModel> {
+        self.model.as_deref().and_then(parse_device_model)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        (|| {
+            if self.address.is_none() && self.local_name.is_none() {
+                bail!("set address, local_name or both");
+            }
+            if let Some(model) = &self.model {
+                if parse_device_model(model).is_none() {
+                    bail!(
+                        "unknown model '{model}' (expected one of {})",
+                        MODEL_SELECTORS.join(", ")
+                    );
+                }
+            }
+            capability_mask(&self.allow)?;
+            capability_mask(&self.deny)?;
+            Ok(())
+        })()
+        .with_context(|| format!("Invalid model override {}", self.id()))
+    }
+}
+
+/// A device's model and capabilities once overrides are applied. Capability
+/// gates ask this instead of the detected model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub model: DeviceModel,
+    allowed: u16,
+    denied: u16,
+}
+
+impl DeviceProfile {
+    /// The model as detected, with no override.
+    pub const fn detected(model: DeviceModel) -> Self {
+        Self {
+            model,
+            allowed: 0,
+            denied: 0,
+        }
+    }
+
+    /// The profile of a connected device, whose `model` already has any
+    /// override applied.
+    pub fn of(device: &ConnectedDevice) -> Self {
+        resolve(
+            configured(),
+            &device.address,
+            device.local_name.as_deref(),
+            device.model,
+        )
+    }
+
+    /// `deny` wins over `allow`, and both win over the model.
+    pub fn supports(self, capability: DeviceCapability) -> bool {
+        let bit = capability_bit(capability);
+        if self.denied & bit != 0 {
+            false
+        } else {
+            self.allowed & bit != 0 || self.model.supports(capability)
+        }
+    }
+
+    pub fn supports_charge_start_vibration(self) -> bool {
+        self.supports(DeviceCapability::ChargeStartVibration)
+    }
+}
+
+/// Apply the first override matching the device to its detected model.
+pub fn resolve(
+    overrides: &[ModelOverride],
+    address: &str,
+    local_name: Option<&str>,
+    detected: DeviceModel,
+) -> DeviceProfile {
+    let Some(entry) = overrides
+        .iter()
+        .find(|entry| entry.matches(address, local_name))
+    else {
+        return DeviceProfile::detected(detected);
+    };
+    DeviceProfile {
+        model: entry.model().unwrap_or(detected),
+        allowed: capability_mask(&entry.allow).unwrap_or_default(),
+        denied: capability_mask(&entry.deny).unwrap_or_default(),
+    }
+}
+
+/// The model a scanned device is taken for: an override's model, otherwise
+/// the one its advertised name implies.
+pub fn scanned_model(
+    overrides: &[ModelOverride],
+    address: &str,
+    local_name: Option<&str>,
+) -> Option<DeviceModel> {
+    overrides
+        .iter()
+        .find(|entry| entry.matches(address, local_name))
+        .and_then(ModelOverride::model)
+        .or_else(|| local_name.map(DeviceModel::from_local_name))
+}
+
+/// The entry a `.iqos.toml` pins with `profile = "<name>"`; names match
+/// ignoring case, and the first entry with the name wins.
+pub fn named<'a>(overrides: &'a [ModelOverride], name: &str) -> Option<&'a ModelOverride> {
+    overrides.iter().find(|entry| {
+        entry
+            .name
+            .as_deref()
+            .is_some_and(|known| known.trim().eq_ignore_ascii_case(name.trim()))
+    })
+}
+
+/// Whether any override names the device, so it is offered even when its
+/// advertised name does not look like an IQOS.
+pub fn is_listed(overrides: &[ModelOverride], address: &str, local_name: Option<&str>) -> bool {
+    overrides
+        .iter()
+        .any(|entry| entry.matches(address, local_name))
+}
+
+/// The valid `[[model_overrides]]` from config.toml, read once. Invalid
+/// entries, and a config.toml that cannot be read, are reported and
+/// skipped.
+pub fn configured() -> &'static [ModelOverride] {
+    static CONFIGURED: OnceLock<Vec<ModelOverride>> = OnceLock::new();
+    CONFIGURED.get_or_init(|| {
+        let overrides = match AppConfig::load() {
+            Ok(config) => config.model_overrides,
+            Err(error) => {
+                eprintln!("Warning: model overrides not applied: {error:#}");
+                Vec::new()
+            }
+        };
+        overrides
+            .into_iter()
+            .filter(|entry| match entry.validate() {
+                Ok(()) => true,
+                Err(error) => {
+                    eprintln!("Warning: {error:#}");
+                    false
+                }
+            })
+            .collect()
+    })
+}
+
+fn capability_bit(capability: DeviceCapability) -> u16 {
+    CAPABILITIES
+        .iter()
+        .position(|(_, known)| *known == capability)
+        .map_or(0, |index| 1 << index)
+}
+
+fn capability_mask(names: &[String]) -> Result<u16> {
+    names.iter().try_fold(0, |mask, name| {
+        let name = name.trim().to_ascii_lowercase();
+        let (_, capability) = CAPABILITIES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .ok_or_else(|| {
+                let known: Vec<_> = CAPABILITIES.iter().map(|(known, _)| *known).collect();
+                anyhow!(
+                    "unknown capability '{name}' (expected one of {})",
+                    known.join(", ")
+                )
+            })?;
+        Ok(mask | capability_bit(*capability))
+    })
+}
+
+/// Case-insensitive glob match of the whole of `text`.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(contents: &str) -> ModelOverride {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn matches_names_and_addresses() {
+        let by_name = entry("local_name = \"IQOS*ILUMA*\"");
+        assert!(by_name.matches("AA:BB:CC:DD:EE:01", Some("iqos 4 iluma kr")));
+        assert!(!by_name.matches("AA:BB:CC:DD:EE:01", Some("ILUMA")));
+        assert!(!by_name.matches("AA:BB:CC:DD:EE:01", None));
+
+        let both = entry("address = \"aa:bb:cc:dd:ee:01\"\nlocal_name = \"IQOS ?\"");
+        assert!(both.matches("AA:BB:CC:DD:EE:01", Some("IQOS X")));
+        assert!(!both.matches("AA:BB:CC:DD:EE:02", Some("IQOS X")));
+        assert!(!both.matches("AA:BB:CC:DD:EE:01", Some("IQOS XY")));
+
+        assert!(!ModelOverride::default().matches("AA:BB:CC:DD:EE:01", Some("IQOS")));
+    }
+
+    #[test]
+    fn first_match_sets_model_and_capabilities() {
+        let overrides = [
+            entry(
+                "address = \"AA:BB:CC:DD:EE:01\"\nmodel = \"iluma-i\"\nallow = [\"lock\"]\ndeny = [\"FlexPuff\"]",
+            ),
+            entry("local_name = \"*\"\nmodel = \"iluma\""),
+        ];
+
+        let profile = resolve(
+            &overrides,
+            "AA:BB:CC:DD:EE:01",
+            Some("Stick"),
+            DeviceModel::Unknown,
+        );
+        assert_eq!(profile.model, DeviceModel::IlumaI);
+        assert!(!profile.supports(DeviceCapability::FlexPuff));
+        assert!(profile.supports(DeviceCapability::FlexBattery));
+
+        let profile = resolve(&overrides, "AA:BB:CC:DD:EE:02", None, DeviceModel::IlumaOne);
+        assert_eq!(profile, DeviceProfile::detected(DeviceModel::IlumaOne));
+        assert!(!profile.supports(DeviceCapability::AutoStart));
+
+        assert_eq!(
+            scanned_model(&overrides, "AA:BB:CC:DD:EE:02", Some("Stick")),
+            Some(DeviceModel::Iluma)
+        );
+        assert_eq!(scanned_model(&[], "AA:BB:CC:DD:EE:02", None), None);
+        assert!(is_listed(&overrides, "AA:BB:CC:DD:EE:01", None));
+    }
+
+    #[test]
+    fn finds_profiles_by_name() {
+        let overrides = [
+            entry("local_name = \"IQOS*\""),
+            entry("name = \"Rig-A\"\nlocal_name = \"IQOS ILUMA*\"\nmodel = \"iluma\""),
+        ];
+
+        assert_eq!(named(&overrides, " rig-a "), Some(&overrides[1]));
+        assert_eq!(named(&overrides, "rig-b"), None);
+        assert!(entry("name = \"rig\"")
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("rig"));
+    }
+
+    #[test]
+    fn allow_adds_capabilities_the_model_lacks() {
+        let overrides = [entry(
+            "local_name = \"IQOS ILUMA ONE*\"\nallow = [\"autostart\"]",
+        )];
+        let profile = resolve(
+            &overrides,
+            "AA:BB:CC:DD:EE:01",
+            Some("IQOS ILUMA ONE 2"),
+            DeviceModel::IlumaOne,
+        );
+        assert!(profile.supports(DeviceCapability::AutoStart));
+        assert!(profile.supports(DeviceCapability::Brightness));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_names() {
+        assert!(entry("model = \"iluma\"").validate().is_err());
+        assert!(entry("address = \"x\"\nmodel = \"glo\"")
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid model override x"));
+        assert!(format!(
+            "{:#}",
+            entry("address = \"x\"\ndeny = [\"turbo\"]")
+                .validate()
+                .unwrap_err()
+        )
+        .contains("unknown capability 'turbo'"));
+        assert!(entry("local_name = \"IQOS*\"\nallow = [\"lock\"]")
+            .validate()
+            .is_ok());
+    }
+}
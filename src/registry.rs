@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    shadowed_label_note, validate_device_label, validate_tag, AppConfig, SavedDevice,
+};
+
+/// The file written by `device export`: a `[devices]` table like the one in
+/// config.toml, holding what identifies each device and its annotations.
+/// Connection history, battery readings and budgets stay with whoever made
+/// them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub devices: BTreeMap<String, SavedDevice>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add the imported labels next to the saved ones.
+    #[default]
+    Merge,
+    /// Make the saved labels exactly the imported ones.
+    Replace,
+}
+
+/// An imported label that was left out, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub label: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, self.reason)
+    }
+}
+
+/// What an import changed, label by label.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub removed: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl ImportReport {
+    /// A note for each imported label that a bare `--model` value resolves
+    /// to a model selector instead, as `device save` prints.
+    pub fn shadowed_label_notes(&self) -> Vec<String> {
+        self.added
+            .iter()
+            .chain(&self.updated)
+            .chain(&self.unchanged)
+            .filter_map(|label| shadowed_label_note(label))
+            .collect()
+    }
+}
+
+/// The identifying and annotated part of a saved device.
+fn shareable(saved: &SavedDevice, include_serial: bool) -> SavedDevice {
+    SavedDevice {
+        address: saved.address.clone(),
+        local_name: saved.local_name.clone(),
+        model: saved.model.clone(),
+        serial_number: saved.serial_number.clone().filter(|_| include_serial),
+        product_number: saved.product_number.clone(),
+        holder_product_number: saved.holder_product_number.clone(),
+        owner: saved.owner.clone(),
+        tags: saved.tags.clone(),
+        notes: saved.notes.clone(),
+        ..SavedDevice::default()
+    }
+}
+
+/// Write `labels`, or every saved label when empty, as a registry file.
+pub fn export(config: &AppConfig, labels: &[String], include_serial: bool) -> Result<String> {
+    let mut registry = Registry::default();
+    for (label, saved) in &config.devices {
+        if labels.is_empty() || labels.contains(label) {
+            registry
+                .devices
+                .insert(label.clone(), shareable(saved, include_serial));
+        }
+    }
+    if let Some(missing) = labels
+        .iter()
+        .find(|label| !config.devices.contains_key(*label))
+    {
+        bail!("Device label not found: {missing}");
+    }
+    Ok(toml::to_string_pretty(&registry)?)
+}
+
+/// Read a registry file, prefixing and validating every label. Any invalid
+/// label or missing address rejects the whole file.
+pub fn parse(
+    contents: &str,
+    prefix: Option<&str>,
+    include_serial: bool,
+) -> Result<Vec<(String, SavedDevice)>> {
+    let registry: Registry = toml::from_str(contents).context("Invalid device registry")?;
+    let mut devices: Vec<(String, SavedDevice)> = Vec::new();
+    for (label, saved) in &registry.devices {
+        let label = validate_device_label(&format!("{}{label}", prefix.unwrap_or_default()))?;
+        if saved.address.trim().is_empty() {
+            bail!("Invalid device registry: {label} has no address");
+        }
+        for tag in &saved.tags {
+            validate_tag(tag).with_context(|| format!("Invalid device registry: {label}"))?;
+        }
+        if devices.iter().any(|(other, _)| *other == label) {
+            bail!("Invalid device registry: {label} appears more than once");
+        }
+        devices.push((label, shareable(saved, include_serial)));
+    }
+    Ok(devices)
+}
+
+/// Apply imported labels to `config`. Labels that would give one device two
+/// names, or give a saved name to another device, are skipped and reported.
+/// An updated label keeps its connection history and budget.
+pub fn import(
+    config: &mut AppConfig,
+    imported: Vec<(String, SavedDevice)>,
+    mode: ImportMode,
+) -> ImportReport {
+    let mut report = ImportReport::default();
+    let mut devices = match mode {
+        ImportMode::Merge => config.devices.clone(),
+        ImportMode::Replace => BTreeMap::new(),
+    };
+
+    for (label, device) in imported {
+        if let Some(reason) = conflict(&devices, &label, &device) {
+            report.conflicts.push(Conflict { label, reason });
+            continue;
+        }
+
+        let previous = config
+            .devices
+            .get(&label)
+            .filter(|saved| saved.address.eq_ignore_ascii_case(&device.address));
+        let merged = match previous {
+            Some(previous) => SavedDevice {
+                address: device.address,
+                local_name: device.local_name.or(previous.local_name.clone()),
+                model: device.model.or(previous.model.clone()),
+                serial_number: device.serial_number.or(previous.serial_number.clone()),
+                product_number: device.product_number.or(previous.product_number.clone()),
+                holder_product_number: device
+                    .holder_product_number
+                    .or(previous.holder_product_number.clone()),
+                owner: device.owner.or(previous.owner.clone()),
+                tags: if device.tags.is_empty() {
+                    previous.tags.clone()
+                } else {
+                    device.tags
+                },
+                notes: device.notes.or(previous.notes.clone()),
+                ..previous.clone()
+            },
+            None => device,
+        };
+
+        match config.devices.get(&label) {
+            None => report.added.push(label.clone()),
+            Some(saved) if *saved == merged => report.unchanged.push(label.clone()),
+            Some(_) => report.updated.push(label.clone()),
+        }
+        devices.insert(label, merged);
+    }
+
+    if mode == ImportMode::Replace {
+        report.removed = config
+            .devices
+            .keys()
+            .filter(|label| !devices.contains_key(*label))
+            .cloned()
+            .collect();
+    }
+    config.devices = devices;
+    report
+}
+
+/// Why `device` cannot be saved as `label` next to `devices`, if it cannot.
+fn conflict(
+    devices: &BTreeMap<String, SavedDevice>,
+    label: &str,
+    device: &SavedDevice,
+) -> Option<String> {
+    if let Some(saved) = devices.get(label) {
+        if !saved.address.eq_ignore_ascii_case(&device.address) {
+            return Some(format!("label already names {}", saved.address));
+        }
+    }
+    for (other, saved) in devices.iter().filter(|(other, _)| *other != label) {
+        if saved.address.eq_ignore_ascii_case(&device.address) {
+            return Some(format!("{} is already saved as {other}", device.address));
+        }
+        if let (Some(serial), Some(saved_serial)) = (&device.serial_number, &saved.serial_number) {
+            if serial == saved_serial {
+                return Some(format!(
+                    "serial number {serial} is already saved as {other}"
+                ));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(address: &str, serial: Option<&str>) -> SavedDevice {
+        SavedDevice {
+            address: address.to_string(),
+            serial_number: serial.map(str::to_string),
+            ..SavedDevice::default()
+        }
+    }
+
+    fn config(devices: &[(&str, SavedDevice)]) -> AppConfig {
+        AppConfig {
+            devices: devices
+                .iter()
+                .map(|(label, saved)| (label.to_string(), saved.clone()))
+                .collect(),
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn export_keeps_identity_and_can_leave_out_serials() {
+        let config = config(&[
+            (
+                "minera",
+                SavedDevice {
+                    connect_count: 4,
+                    battery_level: Some(80),
+                    ..saved("AA:BB:CC:DD:EE:01", Some("SN1"))
+                },
+            ),
+            ("blackcat", saved("AA:BB:CC:DD:EE:02", Some("SN2"))),
+        ]);
+
+        let exported = export(&config, &["minera".to_string()], false).unwrap();
+        assert_eq!(
+            exported,
+            "[devices.minera]\naddress = \"AA:BB:CC:DD:EE:01\"\n"
+        );
+
+        let exported = export(&config, &[], true).unwrap();
+        let devices = parse(&exported, None, true).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                (
+                    "blackcat".to_string(),
+                    saved("AA:BB:CC:DD:EE:02", Some("SN2"))
+                ),
+                (
+                    "minera".to_string(),
+                    saved("AA:BB:CC:DD:EE:01", Some("SN1"))
+                ),
+            ]
+        );
+
+        assert!(export(&config, &["missing".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn parse_prefixes_and_validates_labels() {
+        let contents = "[devices.a]\naddress = \"AA:BB:CC:DD:EE:01\"\n";
+        let devices = parse(contents, Some("team-"), true).unwrap();
+        assert_eq!(devices[0].0, "team-a");
+
+        let model = "[devices.iluma]\naddress = \"AA:BB:CC:DD:EE:01\"\n";
+        assert_eq!(parse(model, None, true).unwrap()[0].0, "iluma");
+
+        let prefixed = "[devices.\"tag:a\"]\naddress = \"AA:BB:CC:DD:EE:01\"\n";
+        assert!(parse(prefixed, None, true)
+            .unwrap_err()
+            .to_string()
+            .contains("selector prefix"));
+
+        let no_address = "[devices.a]\naddress = \"\"\n";
+        assert!(parse(no_address, None, true).is_err());
+    }
+
+    #[test]
+    fn merge_reports_address_and_serial_conflicts() {
+        let mut config = config(&[
+            (
+                "minera",
+                SavedDevice {
+                    connect_count: 3,
+                    ..saved("AA:BB:CC:DD:EE:01", Some("SN1"))
+                },
+            ),
+            ("blackcat", saved("AA:BB:CC:DD:EE:02", None)),
+        ]);
+
+        let report = import(
+            &mut config,
+            vec![
+                (
+                    "minera".to_string(),
+                    saved("aa:bb:cc:dd:ee:01", Some("SN1")),
+                ),
+                ("spare".to_string(), saved("AA:BB:CC:DD:EE:02", None)),
+                ("blackcat".to_string(), saved("AA:BB:CC:DD:EE:09", None)),
+                ("clone".to_string(), saved("AA:BB:CC:DD:EE:03", Some("SN1"))),
+                ("team-a".to_string(), saved("AA:BB:CC:DD:EE:04", None)),
+            ],
+            ImportMode::Merge,
+        );
+
+        assert_eq!(report.added, vec!["team-a"]);
+        assert_eq!(report.updated, vec!["minera"]);
+        let conflicts: Vec<String> = report.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            conflicts,
+            vec![
+                "spare: AA:BB:CC:DD:EE:02 is already saved as blackcat",
+                "blackcat: label already names AA:BB:CC:DD:EE:02",
+                "clone: serial number SN1 is already saved as minera",
+            ]
+        );
+        assert_eq!(config.devices.len(), 3);
+        assert_eq!(config.devices["minera"].connect_count, 3);
+    }
+
+    #[test]
+    fn replace_drops_labels_missing_from_the_file() {
+        let mut config = config(&[
+            ("minera", saved("AA:BB:CC:DD:EE:01", None)),
+            ("blackcat", saved("AA:BB:CC:DD:EE:02", None)),
+        ]);
+
+        let report = import(
+            &mut config,
+            vec![("spare".to_string(), saved("AA:BB:CC:DD:EE:02", None))],
+            ImportMode::Replace,
+        );
+
+        assert_eq!(report.added, vec!["spare"]);
+        assert_eq!(report.removed, vec!["blackcat", "minera"]);
+        assert_eq!(config.devices.keys().collect::<Vec<_>>(), vec!["spare"]);
+    }
+
+    #[test]
+    fn notes_imported_labels_that_shadow_a_model() {
+        let mut config = config(&[]);
+        let report = import(
+            &mut config,
+            vec![
+                ("iluma".to_string(), saved("AA:BB:CC:DD:EE:01", None)),
+                ("minera".to_string(), saved("AA:BB:CC:DD:EE:02", None)),
+            ],
+            ImportMode::Merge,
+        );
+
+        assert_eq!(
+            report.shadowed_label_notes(),
+            vec![shadowed_label_note("iluma").unwrap()]
+        );
+        assert!(report.shadowed_label_notes()[0].contains("label:iluma"));
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_file;
+use crate::loader::cmds::RegisteredCommand;
+
+/// One `[[schedule]]` entry in config.toml: run `command` at the times given
+/// by either `at` (plus optional `days`) or a five-field `cron` expression.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Local time of day, `HH:MM`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<String>,
+    /// Weekdays for `at` (`mon` … `sun`); every day when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// `minute hour day-of-month month day-of-week`, in local time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// A command line as typed after `iqos`, e.g. `flexbattery eco`.
+    pub command: String,
+    /// Saved label or model to run against; the default device otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+/// Parses a rule's `command` with the same clap definitions as the CLI.
+#[derive(Debug, Parser)]
+#[command(name = "iqos", no_binary_name = true)]
+struct ScheduledCommand {
+    #[command(subcommand)]
+    command: RegisteredCommand,
+}
+
+impl ScheduleRule {
+    /// Name used in logs and to remember when the rule last ran.
+    pub fn id(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let when = match (&self.cron, &self.at) {
+            (Some(cron), _) => cron.clone(),
+            (None, Some(at)) if self.days.is_empty() => at.clone(),
+            (None, Some(at)) => format!("{at} {}", self.days.join(",")),
+            (None, None) => "?".to_string(),
+        };
+        format!("{} @ {when}", self.command)
+    }
+
+    pub fn timing(&self) -> Result<CronSpec> {
+        match (&self.cron, &self.at) {
+            (Some(cron), None) if self.days.is_empty() => CronSpec::parse(cron),
+            (None, Some(at)) => {
+                let time = NaiveTime::parse_from_str(at.trim(), "%H:%M")
+                    .map_err(|_| anyhow!("invalid time '{at}' (expected HH:MM)"))?;
+                let days = if self.days.is_empty() {
+                    "*".to_string()
+                } else {
+                    self.days
+                        .iter()
+                        .map(|day| weekday(day).map(|day| day.to_string()))
+                        .collect::<Result<Vec<_>>>()?
+                        .join(",")
+                };
+                CronSpec::parse(&format!("{} {} * * {days}", time.minute(), time.hour()))
+            }
+            (Some(_), _) => bail!("use either `cron` or `at`/`days`, not both"),
+            (None, None) => bail!("needs `at` or `cron`"),
+        }
+    }
+
+    pub fn parse_command(&self) -> Result<RegisteredCommand> {
+        let command = ScheduledCommand::try_parse_from(self.command.split_whitespace())
+            .map_err(|error| crate::loader::args::clap_error(&error))?
+            .command;
+        command.validate()?;
+        Ok(command)
+    }
+
+    /// Check timing and command so mistakes surface when the scheduler starts.
+    pub fn validate(&self) -> Result<()> {
+        self.timing()
+            .and_then(|_| self.parse_command().map(|_| ()))
+            .with_context(|| format!("Invalid schedule rule {}", self.id()))
+    }
+}
+
+fn weekday(day: &str) -> Result<u32> {
+    const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    let lower = day.trim().to_ascii_lowercase();
+    DAYS.iter()
+        .position(|name| lower.starts_with(name))
+        .map(|index| index as u32)
+        .ok_or_else(|| anyhow!("invalid day '{day}' (expected mon, tue, … sun)"))
+}
+
+/// A parsed five-field cron expression. Fields accept `*`, numbers, ranges
+/// (`1-5`), lists (`1,3`) and steps (`*/15`); day of week runs 0–7 with
+/// Sunday as 0 or 7.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSpec {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    /// Whether day of month / day of week were restricted; when both are,
+    /// a day matching either one matches, as in cron. A field starting
+    /// with `*`, such as `*/2`, counts as unrestricted, also as in cron.
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronSpec {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, dom, month, dow] = fields[..] else {
+            bail!("invalid cron expression '{expression}' (expected 5 fields)");
+        };
+
+        let mut days_of_week = parse_field(dow, 0, 7)?;
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days_of_month: parse_field(dom, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            days_of_week,
+            dom_restricted: !dom.starts_with('*'),
+            dow_restricted: !dow.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        if !self.months[time.month() as usize] {
+            return false;
+        }
+        let dom = self.days_of_month[time.day() as usize];
+        let dow = self.days_of_week[time.weekday().num_days_from_sunday() as usize];
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+
+    /// The latest matching minute at or before `now`, looking back at most
+    /// a year.
+    pub fn previous(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut time = now.naive_local().with_second(0)?.with_nanosecond(0)?;
+        let oldest = time - Duration::days(366);
+
+        while time > oldest {
+            if !self.matches_day(time) {
+                time = time.date().and_hms_opt(0, 0, 0)? - Duration::minutes(1);
+            } else if !self.hours[time.hour() as usize] {
+                time = time.with_minute(0)? - Duration::minutes(1);
+            } else if !self.minutes[time.minute() as usize] {
+                time -= Duration::minutes(1);
+            } else if let Some(local) = Local.from_local_datetime(&time).earliest() {
+                return Some(local);
+            } else {
+                // Skipped by a daylight-saving change.
+                time -= Duration::minutes(1);
+            }
+        }
+
+        None
+    }
+}
+
+/// Allowed values of one cron field, indexed by value.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut allowed = vec![false; max as usize + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| anyhow!("invalid step in cron field '{field}'"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (cron_value(start, field)?, cron_value(end, field)?)
+        } else {
+            let value = cron_value(range, field)?;
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            bail!("cron field '{field}' is out of range {min}-{max}");
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+
+    Ok(allowed)
+}
+
+fn cron_value(value: &str, field: &str) -> Result<u32> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid value '{value}' in cron field '{field}'"))
+}
+
+/// When each rule last ran, keyed by [`ScheduleRule::id`]. Stores the
+/// scheduled time rather than the wall-clock time so a run that was caught
+/// up late is not repeated.
+pub type ScheduleState = BTreeMap<String, DateTime<FixedOffset>>;
+
+pub fn state_file() -> PathBuf {
+    config_file().with_file_name("scheduler.json")
+}
+
+/// The scheduled time a rule should run for now, if it has not run for it
+/// yet. Rules seen for the first time start counting from `now`, so adding
+/// a rule does not replay its past.
+pub fn due(
+    rule: &ScheduleRule,
+    spec: &CronSpec,
+    state: &mut ScheduleState,
+    now: DateTime<Local>,
+) -> Option<DateTime<Local>> {
+    let occurrence = spec.previous(now)?;
+    let last = state
+        .entry(rule.id())
+        .or_insert_with(|| occurrence.fixed_offset());
+
+    (occurrence.fixed_offset() > *last).then_some(occurrence)
+}
+
+/// Fold entries written on this tick into the state saved on disk. The later
+/// time wins, so a newer run recorded by another scheduler is kept.
+pub fn merge(saved: &mut ScheduleState, updates: &ScheduleState) {
+    for (id, when) in updates {
+        saved
+            .entry(id.clone())
+            .and_modify(|last| *last = (*last).max(*when))
+            .or_insert(*when);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 5, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn rule(toml: &str) -> ScheduleRule {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn finds_previous_cron_occurrence() {
+        let every_quarter = CronSpec::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_quarter.previous(local(10, 12, 7)),
+            Some(local(10, 12, 0))
+        );
+
+        // 2024-05-10 is a Friday; weekdays at 09:00.
+        let work = CronSpec::parse("0 9 * * 1-5").unwrap();
+        assert_eq!(work.previous(local(10, 8, 59)), Some(local(9, 9, 0)));
+        assert_eq!(work.previous(local(13, 8, 0)), Some(local(10, 9, 0)));
+
+        let sunday = CronSpec::parse("30 22 * * 7").unwrap();
+        assert_eq!(sunday.previous(local(10, 12, 0)), Some(local(5, 22, 30)));
+    }
+
+    #[test]
+    fn stepped_star_day_of_month_is_unrestricted() {
+        // Odd days that are also weekdays; 2024-05-11 is an odd Saturday.
+        let odd_weekdays = CronSpec::parse("0 22 */2 * 1-5").unwrap();
+        assert_eq!(
+            odd_weekdays.previous(local(11, 23, 0)),
+            Some(local(9, 22, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_cron() {
+        assert!(CronSpec::parse("0 9 * *").is_err());
+        assert!(CronSpec::parse("60 * * * *").is_err());
+        assert!(CronSpec::parse("*/0 * * * *").is_err());
+        assert!(CronSpec::parse("0 9 * * mon").is_err());
+    }
+
+    #[test]
+    fn converts_time_of_day_rules() {
+        let evening =
+            rule("at = \"20:30\"\ndays = [\"fri\", \"Saturday\"]\ncommand = \"brightness low\"");
+        assert_eq!(
+            evening.timing().unwrap(),
+            CronSpec::parse("30 20 * * 5,6").unwrap()
+        );
+        assert_eq!(evening.id(), "brightness low @ 20:30 fri,Saturday");
+
+        assert!(
+            rule("cron = \"0 9 * * *\"\nat = \"09:00\"\ncommand = \"lock\"")
+                .validate()
+                .is_err()
+        );
+        assert!(rule("command = \"lock\"").validate().is_err());
+    }
+
+    #[test]
+    fn rejects_misspelled_keys() {
+        let error = toml::from_str::<ScheduleRule>("at = \"22:00\"\ncomand = \"lock\"")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown field `comand`"), "{error}");
+    }
+
+    #[test]
+    fn parses_rule_commands_like_the_cli() {
+        assert!(rule("at = \"22:00\"\ncommand = \"flexbattery eco\"")
+            .validate()
+            .is_ok());
+
+        let error = rule("at = \"22:00\"\ncommand = \"flexbattery turbo\"")
+            .validate()
+            .unwrap_err();
+        assert!(
+            format!("{error:#}").contains("flexbattery turbo"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn runs_each_occurrence_once_and_catches_up() {
+        let lock = rule("at = \"09:00\"\ncommand = \"lock\"");
+        let spec = lock.timing().unwrap();
+        let mut state = ScheduleState::new();
+
+        // First sight only records where to count from.
+        assert_eq!(due(&lock, &spec, &mut state, local(10, 12, 0)), None);
+        assert_eq!(due(&lock, &spec, &mut state, local(11, 8, 0)), None);
+        // Out of range all morning: the 09:00 run is still due at 11:00.
+        assert_eq!(
+            due(&lock, &spec, &mut state, local(11, 11, 0)),
+            Some(local(11, 9, 0))
+        );
+        state.insert(lock.id(), local(11, 9, 0).fixed_offset());
+        assert_eq!(due(&lock, &spec, &mut state, local(11, 11, 1)), None);
+    }
+
+    #[test]
+    fn merge_keeps_the_later_run_and_other_rules() {
+        let mut saved = ScheduleState::from([
+            ("night eco".to_string(), local(9, 22, 0).fixed_offset()),
+            ("lock".to_string(), local(10, 23, 0).fixed_offset()),
+        ]);
+        let updates = ScheduleState::from([
+            ("night eco".to_string(), local(10, 22, 0).fixed_offset()),
+            ("lock".to_string(), local(9, 23, 0).fixed_offset()),
+            ("new".to_string(), local(10, 8, 0).fixed_offset()),
+        ]);
+
+        merge(&mut saved, &updates);
+
+        assert_eq!(
+            saved,
+            ScheduleState::from([
+                ("night eco".to_string(), local(10, 22, 0).fixed_offset()),
+                ("lock".to_string(), local(10, 23, 0).fixed_offset()),
+                ("new".to_string(), local(10, 8, 0).fixed_offset()),
+            ])
+        );
+    }
+}
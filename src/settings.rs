@@ -0,0 +1,438 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+use crate::loader::parser::invalid_arguments;
+
+/// The `[settings]` table of `config.toml`. Every key is optional; an unset
+/// key falls back to its environment variable, then to the built-in default.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// BLE scan timeout in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorMode>,
+    /// Bluetooth adapter to scan with, matched against the adapter name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<ConfirmPolicy>,
+    /// Console command history file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Let the terminal and `NO_COLOR`/`CLICOLOR` decide.
+    Auto,
+    Always,
+    Never,
+}
+
+/// How questions such as the console's rollback offer are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmPolicy {
+    /// Prompt and wait for an answer.
+    Ask,
+    /// Answer yes without prompting.
+    Yes,
+    /// Answer no without prompting.
+    No,
+}
+
+/// Where an effective setting value came from, highest precedence first.
+/// Command-line flags, which beat all of these, are not tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Env(&'static str),
+    Config,
+    Default,
+}
+
+struct Key {
+    name: &'static str,
+    env: &'static str,
+    default: &'static str,
+    about: &'static str,
+}
+
+const KEYS: &[Key] = &[
+    Key {
+        name: "timeout",
+        env: "IQOS_SCAN_TIMEOUT",
+        default: "10",
+        about: "BLE scan timeout in seconds",
+    },
+    Key {
+        name: "output",
+        env: "IQOS_OUTPUT",
+        default: "text",
+        about: "Output format of commands that support --output (text, json)",
+    },
+    Key {
+        name: "color",
+        env: "IQOS_COLOR",
+        default: "auto",
+        about: "Colored output (auto, always, never)",
+    },
+    Key {
+        name: "adapter",
+        env: "IQOS_ADAPTER",
+        default: "",
+        about: "Bluetooth adapter to use, matched against its name; first adapter when empty",
+    },
+    Key {
+        name: "confirm",
+        env: "IQOS_CONFIRM",
+        default: "ask",
+        about: "How confirmation prompts are answered (ask, yes, no)",
+    },
+    Key {
+        name: "history_file",
+        env: "IQOS_HISTORY_FILE",
+        default: "~/.iqos_history",
+        about: "Console command history file",
+    },
+];
+
+/// Settings from `config.toml`. An unreadable config counts as empty here;
+/// the commands that need the rest of the file report the error.
+pub fn load() -> Settings {
+    AppConfig::load()
+        .map(|config| config.settings)
+        .unwrap_or_default()
+}
+
+fn key(name: &str) -> Result<&'static Key> {
+    KEYS.iter().find(|key| key.name == name).ok_or_else(|| {
+        let names: Vec<_> = KEYS.iter().map(|key| key.name).collect();
+        invalid_arguments(format!(
+            "Unknown setting: {name} (expected one of {})",
+            names.join(", ")
+        ))
+    })
+}
+
+/// Read `name` from the environment, ignoring values that do not parse.
+fn env<T>(name: &str, parse: fn(&str) -> Result<T>) -> Option<T> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| parse(value.trim()).ok())
+}
+
+fn parse_timeout(value: &str) -> Result<u64> {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(seconds),
+        _ => Err(invalid_arguments(format!(
+            "Invalid timeout: {value} (expected a positive number of seconds)"
+        ))),
+    }
+}
+
+fn parse_enum<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|_| {
+        let expected: Vec<_> = T::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        invalid_arguments(format!(
+            "Invalid {key}: {value} (expected one of {})",
+            expected.join(", ")
+        ))
+    })
+}
+
+fn parse_output(value: &str) -> Result<OutputFormat> {
+    parse_enum("output", value)
+}
+
+fn parse_color(value: &str) -> Result<ColorMode> {
+    parse_enum("color", value)
+}
+
+fn parse_confirm(value: &str) -> Result<ConfirmPolicy> {
+    parse_enum("confirm", value)
+}
+
+fn non_empty(key: &str, value: &str) -> Result<String> {
+    if value.is_empty() {
+        return Err(invalid_arguments(format!(
+            "Invalid {key}: value must not be empty"
+        )));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_adapter(value: &str) -> Result<String> {
+    non_empty("adapter", value)
+}
+
+fn parse_history_file(value: &str) -> Result<PathBuf> {
+    non_empty("history_file", value).map(|path| expand_home(&path))
+}
+
+/// Expand a leading `~/` so paths in config.toml can be written portably.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Settings {
+    pub fn scan_timeout(&self, cli: Option<u64>) -> Duration {
+        let seconds = cli
+            .or_else(|| env("IQOS_SCAN_TIMEOUT", parse_timeout))
+            .or(self.timeout)
+            .unwrap_or(10);
+        Duration::from_secs(seconds)
+    }
+
+    pub fn output(&self, cli: Option<OutputFormat>) -> OutputFormat {
+        cli.or_else(|| env("IQOS_OUTPUT", parse_output))
+            .or(self.output)
+            .unwrap_or(OutputFormat::Text)
+    }
+
+    pub fn color(&self) -> ColorMode {
+        env("IQOS_COLOR", parse_color)
+            .or(self.color)
+            .unwrap_or(ColorMode::Auto)
+    }
+
+    pub fn adapter(&self) -> Option<String> {
+        env("IQOS_ADAPTER", parse_adapter).or_else(|| self.adapter.clone())
+    }
+
+    pub fn confirm(&self) -> ConfirmPolicy {
+        env("IQOS_CONFIRM", parse_confirm)
+            .or(self.confirm)
+            .unwrap_or(ConfirmPolicy::Ask)
+    }
+
+    pub fn history_file(&self) -> PathBuf {
+        env("IQOS_HISTORY_FILE", parse_history_file)
+            .or_else(|| self.history_file.clone())
+            .unwrap_or_else(|| {
+                dirs::home_dir()
+                    .unwrap_or_else(std::env::temp_dir)
+                    .join(".iqos_history")
+            })
+    }
+
+    /// Force colors on or off unless left to the terminal.
+    pub fn apply_color(&self) {
+        match self.color() {
+            ColorMode::Auto => {}
+            ColorMode::Always => colored::control::set_override(true),
+            ColorMode::Never => colored::control::set_override(false),
+        }
+    }
+
+    /// The value stored in config.toml, if any.
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(match key(name)?.name {
+            "timeout" => self.timeout.map(|seconds| seconds.to_string()),
+            "output" => self.output.map(|value| enum_name(&value)),
+            "color" => self.color.map(|value| enum_name(&value)),
+            "adapter" => self.adapter.clone(),
+            "confirm" => self.confirm.map(|value| enum_name(&value)),
+            "history_file" => self
+                .history_file
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            _ => unreachable!("every key is handled"),
+        })
+    }
+
+    /// Validate `value` and store it.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        match key(name)?.name {
+            "timeout" => self.timeout = Some(parse_timeout(value)?),
+            "output" => self.output = Some(parse_output(value)?),
+            "color" => self.color = Some(parse_color(value)?),
+            "adapter" => self.adapter = Some(parse_adapter(value)?),
+            "confirm" => self.confirm = Some(parse_confirm(value)?),
+            "history_file" => self.history_file = Some(parse_history_file(value)?),
+            _ => unreachable!("every key is handled"),
+        }
+        Ok(())
+    }
+
+    /// Remove a stored value; returns whether one was set.
+    pub fn unset(&mut self, name: &str) -> Result<bool> {
+        let was_set = self.get(name)?.is_some();
+        match key(name)?.name {
+            "timeout" => self.timeout = None,
+            "output" => self.output = None,
+            "color" => self.color = None,
+            "adapter" => self.adapter = None,
+            "confirm" => self.confirm = None,
+            "history_file" => self.history_file = None,
+            _ => unreachable!("every key is handled"),
+        }
+        Ok(was_set)
+    }
+
+    /// The value in effect without CLI flags, and where it came from.
+    pub fn effective(&self, name: &str) -> Result<(String, Source)> {
+        let key = key(name)?;
+        if let Ok(value) = std::env::var(key.env) {
+            let value = value.trim().to_string();
+            if Settings::default().set(key.name, &value).is_ok() {
+                return Ok((value, Source::Env(key.env)));
+            }
+        }
+        Ok(match self.get(key.name)? {
+            Some(value) => (value, Source::Config),
+            None => (key.default.to_string(), Source::Default),
+        })
+    }
+
+    /// Check values that parse but are out of range, such as a zero timeout
+    /// written by hand.
+    pub fn validate(&self) -> Result<()> {
+        for key in KEYS {
+            if let Some(value) = self.get(key.name)? {
+                Settings::default().set(key.name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn enum_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Config => f.write_str("config.toml"),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// Every key with its effective value and source, then what it does.
+pub fn print_settings(settings: &Settings) {
+    for key in KEYS {
+        let (value, source) = settings
+            .effective(key.name)
+            .expect("every listed key is known");
+        let value = if value.is_empty() { "(unset)" } else { &value };
+        println!("{} = {value}  ({source})", key.name);
+        println!("    {}", key.about);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_validates_and_normalizes_values() {
+        let mut settings = Settings::default();
+
+        settings.set("timeout", "5").unwrap();
+        settings.set("output", "JSON").unwrap();
+        settings.set("confirm", "yes").unwrap();
+
+        assert_eq!(settings.get("timeout").unwrap().as_deref(), Some("5"));
+        assert_eq!(settings.get("output").unwrap().as_deref(), Some("json"));
+        assert_eq!(settings.confirm, Some(ConfirmPolicy::Yes));
+        assert!(settings
+            .set("timeout", "0")
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid timeout"));
+        assert!(settings
+            .set("color", "sometimes")
+            .unwrap_err()
+            .to_string()
+            .contains("expected one of auto, always, never"));
+        assert!(settings
+            .set("colour", "never")
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown setting: colour"));
+    }
+
+    #[test]
+    fn cli_beats_config_beats_default() {
+        let settings = Settings {
+            timeout: Some(30),
+            output: Some(OutputFormat::Json),
+            ..Settings::default()
+        };
+
+        assert_eq!(settings.scan_timeout(Some(4)), Duration::from_secs(4));
+        assert_eq!(
+            settings.output(Some(OutputFormat::Text)),
+            OutputFormat::Text
+        );
+        assert_eq!(Settings::default().confirm(), ConfirmPolicy::Ask);
+    }
+
+    #[test]
+    fn unset_reports_whether_a_value_was_stored() {
+        let mut settings = Settings {
+            adapter: Some("hci1".to_string()),
+            ..Settings::default()
+        };
+
+        assert!(settings.unset("adapter").unwrap());
+        assert!(!settings.unset("adapter").unwrap());
+        assert!(settings.is_empty());
+    }
+
+    #[test]
+    fn reads_settings_table() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            [settings]
+            timeout = 15
+            color = "never"
+            history_file = "/tmp/iqos_history"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.settings.timeout, Some(15));
+        assert_eq!(config.settings.color, Some(ColorMode::Never));
+        assert_eq!(
+            config.settings.effective("timeout").unwrap().1,
+            Source::Config
+        );
+        assert!(toml::from_str::<AppConfig>("[settings]\ntimout = 15\n").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::lock_file;
+
+/// Read a JSON state file such as `budget.json`; a missing file reads as
+/// empty.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(error) => Err(error).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Load the file at `path` afresh under its advisory lock, apply `change`
+/// and save the result before unlocking, as [`AppConfig::update`] does
+/// for config.toml. Nothing is saved when `change` fails.
+///
+/// [`AppConfig::update`]: crate::config::AppConfig::update
+pub fn update_json<T, R>(path: &Path, change: impl FnOnce(&mut T) -> Result<R>) -> Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let _lock = lock_file(path)?;
+    let mut value = load_json(path)?;
+    let result = change(&mut value)?;
+    write_json(path, &value)?;
+    Ok(result)
+}
+
+/// Write through a temporary file so readers never see half a file. Like
+/// config.toml, the file is readable by its owner only: firmware history
+/// holds serial numbers.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let contents = serde_json::to_string_pretty(value)?;
+    let tmp_path = path.with_extension("json.tmp");
+
+    let result = (|| -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        {
+            options.mode(0o600);
+        }
+
+        let mut file = options
+            .open(&tmp_path)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to set permissions on {}", tmp_path.display()))?;
+
+        file.write_all(contents.as_bytes())
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        drop(file);
+
+        fs::rename(&tmp_path, path).with_context(|| format!("failed to replace {}", path.display()))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::unique_temp_dir;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn updates_keep_other_entries() {
+        let dir = unique_temp_dir("state");
+        let path = dir.join("state.json");
+
+        assert_eq!(
+            load_json::<BTreeMap<String, u32>>(&path).unwrap(),
+            BTreeMap::new()
+        );
+        for (key, value) in [("a", 1), ("b", 2)] {
+            update_json(&path, |state: &mut BTreeMap<String, u32>| {
+                state.insert(key.to_string(), value);
+                Ok(())
+            })
+            .unwrap();
+        }
+        let failed: Result<()> = update_json(&path, |state: &mut BTreeMap<String, u32>| {
+            state.clear();
+            anyhow::bail!("no")
+        });
+        assert!(failed.is_err());
+
+        assert_eq!(
+            load_json::<BTreeMap<String, u32>>(&path).unwrap(),
+            BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 2)])
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::config::TAG_SELECTOR_PREFIX;
+
+/// Names the device to use: a saved label, a model selector or an address.
+pub const DEVICE_ENV: &str = "IQOS_DEVICE";
+/// Looked up from the current directory towards the root.
+pub const PROJECT_FILE: &str = ".iqos.toml";
+
+/// Prefixes that say what a target names, so `label:iluma` reaches a label
+/// that a bare `iluma` would take for a model selector.
+pub const MODEL_PREFIX: &str = "model:";
+pub const LABEL_PREFIX: &str = "label:";
+pub const ADDRESS_PREFIX: &str = "addr:";
+
+/// How long a scan keeps listening after the first device matching a model
+/// selector, to hear any others.
+pub const PICK_WINDOW: Duration = Duration::from_secs(2);
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static PICK: AtomicU8 = AtomicU8::new(PickPolicy::Strongest as u8);
+
+/// Set by `--verbose` to report how the target device was chosen.
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Which device to use when several match a model selector.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PickPolicy {
+    /// The one with the strongest signal.
+    #[default]
+    Strongest,
+    /// The first one heard, without waiting for others.
+    First,
+    /// Ask which one; fail with the candidates when not on a terminal.
+    Prompt,
+}
+
+/// Set by `--pick`.
+pub fn set_pick_policy(policy: PickPolicy) {
+    PICK.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn pick_policy() -> PickPolicy {
+    match PICK.load(Ordering::Relaxed) {
+        value if value == PickPolicy::First as u8 => PickPolicy::First,
+        value if value == PickPolicy::Prompt as u8 => PickPolicy::Prompt,
+        _ => PickPolicy::Strongest,
+    }
+}
+
+/// The index of the strongest signal, the earliest one on a tie. A device
+/// that reported no signal strength ranks last.
+pub fn strongest(signals: &[Option<i16>]) -> usize {
+    signals
+        .iter()
+        .enumerate()
+        .max_by(|(a_index, a), (b_index, b)| a.cmp(b).then(b_index.cmp(a_index)))
+        .map_or(0, |(index, _)| index)
+}
+
+pub fn describe_signal(rssi: Option<i16>) -> String {
+    rssi.map_or_else(
+        || "signal unknown".to_string(),
+        |rssi| format!("{rssi} dBm"),
+    )
+}
+
+/// A `.iqos.toml` pinning the device for a directory tree. Exactly one key
+/// is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectFile {
+    /// A saved label.
+    pub label: Option<String>,
+    /// A device address, saved or not.
+    pub address: Option<String>,
+    /// A model selector such as `iluma-i`.
+    pub model: Option<String>,
+    /// The `name` of a `[[model_overrides]]` entry in config.toml.
+    pub profile: Option<String>,
+}
+
+/// What a pin names. `Any` is an unprefixed value and is tried as a model
+/// selector, then a saved label, then an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pin {
+    Label(String),
+    Address(String),
+    Model(String),
+    /// A named `[[model_overrides]]` entry; only `.iqos.toml` sets this.
+    Profile(String),
+    Any(String),
+}
+
+/// Where the target device came from, in order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetSource {
+    Flag,
+    Env,
+    ProjectFile(PathBuf),
+    Default,
+    /// One side of `iqos compare`.
+    Compare,
+    /// A device the scheduler connects to for its rules.
+    Schedule,
+    /// The device chosen in the interactive picker, `iqos` with no arguments.
+    Picker,
+}
+
+impl TargetSource {
+    /// Targets pinned by the environment or a project file, and devices
+    /// that compare or the scheduler connect to, are used without becoming
+    /// the stored default device.
+    pub fn remembers_default(&self) -> bool {
+        matches!(self, Self::Flag | Self::Default | Self::Picker)
+    }
+}
+
+impl fmt::Display for TargetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Flag => f.write_str("command line"),
+            Self::Env => f.write_str(DEVICE_ENV),
+            Self::ProjectFile(path) => write!(f, "{}", path.display()),
+            Self::Default => f.write_str("default device in config.toml"),
+            Self::Compare => f.write_str("compare"),
+            Self::Schedule => f.write_str("scheduler"),
+            Self::Picker => f.write_str("device picker"),
+        }
+    }
+}
+
+/// The device pinned by `IQOS_DEVICE` or, failing that, the nearest
+/// `.iqos.toml` at or above `dir`.
+pub fn pinned_target(env: Option<String>, dir: &Path) -> Result<Option<(Pin, TargetSource)>> {
+    if let Some(value) = env.map(|value| value.trim().to_string()) {
+        if !value.is_empty() {
+            return Ok(Some((parse_pin(&value), TargetSource::Env)));
+        }
+    }
+
+    let Some(path) = find_project_file(dir) else {
+        return Ok(None);
+    };
+    let pin = load_project_file(&path)?;
+    Ok(Some((pin, TargetSource::ProjectFile(path))))
+}
+
+pub fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+pub fn load_project_file(path: &Path) -> Result<Pin> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let file: ProjectFile =
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    let text = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    match (
+        text(file.label),
+        text(file.address),
+        text(file.model),
+        text(file.profile),
+    ) {
+        (Some(label), None, None, None) => Ok(Pin::Label(label)),
+        (None, Some(address), None, None) => Ok(Pin::Address(address)),
+        (None, None, Some(model), None) => Ok(Pin::Model(model)),
+        (None, None, None, Some(profile)) => Ok(Pin::Profile(profile)),
+        _ => bail!(
+            "{} must set exactly one of label, address, model or profile",
+            path.display()
+        ),
+    }
+}
+
+/// The rest of `value` after a selector prefix such as `label:`, which
+/// matches ignoring case.
+pub fn strip_selector_prefix<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    let value = value.trim();
+    value
+        .get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| value[prefix.len()..].trim())
+}
+
+/// Read a target as given to `--model` or `IQOS_DEVICE`.
+pub fn parse_pin(value: &str) -> Pin {
+    let value = value.trim();
+    let prefixed = |prefix: &str| strip_selector_prefix(value, prefix).map(str::to_string);
+    if let Some(model) = prefixed(MODEL_PREFIX) {
+        Pin::Model(model)
+    } else if let Some(label) = prefixed(LABEL_PREFIX) {
+        Pin::Label(label)
+    } else if let Some(address) = prefixed(ADDRESS_PREFIX) {
+        Pin::Address(address)
+    } else {
+        Pin::Any(value.to_string())
+    }
+}
+
+/// Whether `value` starts with a selector prefix, which labels may not.
+pub fn has_selector_prefix(value: &str) -> bool {
+    !matches!(parse_pin(value), Pin::Any(_))
+        || strip_selector_prefix(value, TAG_SELECTOR_PREFIX).is_some()
+}
+
+/// A Bluetooth MAC address (`AA:BB:CC:DD:EE:FF`) or, as macOS reports
+/// peripherals, a UUID.
+pub fn looks_like_address(value: &str) -> bool {
+    let hex_groups = |separator: char, lengths: &[usize]| {
+        let groups: Vec<&str> = value.split(separator).collect();
+        groups.len() == lengths.len()
+            && groups.iter().zip(lengths).all(|(group, len)| {
+                group.len() == *len && group.chars().all(|c| c.is_ascii_hexdigit())
+            })
+    };
+    hex_groups(':', &[2; 6]) || hex_groups('-', &[2; 6]) || hex_groups('-', &[8, 4, 4, 4, 12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::unique_temp_dir;
+
+    #[test]
+    fn env_wins_over_project_file() {
+        let root = unique_temp_dir("env");
+        fs::write(root.join(PROJECT_FILE), "label = \"rig\"\n").unwrap();
+
+        assert_eq!(
+            pinned_target(Some("minera".to_string()), &root).unwrap(),
+            Some((Pin::Any("minera".to_string()), TargetSource::Env))
+        );
+        assert_eq!(
+            pinned_target(Some(" ".to_string()), &root).unwrap(),
+            Some((
+                Pin::Label("rig".to_string()),
+                TargetSource::ProjectFile(root.join(PROJECT_FILE))
+            ))
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn finds_project_file_in_a_parent_directory() {
+        let root = unique_temp_dir("walk");
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(PROJECT_FILE), "address = \"AA:BB:CC:DD:EE:FF\"\n").unwrap();
+
+        assert_eq!(
+            pinned_target(None, &nested).unwrap(),
+            Some((
+                Pin::Address("AA:BB:CC:DD:EE:FF".to_string()),
+                TargetSource::ProjectFile(root.join(PROJECT_FILE))
+            ))
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn project_file_sets_exactly_one_key() {
+        let root = unique_temp_dir("keys");
+        let path = root.join(PROJECT_FILE);
+
+        fs::write(&path, "label = \"a\"\nmodel = \"iluma\"\n").unwrap();
+        assert!(load_project_file(&path).is_err());
+        fs::write(&path, "").unwrap();
+        assert!(load_project_file(&path).is_err());
+        fs::write(&path, "device = \"a\"\n").unwrap();
+        assert!(load_project_file(&path).is_err());
+        fs::write(&path, "profile = \"rig-a\"\n").unwrap();
+        assert_eq!(
+            load_project_file(&path).unwrap(),
+            Pin::Profile("rig-a".to_string())
+        );
+        fs::write(&path, "model = \"iluma-i\"\n").unwrap();
+        assert_eq!(
+            load_project_file(&path).unwrap(),
+            Pin::Model("iluma-i".to_string())
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn only_flags_the_picker_and_the_default_are_remembered() {
+        assert!(TargetSource::Flag.remembers_default());
+        assert!(TargetSource::Default.remembers_default());
+        assert!(TargetSource::Picker.remembers_default());
+        for source in [
+            TargetSource::Env,
+            TargetSource::ProjectFile(PathBuf::from(PROJECT_FILE)),
+            TargetSource::Compare,
+            TargetSource::Schedule,
+        ] {
+            assert!(!source.remembers_default(), "{source}");
+        }
+    }
+
+    #[test]
+    fn parses_prefixed_targets() {
+        assert_eq!(
+            parse_pin("model:iluma-i"),
+            Pin::Model("iluma-i".to_string())
+        );
+        assert_eq!(parse_pin("label:iluma"), Pin::Label("iluma".to_string()));
+        assert_eq!(
+            parse_pin(" ADDR:AA:BB:CC:DD:EE:FF "),
+            Pin::Address("AA:BB:CC:DD:EE:FF".to_string())
+        );
+        assert_eq!(parse_pin("minera"), Pin::Any("minera".to_string()));
+
+        assert!(has_selector_prefix("label:x"));
+        assert!(has_selector_prefix("tag:x"));
+        assert!(!has_selector_prefix("lab:x"));
+    }
+
+    #[test]
+    fn picks_the_strongest_signal() {
+        assert_eq!(strongest(&[Some(-70), Some(-48), Some(-60)]), 1);
+        assert_eq!(strongest(&[None, Some(-90)]), 1);
+        assert_eq!(strongest(&[Some(-50), Some(-50)]), 0);
+        assert_eq!(strongest(&[None, None]), 0);
+        assert_eq!(describe_signal(Some(-48)), "-48 dBm");
+    }
+
+    #[test]
+    fn recognises_addresses() {
+        assert!(looks_like_address("AA:BB:CC:DD:EE:FF"));
+        assert!(looks_like_address("aa-bb-cc-dd-ee-0f"));
+        assert!(looks_like_address("12345678-9ABC-DEF0-1234-56789ABCDEF0"));
+        assert!(!looks_like_address("minera"));
+        assert!(!looks_like_address("AA:BB:CC:DD:EE"));
+    }
+}
@@ -0,0 +1,712 @@
+use std::io::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use iqos::{
+    BrightnessLevel, DeviceCapability, DeviceStatus, DiagnosticData, FlexBatteryMode,
+    FlexBatterySettings, VibrationSettings,
+};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::loader::cmds::vibration::{apply_changes, VibrationFlag};
+use crate::loader::parser::offer_rollback;
+use crate::loader::session::Session;
+use crate::loader::setting::Setting;
+use crate::overrides::DeviceProfile;
+
+/// How often battery and diagnosis counters are re-read.
+const LIVE_REFRESH: Duration = Duration::from_secs(5);
+/// How long to wait for a key before redrawing.
+const INPUT_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Toggle {
+    Brightness,
+    Vibration(VibrationFlag),
+    FlexPuff,
+    FlexBatteryMode,
+    FlexBatteryPause,
+    SmartGesture,
+    AutoStart,
+    Lock,
+}
+
+/// Keyboard shortcut, toggle and label of each row in the settings panel.
+const TOGGLES: &[(char, Toggle, &str)] = &[
+    ('b', Toggle::Brightness, "Brightness"),
+    (
+        '1',
+        Toggle::Vibration(VibrationFlag::Heating),
+        "Vibrate on heating",
+    ),
+    (
+        '2',
+        Toggle::Vibration(VibrationFlag::Starting),
+        "Vibrate on starting",
+    ),
+    (
+        '3',
+        Toggle::Vibration(VibrationFlag::Puffend),
+        "Vibrate on puff end",
+    ),
+    (
+        '4',
+        Toggle::Vibration(VibrationFlag::Terminated),
+        "Vibrate on terminated",
+    ),
+    (
+        '5',
+        Toggle::Vibration(VibrationFlag::Charge),
+        "Vibrate on charge start",
+    ),
+    ('f', Toggle::FlexPuff, "FlexPuff"),
+    ('m', Toggle::FlexBatteryMode, "FlexBattery mode"),
+    ('p', Toggle::FlexBatteryPause, "FlexBattery pause"),
+    ('g', Toggle::SmartGesture, "Smart Gesture"),
+    ('a', Toggle::AutoStart, "Auto-start"),
+    ('l', Toggle::Lock, "Lock"),
+];
+
+impl Toggle {
+    fn capability(self) -> DeviceCapability {
+        match self {
+            Self::Brightness => DeviceCapability::Brightness,
+            Self::Vibration(VibrationFlag::Charge) => DeviceCapability::ChargeStartVibration,
+            Self::Vibration(_) => DeviceCapability::Vibration,
+            Self::FlexPuff => DeviceCapability::FlexPuff,
+            Self::FlexBatteryMode | Self::FlexBatteryPause => DeviceCapability::FlexBattery,
+            Self::SmartGesture => DeviceCapability::SmartGesture,
+            Self::AutoStart => DeviceCapability::AutoStart,
+            Self::Lock => DeviceCapability::DeviceLock,
+        }
+    }
+}
+
+/// Setting values as last read from or written to the device. Smart
+/// Gesture and the lock state cannot be read, so they are only known once
+/// changed from the dashboard.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Settings {
+    brightness: Option<BrightnessLevel>,
+    vibration: Option<VibrationSettings>,
+    flexpuff: Option<bool>,
+    flexbattery: Option<FlexBatterySettings>,
+    autostart: Option<bool>,
+    smartgesture: Option<bool>,
+    locked: Option<bool>,
+}
+
+impl Settings {
+    fn value(&self, toggle: Toggle) -> Option<String> {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" }.to_string();
+        match toggle {
+            Toggle::Brightness => self.brightness.map(|level| level.to_string()),
+            Toggle::Vibration(flag) => self
+                .vibration
+                .and_then(|settings| vibration_flag(settings, flag))
+                .map(on_off),
+            Toggle::FlexPuff => self.flexpuff.map(on_off),
+            Toggle::FlexBatteryMode => self
+                .flexbattery
+                .map(|settings| format!("{:?}", settings.mode())),
+            Toggle::FlexBatteryPause => self
+                .flexbattery
+                .and_then(|settings| settings.pause_mode())
+                .map(on_off),
+            Toggle::SmartGesture => self.smartgesture.map(on_off),
+            Toggle::AutoStart => self.autostart.map(on_off),
+            Toggle::Lock => self
+                .locked
+                .map(|locked| if locked { "locked" } else { "unlocked" }.to_string()),
+        }
+    }
+
+    /// The write that flips `toggle`, or `None` while its current value is
+    /// unknown. Settings the device cannot report are switched on first.
+    fn next(&self, toggle: Toggle, profile: DeviceProfile) -> Option<(&'static str, Setting)> {
+        Some(match toggle {
+            Toggle::Brightness => (
+                "brightness",
+                Setting::Brightness(match self.brightness? {
+                    BrightnessLevel::High => BrightnessLevel::Low,
+                    _ => BrightnessLevel::High,
+                }),
+            ),
+            Toggle::Vibration(flag) => {
+                let current = self.vibration?;
+                let enabled = vibration_flag(current, flag)?;
+                (
+                    "vibration",
+                    Setting::Vibration(apply_changes(
+                        current,
+                        &[(flag, !enabled)],
+                        profile.supports_charge_start_vibration(),
+                    )),
+                )
+            }
+            Toggle::FlexPuff => ("flexpuff", Setting::FlexPuff(!self.flexpuff?)),
+            Toggle::FlexBatteryMode => (
+                "flexbattery",
+                Setting::FlexBattery(FlexBatterySettings::new(
+                    match self.flexbattery?.mode() {
+                        FlexBatteryMode::Eco => FlexBatteryMode::Performance,
+                        _ => FlexBatteryMode::Eco,
+                    },
+                    None,
+                )),
+            ),
+            Toggle::FlexBatteryPause => {
+                let current = self.flexbattery?;
+                (
+                    "flexbattery",
+                    Setting::FlexBattery(FlexBatterySettings::new(
+                        current.mode(),
+                        Some(!current.pause_mode().unwrap_or(false)),
+                    )),
+                )
+            }
+            Toggle::SmartGesture => (
+                "smartgesture",
+                Setting::SmartGesture(!self.smartgesture.unwrap_or(false)),
+            ),
+            Toggle::AutoStart => ("autostart", Setting::AutoStart(!self.autostart?)),
+            Toggle::Lock => {
+                let lock = !self.locked.unwrap_or(false);
+                (if lock { "lock" } else { "unlock" }, Setting::Locked(lock))
+            }
+        })
+    }
+
+    /// The value currently shown for the setting `new` replaces.
+    fn previous(&self, new: Setting) -> Option<Setting> {
+        match new {
+            Setting::Brightness(_) => self.brightness.map(Setting::Brightness),
+            Setting::Vibration(_) => self.vibration.map(Setting::Vibration),
+            Setting::FlexPuff(_) => self.flexpuff.map(Setting::FlexPuff),
+            Setting::FlexBattery(_) => self.flexbattery.map(Setting::FlexBattery),
+            Setting::AutoStart(_) => self.autostart.map(Setting::AutoStart),
+            Setting::SmartGesture(_) => self.smartgesture.map(Setting::SmartGesture),
+            Setting::Locked(_) => self.locked.map(Setting::Locked),
+        }
+    }
+
+    fn record(&mut self, setting: Setting) {
+        match setting {
+            Setting::Brightness(level) => self.brightness = Some(level),
+            Setting::Vibration(settings) => self.vibration = Some(settings),
+            Setting::FlexPuff(enabled) => self.flexpuff = Some(enabled),
+            Setting::FlexBattery(settings) => {
+                // A mode-only write leaves pause as it was.
+                let pause = settings
+                    .pause_mode()
+                    .or_else(|| self.flexbattery.and_then(|current| current.pause_mode()));
+                self.flexbattery = Some(FlexBatterySettings::new(settings.mode(), pause));
+            }
+            Setting::AutoStart(enabled) => self.autostart = Some(enabled),
+            Setting::SmartGesture(enabled) => self.smartgesture = Some(enabled),
+            Setting::Locked(locked) => self.locked = Some(locked),
+        }
+    }
+}
+
+fn vibration_flag(settings: VibrationSettings, flag: VibrationFlag) -> Option<bool> {
+    match flag {
+        VibrationFlag::Heating => Some(settings.when_heating_start()),
+        VibrationFlag::Starting => Some(settings.when_starting_to_use()),
+        VibrationFlag::Puffend => Some(settings.when_puff_end()),
+        VibrationFlag::Terminated => Some(settings.when_manually_terminated()),
+        VibrationFlag::Charge => settings.when_charging_start(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Connection {
+    Connected,
+    Lost(String),
+}
+
+struct Dashboard {
+    profile: DeviceProfile,
+    label: Option<String>,
+    address: Option<String>,
+    status: Option<DeviceStatus>,
+    battery: Option<u8>,
+    diagnosis: Option<DiagnosticData>,
+    settings: Settings,
+    connection: Connection,
+    refreshed_at: Option<DateTime<Local>>,
+    message: Option<String>,
+}
+
+/// Run the dashboard until `q`, Esc or Ctrl-C.
+pub async fn run(session: Arc<Session>) -> Result<()> {
+    let mut dashboard = Dashboard {
+        profile: session.profile(),
+        label: session.label().map(str::to_string),
+        address: session.device().map(|device| device.address.clone()),
+        status: None,
+        battery: None,
+        diagnosis: None,
+        settings: Settings::default(),
+        connection: Connection::Connected,
+        refreshed_at: None,
+        message: None,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &session, &mut dashboard).await;
+    ratatui::restore();
+    offer_rollback(&session, |prompt| {
+        print!("{prompt}");
+        let _ = std::io::stdout().flush();
+        let mut answer = String::new();
+        let _ = std::io::stdin().read_line(&mut answer);
+        answer
+    })
+    .await;
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    session: &Session,
+    dashboard: &mut Dashboard,
+) -> Result<()> {
+    terminal.draw(|frame| render(frame, dashboard))?;
+    refresh_all(session, dashboard).await;
+    let mut last_refresh = Instant::now();
+
+    loop {
+        terminal.draw(|frame| render(frame, dashboard))?;
+
+        let key = tokio::task::block_in_place(|| -> Result<Option<_>> {
+            if !event::poll(INPUT_POLL)? {
+                return Ok(None);
+            }
+            Ok(match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => Some(key),
+                _ => None,
+            })
+        })?;
+
+        if let Some(key) = key {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Char('r') => {
+                    refresh_all(session, dashboard).await;
+                    last_refresh = Instant::now();
+                }
+                KeyCode::Char('u') => undo(session, dashboard).await,
+                KeyCode::Char(pressed) => {
+                    if let Some((_, toggle, label)) =
+                        TOGGLES.iter().find(|(key, _, _)| *key == pressed)
+                    {
+                        apply(session, dashboard, *toggle, label).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if last_refresh.elapsed() >= LIVE_REFRESH {
+            refresh_live(session, dashboard).await;
+            last_refresh = Instant::now();
+        }
+    }
+}
+
+/// Read everything shown on the dashboard.
+async fn refresh_all(session: &Session, dashboard: &mut Dashboard) {
+    let result = async {
+        let iqos = session.iqos().await;
+        let profile = dashboard.profile;
+        let model = profile.model;
+        let device_info = iqos.transport().device_info().clone();
+        dashboard.status = Some(iqos.read_device_status(model, device_info).await?);
+
+        let settings = &mut dashboard.settings;
+        if profile.supports(DeviceCapability::Brightness) {
+            settings.brightness = Some(iqos.read_brightness().await?);
+        }
+        if profile.supports(DeviceCapability::Vibration) {
+            settings.vibration = Some(iqos.read_vibration_settings(model).await?);
+        }
+        if profile.supports(DeviceCapability::FlexPuff) {
+            settings.flexpuff = Some(iqos.read_flexpuff(model).await?.is_enabled());
+        }
+        if profile.supports(DeviceCapability::FlexBattery) {
+            settings.flexbattery = Some(iqos.read_flexbattery(model).await?);
+        }
+        if profile.supports(DeviceCapability::AutoStart) {
+            settings.autostart = Some(iqos.read_autostart(model).await?);
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => refresh_live(session, dashboard).await,
+        Err(error) => dashboard.connection = Connection::Lost(format!("{error:#}")),
+    }
+}
+
+/// Re-read the values that change while the device is in use.
+async fn refresh_live(session: &Session, dashboard: &mut Dashboard) {
+    let result = async {
+        let iqos = session.iqos().await;
+        dashboard.battery = Some(iqos.transport().read_battery_level().await?);
+        dashboard.diagnosis = Some(iqos.read_diagnosis().await?);
+        anyhow::Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            dashboard.connection = Connection::Connected;
+            dashboard.refreshed_at = Some(Local::now());
+        }
+        Err(error) => dashboard.connection = Connection::Lost(format!("{error:#}")),
+    }
+}
+
+async fn apply(session: &Session, dashboard: &mut Dashboard, toggle: Toggle, label: &str) {
+    if !dashboard.profile.supports(toggle.capability()) {
+        dashboard.message = Some(format!(
+            "{label} is not supported on {:?}",
+            dashboard.profile.model
+        ));
+        return;
+    }
+    let Some((command, new)) = dashboard.settings.next(toggle, dashboard.profile) else {
+        dashboard.message = Some(format!("{label} is unknown; press r to refresh"));
+        return;
+    };
+    let previous = dashboard.settings.previous(new);
+
+    // The session prints dry-run reports, which would draw over the screen.
+    if session.options().dry_run {
+        let current = previous.map_or_else(|| "unknown".to_string(), |setting| setting.to_string());
+        dashboard.message = Some(format!(
+            "Dry run: {command} not written ({current} -> {new})"
+        ));
+        return;
+    }
+
+    let iqos = session.iqos().await;
+    dashboard.message = Some(match session.write(&iqos, command, previous, new).await {
+        Ok(written) => {
+            dashboard.settings.record(new);
+            written.report(format_args!(
+                "{label}: {}",
+                dashboard.settings.value(toggle).unwrap_or_default()
+            ))
+        }
+        Err(error) => format!("Error: {error:#}"),
+    });
+}
+
+/// Restore the setting changed most recently in this session.
+async fn undo(session: &Session, dashboard: &mut Dashboard) {
+    let iqos = session.iqos().await;
+    dashboard.message = Some(match session.undo_last(&iqos).await {
+        Ok(Some(change)) => {
+            if let Some(previous) = change.previous {
+                dashboard.settings.record(previous);
+            }
+            format!("Undid {change}")
+        }
+        Ok(None) => "Nothing to undo".to_string(),
+        Err(error) => format!("Error: {error:#}"),
+    });
+}
+
+fn render(frame: &mut Frame, dashboard: &Dashboard) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+    let [identity, battery, usage] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(3),
+        Constraint::Length(5),
+    ])
+    .areas(left);
+
+    let title = match &dashboard.label {
+        Some(label) => format!("IQOS {:?} — {label}", dashboard.profile.model),
+        None => format!("IQOS {:?}", dashboard.profile.model),
+    };
+    frame.render_widget(
+        Paragraph::new(title).style(Style::default().add_modifier(Modifier::BOLD)),
+        header,
+    );
+
+    frame.render_widget(
+        Paragraph::new(identity_lines(dashboard))
+            .block(Block::default().borders(Borders::ALL).title("Device")),
+        identity,
+    );
+
+    let level = dashboard.battery.unwrap_or(0).min(100);
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title("Battery"))
+            .gauge_style(Style::default().fg(if level <= 20 {
+                Color::Red
+            } else {
+                Color::Green
+            }))
+            .percent(u16::from(level))
+            .label(match dashboard.battery {
+                Some(level) => format!("{level}%"),
+                None => "unknown".to_string(),
+            }),
+        battery,
+    );
+
+    let diagnosis = dashboard.diagnosis.clone().unwrap_or_default();
+    let voltage = diagnosis.battery_voltage.or_else(|| {
+        dashboard
+            .status
+            .as_ref()
+            .and_then(|status| status.battery_voltage)
+    });
+    frame.render_widget(
+        Paragraph::new(vec![
+            field_line("Voltage", voltage.map(|volts| format!("{volts:.2}V"))),
+            field_line(
+                "Total puffs",
+                diagnosis.total_smoking_count.map(|count| count.to_string()),
+            ),
+            field_line(
+                "Days used",
+                diagnosis.days_used.map(|days| days.to_string()),
+            ),
+        ])
+        .block(Block::default().borders(Borders::ALL).title("Usage")),
+        usage,
+    );
+
+    frame.render_widget(
+        Paragraph::new(settings_lines(dashboard))
+            .block(Block::default().borders(Borders::ALL).title("Settings")),
+        right,
+    );
+
+    frame.render_widget(Paragraph::new(status_lines(dashboard)), footer);
+}
+
+fn field_line(name: &str, value: Option<String>) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(format!("{name:<16}"), Style::default().fg(Color::DarkGray)),
+        Span::raw(value.unwrap_or_else(|| "N/A".to_string())),
+    ])
+}
+
+fn identity_lines(dashboard: &Dashboard) -> Vec<Line<'static>> {
+    let Some(status) = &dashboard.status else {
+        return vec![field_line("Address", dashboard.address.clone())];
+    };
+    let info = &status.device_info;
+
+    vec![
+        field_line("Address", dashboard.address.clone()),
+        field_line("Serial number", info.serial_number.clone()),
+        field_line("Model number", info.model_number.clone()),
+        field_line("Manufacturer", info.manufacturer_name.clone()),
+        field_line("Software rev", info.software_revision.clone()),
+        field_line("Product number", Some(status.product_number.clone())),
+        field_line("Stick firmware", Some(status.stick_firmware.to_string())),
+        field_line("Holder product", status.holder_product_number.clone()),
+        field_line(
+            "Holder firmware",
+            status
+                .holder_firmware
+                .as_ref()
+                .map(|firmware| firmware.to_string()),
+        ),
+    ]
+}
+
+fn settings_lines(dashboard: &Dashboard) -> Vec<Line<'static>> {
+    TOGGLES
+        .iter()
+        .map(|(key, toggle, label)| {
+            let supported = dashboard.profile.supports(toggle.capability());
+            let value = if supported {
+                dashboard
+                    .settings
+                    .value(*toggle)
+                    .unwrap_or_else(|| "unknown".to_string())
+            } else {
+                "not supported".to_string()
+            };
+            let style = if supported {
+                Style::default()
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            Line::from(vec![
+                Span::styled(format!("[{key}] "), style.fg(Color::Cyan)),
+                Span::styled(format!("{label:<24}"), style),
+                Span::styled(value, style.add_modifier(Modifier::BOLD)),
+            ])
+            .style(style)
+        })
+        .collect()
+}
+
+fn status_lines(dashboard: &Dashboard) -> Vec<Line<'static>> {
+    let connection = match &dashboard.connection {
+        Connection::Connected => Span::styled("● connected", Style::default().fg(Color::Green)),
+        Connection::Lost(error) => Span::styled(
+            format!("● connection lost: {error}"),
+            Style::default().fg(Color::Red),
+        ),
+    };
+    let refreshed = dashboard
+        .refreshed_at
+        .map(|time| format!("  updated {}", time.format("%H:%M:%S")))
+        .unwrap_or_default();
+    let message = dashboard
+        .message
+        .as_ref()
+        .map(|message| format!("  {message}"))
+        .unwrap_or_default();
+
+    vec![
+        Line::from(vec![connection, Span::raw(refreshed), Span::raw(message)]),
+        Line::from(Span::styled(
+            "q quit  r refresh  u undo  press a key in [ ] to toggle that setting",
+            Style::default().fg(Color::DarkGray),
+        )),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iqos::DeviceModel;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn dashboard(model: DeviceModel) -> Dashboard {
+        Dashboard {
+            profile: DeviceProfile::detected(model),
+            label: Some("minera".to_string()),
+            address: Some("AA:BB:CC:DD:EE:FF".to_string()),
+            status: None,
+            battery: Some(64),
+            diagnosis: None,
+            settings: Settings {
+                brightness: Some(BrightnessLevel::High),
+                vibration: Some(VibrationSettings::new(true, false, true, false)),
+                ..Settings::default()
+            },
+            connection: Connection::Connected,
+            refreshed_at: None,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn toggles_flip_the_known_value() {
+        let settings = dashboard(DeviceModel::IlumaOne).settings;
+
+        assert_eq!(
+            settings.next(
+                Toggle::Brightness,
+                DeviceProfile::detected(DeviceModel::IlumaOne)
+            ),
+            Some(("brightness", Setting::Brightness(BrightnessLevel::Low)))
+        );
+        assert_eq!(
+            settings.next(
+                Toggle::Vibration(VibrationFlag::Starting),
+                DeviceProfile::detected(DeviceModel::IlumaOne)
+            ),
+            Some((
+                "vibration",
+                Setting::Vibration(VibrationSettings::new(true, true, true, false))
+            ))
+        );
+        // Unknown until read, so nothing to flip.
+        assert_eq!(
+            settings.next(
+                Toggle::FlexPuff,
+                DeviceProfile::detected(DeviceModel::IlumaOne)
+            ),
+            None
+        );
+        // Unreadable settings are switched on first.
+        assert_eq!(
+            settings.next(Toggle::Lock, DeviceProfile::detected(DeviceModel::IlumaOne)),
+            Some(("lock", Setting::Locked(true)))
+        );
+    }
+
+    #[test]
+    fn mode_write_keeps_the_shown_pause_state() {
+        let mut settings = Settings {
+            flexbattery: Some(FlexBatterySettings::new(
+                FlexBatteryMode::Performance,
+                Some(true),
+            )),
+            ..Settings::default()
+        };
+        let (_, new) = settings
+            .next(
+                Toggle::FlexBatteryMode,
+                DeviceProfile::detected(DeviceModel::IlumaI),
+            )
+            .unwrap();
+        settings.record(new);
+
+        assert_eq!(
+            settings.value(Toggle::FlexBatteryMode).as_deref(),
+            Some("Eco")
+        );
+        assert_eq!(
+            settings.value(Toggle::FlexBatteryPause).as_deref(),
+            Some("on")
+        );
+    }
+
+    #[test]
+    fn renders_settings_with_capability_gating() {
+        let mut terminal = Terminal::new(TestBackend::new(100, 24)).unwrap();
+        let dashboard = dashboard(DeviceModel::IlumaOne);
+
+        terminal.draw(|frame| render(frame, &dashboard)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("IQOS IlumaOne — minera"), "{screen}");
+        assert!(screen.contains("[b] Brightness"), "{screen}");
+        assert!(screen.contains("64%"), "{screen}");
+        assert!(screen.contains("● connected"), "{screen}");
+        assert!(
+            screen.contains("FlexBattery mode        not supported"),
+            "{screen}"
+        );
+    }
+}